@@ -3284,7 +3284,10 @@ fn test_spend_coinbase() {
             immature: 25_000,
             trusted_pending: 0,
             untrusted_pending: 0,
-            confirmed: 0
+            confirmed: 0,
+            immature_coinbase: [(confirmation_height, 25_000)].into(),
+            locked_immature_coinbase: Default::default(),
+            locked: 0,
         }
     );
 
@@ -3331,7 +3334,10 @@ fn test_spend_coinbase() {
             immature: 0,
             trusted_pending: 0,
             untrusted_pending: 0,
-            confirmed: 25_000
+            confirmed: 25_000,
+            immature_coinbase: Default::default(),
+            locked_immature_coinbase: Default::default(),
+            locked: 0,
         }
     );
     let mut builder = wallet.build_tx();
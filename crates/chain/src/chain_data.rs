@@ -168,6 +168,48 @@ impl Anchor for ConfirmationHeightAnchor {
     }
 }
 
+/// An [`Anchor`] implementation that also records the exact confirmation height and block hash of
+/// the transaction.
+///
+/// Unlike [`ConfirmationHeightAnchor`], the confirmation block can be checked against the chain.
+/// This allows the anchor to be invalidated when the confirmation block is reorged out even though
+/// the anchor block is still part of the chain.
+///
+/// Refer to [`Anchor`] for more details.
+#[derive(Debug, Clone, PartialEq, Eq, Copy, PartialOrd, Ord, core::hash::Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ConfirmationHashAnchor {
+    /// The anchor block.
+    pub anchor_block: BlockId,
+    /// The exact confirmation height of the transaction.
+    ///
+    /// It is assumed that this value is never larger than the height of the anchor block.
+    pub confirmation_height: u32,
+    /// The hash of the block that the transaction is confirmed in.
+    pub confirmation_hash: BlockHash,
+}
+
+impl Anchor for ConfirmationHashAnchor {
+    fn anchor_block(&self) -> BlockId {
+        self.anchor_block
+    }
+
+    fn confirmation_height_upper_bound(&self) -> u32 {
+        self.confirmation_height
+    }
+
+    fn confirmation_block(&self) -> Option<BlockId> {
+        Some(BlockId {
+            height: self.confirmation_height,
+            hash: self.confirmation_hash,
+        })
+    }
+}
+
 /// An [`Anchor`] implementation that also records the exact confirmation time and height of the
 /// transaction.
 ///
@@ -220,6 +262,17 @@ impl<A: Anchor> FullTxOut<A> {
     ///
     /// [`confirmation_height_upper_bound`]: Anchor::confirmation_height_upper_bound
     pub fn is_mature(&self, tip: u32) -> bool {
+        self.is_mature_with_coinbase_maturity(tip, COINBASE_MATURITY)
+    }
+
+    /// Whether the `txout` is considered mature, where coinbase outputs require
+    /// `coinbase_maturity` confirmations (instead of [`COINBASE_MATURITY`]).
+    ///
+    /// This is useful for chains with non-standard coinbase maturity. Refer to [`is_mature`] for
+    /// more.
+    ///
+    /// [`is_mature`]: Self::is_mature
+    pub fn is_mature_with_coinbase_maturity(&self, tip: u32, coinbase_maturity: u32) -> bool {
         if self.is_on_coinbase {
             let tx_height = match &self.chain_position {
                 ChainPosition::Confirmed(anchor) => anchor.confirmation_height_upper_bound(),
@@ -229,7 +282,7 @@ impl<A: Anchor> FullTxOut<A> {
                 }
             };
             let age = tip.saturating_sub(tx_height);
-            if age + 1 < COINBASE_MATURITY {
+            if age + 1 < coinbase_maturity {
                 return false;
             }
         }
@@ -247,7 +300,21 @@ impl<A: Anchor> FullTxOut<A> {
     ///
     /// [`confirmation_height_upper_bound`]: Anchor::confirmation_height_upper_bound
     pub fn is_confirmed_and_spendable(&self, tip: u32) -> bool {
-        if !self.is_mature(tip) {
+        self.is_confirmed_and_spendable_with_coinbase_maturity(tip, COINBASE_MATURITY)
+    }
+
+    /// Whether the utxo is/was/will be spendable with chain `tip`, where coinbase outputs require
+    /// `coinbase_maturity` confirmations (instead of [`COINBASE_MATURITY`]).
+    ///
+    /// Refer to [`is_confirmed_and_spendable`] for more.
+    ///
+    /// [`is_confirmed_and_spendable`]: Self::is_confirmed_and_spendable
+    pub fn is_confirmed_and_spendable_with_coinbase_maturity(
+        &self,
+        tip: u32,
+        coinbase_maturity: u32,
+    ) -> bool {
+        if !self.is_mature_with_coinbase_maturity(tip, coinbase_maturity) {
             return false;
         }
 
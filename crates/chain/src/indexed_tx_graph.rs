@@ -3,12 +3,14 @@
 //! This is essentially a [`TxGraph`] combined with an indexer.
 
 use alloc::vec::Vec;
-use bitcoin::{OutPoint, Transaction, TxOut};
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+use core::convert::Infallible;
 
 use crate::{
+    collections::{BTreeMap, BTreeSet, HashMap},
     keychain,
     tx_graph::{self, TxGraph},
-    Anchor, Append,
+    Anchor, Append, BlockId, ChainOracle,
 };
 
 /// A struct that combines [`TxGraph`] and an [`Indexer`] implementation.
@@ -47,9 +49,15 @@ impl<A, I> IndexedTxGraph<A, I> {
 
 impl<A: Anchor, I: Indexer> IndexedTxGraph<A, I> {
     /// Applies the [`ChangeSet`] to the [`IndexedTxGraph`].
+    ///
+    /// As in [`TxGraph::apply_changeset_with_removals`], removed transactions are removed (and
+    /// unindexed) before the additions are applied.
     pub fn apply_changeset(&mut self, changeset: ChangeSet<A, I::ChangeSet>) {
         self.index.apply_changeset(changeset.indexer);
 
+        for &txid in &changeset.removed {
+            self.index.unindex_tx(txid);
+        }
         for tx in &changeset.graph.txs {
             self.index.index_tx(tx);
         }
@@ -57,14 +65,65 @@ impl<A: Anchor, I: Indexer> IndexedTxGraph<A, I> {
             self.index.index_txout(outpoint, txout);
         }
 
-        self.graph.apply_changeset(changeset.graph);
+        self.graph
+            .apply_changeset_with_removals(tx_graph::ChangeSetWithRemovals {
+                graph: changeset.graph,
+                removed: changeset.removed,
+                removed_anchors: changeset.removed_anchors,
+            });
     }
 
     /// Determines the [`ChangeSet`] between `self` and an empty [`IndexedTxGraph`].
     pub fn initial_changeset(&self) -> ChangeSet<A, I::ChangeSet> {
         let graph = self.graph.initial_changeset();
         let indexer = self.index.initial_changeset();
-        ChangeSet { graph, indexer }
+        ChangeSet {
+            graph,
+            indexer,
+            removed: Default::default(),
+            removed_anchors: Default::default(),
+        }
+    }
+
+    /// List the txids of transactions which are in `chain` with `chain_tip`, but are unconfirmed.
+    ///
+    /// Transactions which are conflicted out of the best chain are not included. This is useful
+    /// to check whether unconfirmed transactions have since been confirmed (i.e. by passing the
+    /// txids to a chain source).
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. If the
+    /// [`ChainOracle`] is infallible, [`unconfirmed_txids`] can be used instead.
+    ///
+    /// [`unconfirmed_txids`]: Self::unconfirmed_txids
+    pub fn try_unconfirmed_txids<C: ChainOracle>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Result<Vec<Txid>, C::Error> {
+        let mut txids = Vec::new();
+        for canonical_tx in self.graph.try_list_chain_txs(chain, chain_tip) {
+            let canonical_tx = canonical_tx?;
+            if !canonical_tx.chain_position.is_confirmed() {
+                txids.push(canonical_tx.tx_node.txid);
+            }
+        }
+        Ok(txids)
+    }
+
+    /// List the txids of transactions which are in `chain` with `chain_tip`, but are unconfirmed.
+    ///
+    /// This is the infallible version of [`try_unconfirmed_txids`].
+    ///
+    /// [`try_unconfirmed_txids`]: Self::try_unconfirmed_txids
+    pub fn unconfirmed_txids<C: ChainOracle<Error = Infallible>>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Vec<Txid> {
+        self.try_unconfirmed_txids(chain, chain_tip)
+            .expect("oracle is infallible")
     }
 }
 
@@ -86,7 +145,131 @@ where
             indexer.append(self.index.index_txout(added_outpoint, added_txout));
         }
 
-        ChangeSet { graph, indexer }
+        ChangeSet {
+            graph,
+            indexer,
+            removed: Default::default(),
+            removed_anchors: Default::default(),
+        }
+    }
+
+    /// Re-run the indexer over every transaction and floating txout already in the graph.
+    ///
+    /// This is needed after the index starts tracking more script pubkeys (e.g. a keychain is added
+    /// to a [`KeychainTxOutIndex`]) so that outputs of the graph which were previously ignored are
+    /// indexed. The returned [`ChangeSet`] only contains the changes of the index.
+    ///
+    /// [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
+    pub fn reindex(&mut self) -> ChangeSet<A, I::ChangeSet> {
+        let mut indexer = I::ChangeSet::default();
+        for tx_node in self.graph.full_txs() {
+            indexer.append(self.index.index_tx(tx_node.tx));
+        }
+        for (outpoint, txout) in self.graph.floating_txouts() {
+            indexer.append(self.index.index_txout(outpoint, txout));
+        }
+        ChangeSet {
+            graph: tx_graph::ChangeSet::default(),
+            indexer,
+            removed: Default::default(),
+            removed_anchors: Default::default(),
+        }
+    }
+
+    /// Prune old transactions that are confirmed and fully spent, and remove their txouts from the
+    /// index.
+    ///
+    /// Refer to [`TxGraph::try_prune_spent_before`] for which transactions are pruned. The removed
+    /// transactions are recorded in the returned [`ChangeSet`] so that they can be removed from
+    /// persistence as well.
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. If the
+    /// [`ChainOracle`] is infallible, [`prune_spent_before`] can be used instead.
+    ///
+    /// [`prune_spent_before`]: Self::prune_spent_before
+    pub fn try_prune_spent_before<C: ChainOracle>(
+        &mut self,
+        height: u32,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Result<ChangeSet<A, I::ChangeSet>, C::Error> {
+        let changeset = self
+            .graph
+            .try_prune_spent_before(height, chain, chain_tip)?;
+        for &txid in &changeset.removed {
+            self.index.unindex_tx(txid);
+        }
+        Ok(changeset.into())
+    }
+
+    /// Prune old transactions that are confirmed and fully spent, and remove their txouts from the
+    /// index.
+    ///
+    /// This is the infallible version of [`try_prune_spent_before`].
+    ///
+    /// [`try_prune_spent_before`]: Self::try_prune_spent_before
+    pub fn prune_spent_before<C: ChainOracle<Error = Infallible>>(
+        &mut self,
+        height: u32,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> ChangeSet<A, I::ChangeSet> {
+        self.try_prune_spent_before(height, chain, chain_tip)
+            .expect("oracle is infallible")
+    }
+
+    /// Evict unconfirmed transactions which were last seen before `last_seen_cutoff`, and remove
+    /// their txouts from the index.
+    ///
+    /// Refer to [`TxGraph::try_evict_unconfirmed_before`] for which transactions are evicted. The
+    /// removed transactions are recorded in the returned [`ChangeSet`] so that they can be removed
+    /// from persistence as well.
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. If the
+    /// [`ChainOracle`] is infallible, [`evict_unconfirmed_before`] can be used instead.
+    ///
+    /// [`evict_unconfirmed_before`]: Self::evict_unconfirmed_before
+    pub fn try_evict_unconfirmed_before<C: ChainOracle>(
+        &mut self,
+        last_seen_cutoff: u64,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Result<ChangeSet<A, I::ChangeSet>, C::Error> {
+        let changeset =
+            self.graph
+                .try_evict_unconfirmed_before(last_seen_cutoff, chain, chain_tip)?;
+        for &txid in &changeset.removed {
+            self.index.unindex_tx(txid);
+        }
+        Ok(changeset.into())
+    }
+
+    /// Evict unconfirmed transactions which were last seen before `last_seen_cutoff`, and remove
+    /// their txouts from the index.
+    ///
+    /// This is the infallible version of [`try_evict_unconfirmed_before`].
+    ///
+    /// [`try_evict_unconfirmed_before`]: Self::try_evict_unconfirmed_before
+    pub fn evict_unconfirmed_before<C: ChainOracle<Error = Infallible>>(
+        &mut self,
+        last_seen_cutoff: u64,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> ChangeSet<A, I::ChangeSet> {
+        self.try_evict_unconfirmed_before(last_seen_cutoff, chain, chain_tip)
+            .expect("oracle is infallible")
+    }
+
+    /// Removes the given `anchor` of `txid`, without removing the transaction.
+    ///
+    /// Refer to [`TxGraph::remove_anchor`]. The removal is recorded in the returned [`ChangeSet`]
+    /// so that it can be persisted. The index is unchanged, as anchors are not indexed.
+    pub fn remove_anchor(&mut self, txid: Txid, anchor: &A) -> ChangeSet<A, I::ChangeSet> {
+        self.graph.remove_anchor(txid, anchor).into()
     }
 
     /// Insert a floating `txout` of given `outpoint`.
@@ -126,6 +309,47 @@ where
         self.apply_update(update)
     }
 
+    /// Determine the [`ChangeSet`] that inserting `tx` (anchored with `anchor`) would produce,
+    /// without applying it.
+    ///
+    /// The returned changeset includes the changes of the index (i.e. script pubkeys revealed by
+    /// a [`KeychainTxOutIndex`]), as `tx` is indexed by a clone of the index. Applying it with
+    /// [`apply_changeset`] results in the same state as calling [`insert_tx`] directly, as long
+    /// as `self` has not changed in between. This is useful to persist the changes before the
+    /// in-memory state is mutated. Note that the index is cloned for each call.
+    ///
+    /// [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
+    /// [`apply_changeset`]: Self::apply_changeset
+    /// [`insert_tx`]: Self::insert_tx
+    pub fn stage_tx(&self, tx: &Transaction, anchor: Option<A>) -> ChangeSet<A, I::ChangeSet>
+    where
+        I: Clone,
+    {
+        let txid = tx.txid();
+
+        let mut update = TxGraph::<A>::default();
+        if self.graph.get_tx(txid).is_none() {
+            let _ = update.insert_tx(tx.clone());
+        }
+        if let Some(anchor) = anchor {
+            let _ = update.insert_anchor(txid, anchor);
+        }
+        let graph = self.graph.determine_changeset(update);
+
+        let mut index = self.index.clone();
+        let mut indexer = I::ChangeSet::default();
+        for added_tx in &graph.txs {
+            indexer.append(index.index_tx(added_tx));
+        }
+
+        ChangeSet {
+            graph,
+            indexer,
+            removed: Default::default(),
+            removed_anchors: Default::default(),
+        }
+    }
+
     /// Insert relevant transactions from the given `txs` iterator.
     ///
     /// Relevancy is determined by the [`Indexer::is_tx_relevant`] implementation of `I`. Irrelevant
@@ -164,6 +388,50 @@ where
         );
         changeset
     }
+
+    /// Insert the relevant transactions of a block (or any batch of transactions that share the
+    /// same `anchor`).
+    ///
+    /// All `txs` are indexed first, so `txs` do not need to be in topological order. Then,
+    /// transactions that are relevant (as determined by [`Indexer::is_tx_relevant`]) are inserted
+    /// and anchored with `anchor`. Transactions in `txs` which fund inputs of relevant transactions
+    /// are also inserted and anchored, so that the prevouts of relevant transactions are known.
+    /// All other transactions are dropped.
+    pub fn batch_insert_relevant(
+        &mut self,
+        anchor: A,
+        txs: impl IntoIterator<Item = Transaction>,
+    ) -> ChangeSet<A, I::ChangeSet> {
+        let txs = txs.into_iter().collect::<Vec<_>>();
+
+        let mut changeset = ChangeSet::<A, I::ChangeSet>::default();
+        for tx in &txs {
+            changeset.indexer.append(self.index.index_tx(tx));
+        }
+
+        let positions = txs
+            .iter()
+            .enumerate()
+            .map(|(pos, tx)| (tx.txid(), pos))
+            .collect::<HashMap<Txid, usize>>();
+        let mut to_insert = BTreeSet::new();
+        for (pos, tx) in txs.iter().enumerate() {
+            if !self.index.is_tx_relevant(tx) {
+                continue;
+            }
+            to_insert.insert(pos);
+            to_insert.extend(
+                tx.input
+                    .iter()
+                    .filter_map(|txin| positions.get(&txin.previous_output.txid)),
+            );
+        }
+
+        for pos in to_insert {
+            changeset.append(self.insert_tx(&txs[pos], [anchor.clone()], None));
+        }
+        changeset
+    }
 }
 
 /// A structure that represents changes to an [`IndexedTxGraph`].
@@ -185,6 +453,13 @@ pub struct ChangeSet<A, IA> {
     pub graph: tx_graph::ChangeSet<A>,
     /// [`Indexer`] changeset.
     pub indexer: IA,
+    /// Txids of transactions removed from the [`TxGraph`] (refer to
+    /// [`IndexedTxGraph::prune_spent_before`]).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub removed: BTreeSet<Txid>,
+    /// Anchors removed from the [`TxGraph`] (refer to [`IndexedTxGraph::remove_anchor`]).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub removed_anchors: BTreeSet<(A, Txid)>,
 }
 
 impl<A, IA: Default> Default for ChangeSet<A, IA> {
@@ -192,21 +467,95 @@ impl<A, IA: Default> Default for ChangeSet<A, IA> {
         Self {
             graph: Default::default(),
             indexer: Default::default(),
+            removed: Default::default(),
+            removed_anchors: Default::default(),
         }
     }
 }
 
 impl<A: Anchor, IA: Append> Append for ChangeSet<A, IA> {
+    /// Append another [`ChangeSet`] into self.
+    ///
+    /// Additions and removals of the [`TxGraph`] are merged as in
+    /// [`tx_graph::ChangeSetWithRemovals::append`].
     fn append(&mut self, other: Self) {
-        self.graph.append(other.graph);
+        let mut graph = tx_graph::ChangeSetWithRemovals {
+            graph: core::mem::take(&mut self.graph),
+            removed: core::mem::take(&mut self.removed),
+            removed_anchors: core::mem::take(&mut self.removed_anchors),
+        };
+        graph.append(tx_graph::ChangeSetWithRemovals {
+            graph: other.graph,
+            removed: other.removed,
+            removed_anchors: other.removed_anchors,
+        });
+        self.graph = graph.graph;
+        self.removed = graph.removed;
+        self.removed_anchors = graph.removed_anchors;
         self.indexer.append(other.indexer);
     }
 
     fn is_empty(&self) -> bool {
-        self.graph.is_empty() && self.indexer.is_empty()
+        self.graph.is_empty()
+            && self.removed.is_empty()
+            && self.removed_anchors.is_empty()
+            && self.indexer.is_empty()
+    }
+}
+
+impl<A: Anchor, IA> ChangeSet<A, IA> {
+    /// Check that no transaction is anchored to two different heights within this changeset.
+    ///
+    /// A transaction anchored to different blocks at the same height (i.e. across a reorg) is
+    /// consistent. Anchors at different heights within a single changeset usually indicate a bug in
+    /// the code producing the changeset.
+    ///
+    /// This is opt-in: [`Append::append`] never fails and does not perform this check.
+    pub fn check_consistency(&self) -> Result<(), ConsistencyError> {
+        let mut anchor_heights = BTreeMap::<Txid, u32>::new();
+        for (anchor, txid) in &self.graph.anchors {
+            let height = anchor.anchor_block().height;
+            match anchor_heights.get(txid) {
+                Some(&first_height) if first_height != height => {
+                    return Err(ConsistencyError {
+                        txid: *txid,
+                        first_height,
+                        second_height: height,
+                    })
+                }
+                _ => {
+                    anchor_heights.insert(*txid, height);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error returned by [`ChangeSet::check_consistency`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsistencyError {
+    /// The transaction that is anchored to different heights.
+    pub txid: Txid,
+    /// The height of the first anchor of the transaction.
+    pub first_height: u32,
+    /// The height of the conflicting anchor of the transaction.
+    pub second_height: u32,
+}
+
+impl core::fmt::Display for ConsistencyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "transaction {} is anchored to conflicting heights {} and {}",
+            self.txid, self.first_height, self.second_height
+        )
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ConsistencyError {}
+
 impl<A, IA: Default> From<tx_graph::ChangeSet<A>> for ChangeSet<A, IA> {
     fn from(graph: tx_graph::ChangeSet<A>) -> Self {
         Self {
@@ -216,11 +565,24 @@ impl<A, IA: Default> From<tx_graph::ChangeSet<A>> for ChangeSet<A, IA> {
     }
 }
 
+impl<A, IA: Default> From<tx_graph::ChangeSetWithRemovals<A>> for ChangeSet<A, IA> {
+    fn from(changeset: tx_graph::ChangeSetWithRemovals<A>) -> Self {
+        Self {
+            graph: changeset.graph,
+            removed: changeset.removed,
+            removed_anchors: changeset.removed_anchors,
+            ..Default::default()
+        }
+    }
+}
+
 impl<A, K> From<keychain::ChangeSet<K>> for ChangeSet<A, keychain::ChangeSet<K>> {
     fn from(indexer: keychain::ChangeSet<K>) -> Self {
         Self {
             graph: Default::default(),
             indexer,
+            removed: Default::default(),
+            removed_anchors: Default::default(),
         }
     }
 }
@@ -244,4 +606,10 @@ pub trait Indexer {
 
     /// Determines whether the transaction should be included in the index.
     fn is_tx_relevant(&self, tx: &Transaction) -> bool;
+
+    /// Remove the indexed txouts of the transaction of `txid`.
+    ///
+    /// This is called when the transaction is removed from the [`TxGraph`] of an
+    /// [`IndexedTxGraph`]. The default implementation does nothing.
+    fn unindex_tx(&mut self, _txid: Txid) {}
 }
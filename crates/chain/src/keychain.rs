@@ -11,8 +11,12 @@
 //! [`SpkTxOutIndex`]: crate::SpkTxOutIndex
 
 use crate::{
-    collections::BTreeMap, indexed_tx_graph, local_chain, tx_graph::TxGraph, Anchor, Append,
+    collections::{BTreeMap, BTreeSet},
+    indexed_tx_graph, local_chain,
+    tx_graph::TxGraph,
+    Anchor, Append, BlockId, COINBASE_MATURITY,
 };
+use bitcoin::BlockHash;
 
 #[cfg(feature = "miniscript")]
 mod txout_index;
@@ -82,10 +86,91 @@ impl<K> AsRef<BTreeMap<K, u32>> for ChangeSet<K> {
     }
 }
 
+/// A [`ChangeSet`] that can also record the removal of keychains.
+///
+/// Unlike [`ChangeSet`], this is not monotone: a keychain that is removed loses its derivation
+/// index. The removal set is empty (and does not allocate) in the common case where no keychains
+/// are removed.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(
+        crate = "serde_crate",
+        bound(
+            deserialize = "K: Ord + serde::Deserialize<'de>",
+            serialize = "K: Ord + serde::Serialize"
+        )
+    )
+)]
+#[must_use]
+pub struct RemovableChangeSet<K> {
+    /// The derivation index changes of keychains that are retained.
+    pub indices: ChangeSet<K>,
+    /// Keychains that are removed.
+    pub removed: BTreeSet<K>,
+}
+
+impl<K: Ord> Append for RemovableChangeSet<K> {
+    /// Append another [`RemovableChangeSet`] into self.
+    ///
+    /// Derivation indices are merged as in [`ChangeSet::append`]. A keychain with an index in
+    /// `other` is no longer considered removed. A keychain removed in `other` is removed from self,
+    /// even if self (or `other`) has an index for it.
+    fn append(&mut self, mut other: Self) {
+        if !self.removed.is_empty() {
+            for keychain in other.indices.0.keys() {
+                self.removed.remove(keychain);
+            }
+        }
+        self.indices.append(core::mem::take(&mut other.indices));
+
+        if !other.removed.is_empty() {
+            for keychain in &other.removed {
+                self.indices.0.remove(keychain);
+            }
+            self.removed.append(&mut other.removed);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl<K> Default for RemovableChangeSet<K> {
+    fn default() -> Self {
+        Self {
+            indices: Default::default(),
+            removed: Default::default(),
+        }
+    }
+}
+
+impl<K> From<ChangeSet<K>> for RemovableChangeSet<K> {
+    fn from(indices: ChangeSet<K>) -> Self {
+        Self {
+            indices,
+            removed: Default::default(),
+        }
+    }
+}
+
 /// A structure to update [`KeychainTxOutIndex`], [`TxGraph`] and [`LocalChain`] atomically.
 ///
 /// [`LocalChain`]: local_chain::LocalChain
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(
+        crate = "serde_crate",
+        bound(
+            deserialize = "K: Ord + serde::Deserialize<'de>, A: Clone + Ord + serde::Deserialize<'de>",
+            serialize = "K: Ord + serde::Serialize, A: Clone + Ord + serde::Serialize",
+        )
+    )
+)]
 pub struct WalletUpdate<K, A> {
     /// Contains the last active derivation indices per keychain (`K`), which is used to update the
     /// [`KeychainTxOutIndex`].
@@ -111,6 +196,85 @@ impl<K, A> WalletUpdate<K, A> {
     }
 }
 
+impl<K: Ord, A: Anchor> WalletUpdate<K, A> {
+    /// Merge `other` into `self`.
+    ///
+    /// The last active indices are merged by taking the max index per keychain, the graphs are
+    /// combined and the chain updates are combined into a single chain that contains the blocks of
+    /// both. This is useful for recombining updates obtained from separate partial scans (e.g. a
+    /// scan per keychain performed in parallel).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MergeUpdateError`] if the chain updates have different blocks at the same height.
+    /// `self` is left unchanged in this case.
+    pub fn merge(&mut self, other: WalletUpdate<K, A>) -> Result<(), MergeUpdateError> {
+        let mut blocks = self
+            .chain
+            .tip
+            .iter()
+            .map(|cp| (cp.height(), cp.hash()))
+            .collect::<BTreeMap<_, _>>();
+        for cp in other.chain.tip.iter() {
+            let (height, other_hash) = (cp.height(), cp.hash());
+            match blocks.get(&height) {
+                Some(&this_hash) if this_hash != other_hash => {
+                    return Err(MergeUpdateError {
+                        height,
+                        this_hash,
+                        other_hash,
+                    })
+                }
+                _ => {
+                    blocks.insert(height, other_hash);
+                }
+            }
+        }
+
+        let mut blocks = blocks
+            .into_iter()
+            .map(|(height, hash)| BlockId { height, hash });
+        let first_block = blocks.next().expect("update tip must exist");
+        self.chain = local_chain::Update {
+            tip: local_chain::CheckPoint::new(first_block)
+                .extend(blocks)
+                .expect("blocks are in ascending height order"),
+            introduce_older_blocks: self.chain.introduce_older_blocks
+                || other.chain.introduce_older_blocks,
+        };
+        let _ = self.graph.apply_update(other.graph);
+        for (keychain, index) in other.last_active_indices {
+            let this_index = self.last_active_indices.entry(keychain).or_insert(index);
+            *this_index = (*this_index).max(index);
+        }
+        Ok(())
+    }
+}
+
+/// An error returned by [`WalletUpdate::merge`] when the chain updates conflict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeUpdateError {
+    /// The height where the chain updates conflict.
+    pub height: u32,
+    /// The block hash of the update being merged into.
+    pub this_hash: BlockHash,
+    /// The block hash of the update being merged.
+    pub other_hash: BlockHash,
+}
+
+impl core::fmt::Display for MergeUpdateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "cannot merge chain updates with conflicting blocks at height {}: {} and {}",
+            self.height, self.this_hash, self.other_hash
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MergeUpdateError {}
+
 /// A structure that records the corresponding changes as result of applying an [`WalletUpdate`].
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(
@@ -145,6 +309,54 @@ impl<K, A> Default for WalletChangeSet<K, A> {
     }
 }
 
+#[cfg(feature = "serde_json")]
+impl<K, A> WalletChangeSet<K, A>
+where
+    K: Ord + serde::Serialize + serde::de::DeserializeOwned,
+    A: Ord + serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Serialize the [`WalletChangeSet`] as pretty-printed JSON.
+    ///
+    /// This is intended for human-readable backups and inspection of persisted state. Use
+    /// [`from_json`] to read it back.
+    ///
+    /// [`from_json`]: Self::from_json
+    pub fn to_json(&self) -> serde_json::Result<alloc::string::String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Deserialize a [`WalletChangeSet`] from JSON (as written by [`to_json`]).
+    ///
+    /// [`to_json`]: Self::to_json
+    pub fn from_json(s: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(s)
+    }
+}
+
+impl<K, A> WalletChangeSet<K, A> {
+    /// Split the [`WalletChangeSet`] into its [`local_chain::ChangeSet`] and
+    /// [`indexed_tx_graph::ChangeSet`].
+    pub fn into_parts(
+        self,
+    ) -> (
+        local_chain::ChangeSet,
+        indexed_tx_graph::ChangeSet<A, ChangeSet<K>>,
+    ) {
+        (self.chain, self.indexed_tx_graph)
+    }
+
+    /// Get references to the [`local_chain::ChangeSet`] and [`indexed_tx_graph::ChangeSet`] of the
+    /// [`WalletChangeSet`].
+    pub fn parts(
+        &self,
+    ) -> (
+        &local_chain::ChangeSet,
+        &indexed_tx_graph::ChangeSet<A, ChangeSet<K>>,
+    ) {
+        (&self.chain, &self.indexed_tx_graph)
+    }
+}
+
 impl<K: Ord, A: Anchor> Append for WalletChangeSet<K, A> {
     fn append(&mut self, other: Self) {
         Append::append(&mut self.chain, other.chain);
@@ -174,6 +386,209 @@ impl<K, A> From<indexed_tx_graph::ChangeSet<A, ChangeSet<K>>> for WalletChangeSe
     }
 }
 
+/// A [`WalletChangeSet`] tagged with the version of its schema, for forward-compatible
+/// persistence.
+///
+/// When serialized, the `version` is written before the `inner` changeset. On deserialization,
+/// the `version` is checked before `inner` is decoded, so a changeset persisted by a newer
+/// (unknown) version results in a clear error rather than a garbled changeset. Use [`migrate`] to
+/// obtain the [`WalletChangeSet`] of a loaded [`PersistedChangeSet`].
+///
+/// [`migrate`]: Self::migrate
+#[derive(Debug, Clone, PartialEq)]
+#[must_use]
+pub struct PersistedChangeSet<K, A> {
+    /// The schema version of `inner`.
+    pub version: u16,
+    /// The versioned changeset.
+    pub inner: WalletChangeSet<K, A>,
+}
+
+impl<K, A> PersistedChangeSet<K, A> {
+    /// The schema version of [`WalletChangeSet`] of this version of the crate.
+    pub const CURRENT_VERSION: u16 = 1;
+
+    /// Tag `inner` with the [`CURRENT_VERSION`].
+    ///
+    /// [`CURRENT_VERSION`]: Self::CURRENT_VERSION
+    pub fn new(inner: WalletChangeSet<K, A>) -> Self {
+        Self {
+            version: Self::CURRENT_VERSION,
+            inner,
+        }
+    }
+
+    /// Migrate the changeset to the [`CURRENT_VERSION`] and return it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UnknownVersionError`] if `version` is not supported by this version of the crate.
+    ///
+    /// [`CURRENT_VERSION`]: Self::CURRENT_VERSION
+    pub fn migrate(self) -> Result<WalletChangeSet<K, A>, UnknownVersionError> {
+        match self.version {
+            Self::CURRENT_VERSION => Ok(self.inner),
+            version => Err(UnknownVersionError { version }),
+        }
+    }
+}
+
+impl<K, A> Default for PersistedChangeSet<K, A> {
+    fn default() -> Self {
+        Self::new(WalletChangeSet::default())
+    }
+}
+
+impl<K: Ord, A: Anchor> Append for PersistedChangeSet<K, A> {
+    fn append(&mut self, other: Self) {
+        self.version = self.version.max(other.version);
+        self.inner.append(other.inner);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl<K, A> From<WalletChangeSet<K, A>> for PersistedChangeSet<K, A> {
+    fn from(inner: WalletChangeSet<K, A>) -> Self {
+        Self::new(inner)
+    }
+}
+
+/// A [`PersistedChangeSet`] is serialized as a `(version, inner)` tuple.
+#[cfg(feature = "serde")]
+impl<K, A> serde::Serialize for PersistedChangeSet<K, A>
+where
+    K: Ord + serde::Serialize,
+    A: Ord + serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&self.version)?;
+        tuple.serialize_element(&self.inner)?;
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, A> serde::Deserialize<'de> for PersistedChangeSet<K, A>
+where
+    K: Ord + serde::Deserialize<'de>,
+    A: Ord + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor<K, A>(core::marker::PhantomData<(K, A)>);
+
+        impl<'de, K, A> serde::de::Visitor<'de> for Visitor<K, A>
+        where
+            K: Ord + serde::Deserialize<'de>,
+            A: Ord + serde::Deserialize<'de>,
+        {
+            type Value = PersistedChangeSet<K, A>;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "a (version, changeset) tuple")
+            }
+
+            fn visit_seq<S: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: S,
+            ) -> Result<Self::Value, S::Error> {
+                use serde::de::Error;
+
+                let version: u16 = seq
+                    .next_element()?
+                    .ok_or_else(|| S::Error::invalid_length(0, &self))?;
+                // do not attempt to decode a changeset of an unknown schema
+                if version > PersistedChangeSet::<K, A>::CURRENT_VERSION {
+                    return Err(S::Error::custom(UnknownVersionError { version }));
+                }
+                let inner = seq
+                    .next_element()?
+                    .ok_or_else(|| S::Error::invalid_length(1, &self))?;
+                Ok(PersistedChangeSet { version, inner })
+            }
+        }
+
+        deserializer.deserialize_tuple(2, Visitor(core::marker::PhantomData))
+    }
+}
+
+/// An error that occurs when a [`PersistedChangeSet`] has a version that is not supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownVersionError {
+    /// The unsupported version.
+    pub version: u16,
+}
+
+impl core::fmt::Display for UnknownVersionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "unknown changeset version {} (the latest supported version is {})",
+            self.version,
+            PersistedChangeSet::<(), ()>::CURRENT_VERSION
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnknownVersionError {}
+
+/// The kind of a keychain in the common two-keychain setup.
+///
+/// This can be used directly as the keychain type parameter `K` (e.g. of [`KeychainTxOutIndex`])
+/// by applications which do not need custom keychains. [`External`] sorts before [`Internal`], so
+/// iterating over keychains is deterministic. This is the same type as `bdk::KeychainKind` (which
+/// re-exports it), so wallets and applications built directly on `bdk_chain` share keychains.
+///
+/// [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
+/// [`External`]: Self::External
+/// [`Internal`]: Self::Internal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(crate = "serde_crate",)
+)]
+pub enum KeychainKind {
+    /// External keychain, used for deriving recipient addresses.
+    External = 0,
+    /// Internal keychain, used for deriving change addresses.
+    Internal = 1,
+}
+
+impl KeychainKind {
+    /// Return [`KeychainKind`] as a byte
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            KeychainKind::External => b'e',
+            KeychainKind::Internal => b'i',
+        }
+    }
+}
+
+impl AsRef<[u8]> for KeychainKind {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            KeychainKind::External => b"e",
+            KeychainKind::Internal => b"i",
+        }
+    }
+}
+
+impl core::fmt::Display for KeychainKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            KeychainKind::External => write!(f, "external"),
+            KeychainKind::Internal => write!(f, "internal"),
+        }
+    }
+}
+
 /// Balance, differentiated into various categories.
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 #[cfg_attr(
@@ -190,20 +605,153 @@ pub struct Balance {
     pub untrusted_pending: u64,
     /// Confirmed and immediately spendable balance
     pub confirmed: u64,
+    /// Immature coinbase amounts keyed by the confirmation height of the coinbase transaction.
+    ///
+    /// Together with `locked_immature_coinbase`, the values sum up to `immature`. This allows
+    /// [`spendable_at_height`] to determine which amounts have matured at a later tip.
+    ///
+    /// [`spendable_at_height`]: Self::spendable_at_height
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub immature_coinbase: BTreeMap<u32, u64>,
+    /// Immature coinbase amounts which the caller has locked, keyed like `immature_coinbase`.
+    ///
+    /// These amounts move into `locked` (instead of `confirmed`) once they have matured.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub locked_immature_coinbase: BTreeMap<u32, u64>,
+    /// Confirmed or trusted pending UTXOs which the caller has locked (i.e. frozen for coin
+    /// control) and which are therefore not counted as spendable
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub locked: u64,
 }
 
 impl Balance {
     /// Get sum of trusted_pending and confirmed coins.
     ///
     /// This is the balance you can spend right now that shouldn't get cancelled via another party
-    /// double spending it.
+    /// double spending it. `locked` coins are excluded.
     pub fn trusted_spendable(&self) -> u64 {
         self.confirmed + self.trusted_pending
     }
 
-    /// Get the whole balance visible to the wallet.
+    /// Get the whole balance visible to the wallet, including `locked` coins.
     pub fn total(&self) -> u64 {
-        self.confirmed + self.trusted_pending + self.untrusted_pending + self.immature
+        self.confirmed + self.trusted_pending + self.untrusted_pending + self.immature + self.locked
+    }
+
+    /// Get the fraction (between `0.0` and `1.0`) of the [`total`] balance that is `confirmed`.
+    ///
+    /// Returns `0.0` if the total balance is zero.
+    ///
+    /// [`total`]: Self::total
+    pub fn confirmed_ratio(&self) -> f64 {
+        Self::ratio(self.confirmed, self.total())
+    }
+
+    /// Get the fraction (between `0.0` and `1.0`) of the [`total`] balance that is pending. This
+    /// includes `trusted_pending`, `untrusted_pending` and `immature` amounts.
+    ///
+    /// Returns `0.0` if the total balance is zero.
+    ///
+    /// [`total`]: Self::total
+    pub fn pending_ratio(&self) -> f64 {
+        Self::ratio(
+            self.trusted_pending + self.untrusted_pending + self.immature,
+            self.total(),
+        )
+    }
+
+    fn ratio(amount: u64, total: u64) -> f64 {
+        if total == 0 {
+            return 0.0;
+        }
+        amount as f64 / total as f64
+    }
+
+    /// Get the balance as it would be with a chain tip of `tip_height`.
+    ///
+    /// Immature coinbase amounts which have reached [`COINBASE_MATURITY`] at `tip_height` are
+    /// moved from `immature` into `confirmed`, or into `locked` if they are locked. All other
+    /// amounts are left untouched.
+    ///
+    /// A `tip_height` lower than the tip that the balance was computed with never moves amounts
+    /// back into `immature`. Reorgs are accounted for by recomputing the balance against the new
+    /// chain.
+    pub fn spendable_at_height(&self, tip_height: u32) -> Balance {
+        self.spendable_at_height_with_coinbase_maturity(tip_height, COINBASE_MATURITY)
+    }
+
+    /// Get the balance as it would be with a chain tip of `tip_height`, where coinbase outputs
+    /// require `coinbase_maturity` confirmations to be spendable (instead of
+    /// [`COINBASE_MATURITY`]).
+    ///
+    /// Refer to [`spendable_at_height`] for more.
+    ///
+    /// [`spendable_at_height`]: Self::spendable_at_height
+    pub fn spendable_at_height_with_coinbase_maturity(
+        &self,
+        tip_height: u32,
+        coinbase_maturity: u32,
+    ) -> Balance {
+        let is_mature = |height: u32| {
+            let age = tip_height.saturating_sub(height);
+            height <= tip_height && age + 1 >= coinbase_maturity
+        };
+        let mut balance = Balance {
+            immature_coinbase: BTreeMap::new(),
+            locked_immature_coinbase: BTreeMap::new(),
+            ..self.clone()
+        };
+        for (&height, &value) in &self.immature_coinbase {
+            if is_mature(height) {
+                balance.immature -= value;
+                balance.confirmed += value;
+            } else {
+                balance.immature_coinbase.insert(height, value);
+            }
+        }
+        for (&height, &value) in &self.locked_immature_coinbase {
+            if is_mature(height) {
+                balance.immature -= value;
+                balance.locked += value;
+            } else {
+                balance.locked_immature_coinbase.insert(height, value);
+            }
+        }
+        balance
+    }
+
+    /// Add each category of `other` to `self`, returning `None` if any category overflows.
+    ///
+    /// Use this instead of `+` when summing balances which are not known to be small enough, as
+    /// the [`Add`](core::ops::Add) impl panics on overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        fn checked_add_coinbase(
+            mut lhs: BTreeMap<u32, u64>,
+            rhs: BTreeMap<u32, u64>,
+        ) -> Option<BTreeMap<u32, u64>> {
+            for (height, value) in rhs {
+                let entry = lhs.entry(height).or_default();
+                *entry = entry.checked_add(value)?;
+            }
+            Some(lhs)
+        }
+        Some(Self {
+            immature: self.immature.checked_add(other.immature)?,
+            trusted_pending: self.trusted_pending.checked_add(other.trusted_pending)?,
+            untrusted_pending: self
+                .untrusted_pending
+                .checked_add(other.untrusted_pending)?,
+            confirmed: self.confirmed.checked_add(other.confirmed)?,
+            immature_coinbase: checked_add_coinbase(
+                self.immature_coinbase,
+                other.immature_coinbase,
+            )?,
+            locked_immature_coinbase: checked_add_coinbase(
+                self.locked_immature_coinbase,
+                other.locked_immature_coinbase,
+            )?,
+            locked: self.locked.checked_add(other.locked)?,
+        })
     }
 }
 
@@ -211,8 +759,8 @@ impl core::fmt::Display for Balance {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "{{ immature: {}, trusted_pending: {}, untrusted_pending: {}, confirmed: {} }}",
-            self.immature, self.trusted_pending, self.untrusted_pending, self.confirmed
+            "{{ immature: {}, trusted_pending: {}, untrusted_pending: {}, confirmed: {}, locked: {} }}",
+            self.immature, self.trusted_pending, self.untrusted_pending, self.confirmed, self.locked
         )
     }
 }
@@ -220,12 +768,53 @@ impl core::fmt::Display for Balance {
 impl core::ops::Add for Balance {
     type Output = Self;
 
+    /// Adds each category of `other` to `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any category overflows (in both debug and release builds). Use
+    /// [`Balance::checked_add`] to handle overflow.
     fn add(self, other: Self) -> Self {
+        self.checked_add(other)
+            .expect("balance must not overflow when added")
+    }
+}
+
+impl core::ops::Sub for Balance {
+    type Output = Self;
+
+    /// Subtracts each category of `other` from `self`.
+    ///
+    /// Subtraction saturates at zero as a category can legitimately decrease between two balance
+    /// snapshots (e.g. due to reorgs or spends).
+    fn sub(self, other: Self) -> Self {
+        fn sub_coinbase(
+            mut lhs: BTreeMap<u32, u64>,
+            rhs: BTreeMap<u32, u64>,
+        ) -> BTreeMap<u32, u64> {
+            for (height, value) in rhs {
+                if let Some(lhs_value) = lhs.get_mut(&height) {
+                    *lhs_value = lhs_value.saturating_sub(value);
+                    if *lhs_value == 0 {
+                        lhs.remove(&height);
+                    }
+                }
+            }
+            lhs
+        }
         Self {
-            immature: self.immature + other.immature,
-            trusted_pending: self.trusted_pending + other.trusted_pending,
-            untrusted_pending: self.untrusted_pending + other.untrusted_pending,
-            confirmed: self.confirmed + other.confirmed,
+            immature: self.immature.saturating_sub(other.immature),
+            trusted_pending: self.trusted_pending.saturating_sub(other.trusted_pending),
+            untrusted_pending: self
+                .untrusted_pending
+                .saturating_sub(other.untrusted_pending),
+            confirmed: self.confirmed.saturating_sub(other.confirmed),
+            immature_coinbase: sub_coinbase(self.immature_coinbase, other.immature_coinbase),
+            locked_immature_coinbase: sub_coinbase(
+                self.locked_immature_coinbase,
+                other.locked_immature_coinbase,
+            ),
+            locked: self.locked.saturating_sub(other.locked),
         }
     }
 }
@@ -265,4 +854,309 @@ mod test {
         // New keychain gets added if the keychain is in `other` but not in `self`.
         assert_eq!(lhs.0.get(&Keychain::Four), Some(&4));
     }
+
+    #[test]
+    fn append_removable_changeset() {
+        let mut lhs = RemovableChangeSet::<u8>::from(ChangeSet([(1, 7), (2, 3)].into()));
+        lhs.removed.insert(3);
+
+        lhs.append(RemovableChangeSet {
+            indices: ChangeSet([(1, 2), (3, 1)].into()),
+            removed: [2].into(),
+        });
+        // a removal in `other` wins over an index in `self`
+        assert_eq!(lhs.indices.0, [(1, 7), (3, 1)].into());
+        // an index in `other` re-adds a removed keychain
+        assert_eq!(lhs.removed, [2].into());
+
+        // a keychain that is both indexed and removed in `other` is removed
+        lhs.append(RemovableChangeSet {
+            indices: ChangeSet([(1, 9)].into()),
+            removed: [1].into(),
+        });
+        assert_eq!(lhs.indices.0, [(3, 1)].into());
+        assert_eq!(lhs.removed, [1, 2].into());
+
+        lhs.append(RemovableChangeSet::default());
+        assert_eq!(lhs.indices.0, [(3, 1)].into());
+        assert!(!lhs.is_empty());
+        assert!(RemovableChangeSet::<u8>::default().is_empty());
+    }
+
+    #[test]
+    fn wallet_changeset_into_parts() {
+        let chain = local_chain::ChangeSet::from([(1, None)]);
+        let indexed_tx_graph = indexed_tx_graph::ChangeSet::<crate::BlockId, ChangeSet<u8>> {
+            indexer: ChangeSet([(0, 5)].into()),
+            ..Default::default()
+        };
+
+        let mut changeset = WalletChangeSet::from(chain.clone());
+        changeset.append(WalletChangeSet::from(indexed_tx_graph.clone()));
+
+        assert_eq!(changeset.parts(), (&chain, &indexed_tx_graph));
+        assert_eq!(changeset.into_parts(), (chain, indexed_tx_graph));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn persisted_changeset_version() {
+        use alloc::string::ToString;
+        use bitcoin::hashes::Hash;
+
+        type PersistedChangeSet = super::PersistedChangeSet<u8, crate::BlockId>;
+
+        let changeset = WalletChangeSet::from(local_chain::ChangeSet::from([(
+            1,
+            Some(bitcoin::BlockHash::all_zeros()),
+        )]));
+        let persisted = PersistedChangeSet::new(changeset.clone());
+        assert_eq!(persisted.version, PersistedChangeSet::CURRENT_VERSION);
+
+        let serialized = serde_json::to_string(&persisted).expect("must serialize");
+        let deserialized: PersistedChangeSet =
+            serde_json::from_str(&serialized).expect("must deserialize");
+        assert_eq!(deserialized.migrate(), Ok(changeset));
+
+        // a newer version is rejected before the changeset is decoded
+        let unknown_version = PersistedChangeSet::CURRENT_VERSION + 1;
+        let err = serde_json::from_str::<PersistedChangeSet>(&alloc::format!(
+            "[{}, \"not a changeset\"]",
+            unknown_version
+        ))
+        .expect_err("must fail to deserialize");
+        assert!(err.to_string().contains(&alloc::format!(
+            "unknown changeset version {}",
+            unknown_version
+        )));
+
+        let old = PersistedChangeSet {
+            version: 0,
+            inner: WalletChangeSet::default(),
+        };
+        assert_eq!(old.migrate(), Err(UnknownVersionError { version: 0 }));
+    }
+
+    #[test]
+    fn wallet_update_merge() {
+        use crate::tx_graph::ChangeSet as GraphChangeSet;
+        use alloc::vec::Vec;
+        use bitcoin::hashes::Hash;
+
+        let block = |height: u32, tag: u8| BlockId {
+            height,
+            hash: BlockHash::hash(&[tag]),
+        };
+        let chain_update = |blocks: &[BlockId]| local_chain::Update {
+            tip: local_chain::CheckPoint::new(blocks[0])
+                .extend(blocks[1..].iter().copied())
+                .expect("blocks must be in order"),
+            introduce_older_blocks: false,
+        };
+        let anchored_update = |txid_tag: u8, anchor: BlockId| {
+            let mut graph = TxGraph::<BlockId>::default();
+            let _ = graph.insert_anchor(bitcoin::Txid::hash(&[txid_tag]), anchor);
+            graph
+        };
+
+        let mut update = WalletUpdate::<u8, BlockId> {
+            last_active_indices: [(0, 5), (1, 2)].into(),
+            graph: anchored_update(0, block(1, 1)),
+            chain: chain_update(&[block(0, 0), block(1, 1)]),
+        };
+        let other = WalletUpdate::<u8, BlockId> {
+            last_active_indices: [(0, 3), (1, 4), (2, 1)].into(),
+            graph: anchored_update(1, block(2, 2)),
+            chain: chain_update(&[block(0, 0), block(2, 2)]),
+        };
+
+        update.merge(other).expect("must merge");
+        assert_eq!(update.last_active_indices, [(0, 5), (1, 4), (2, 1)].into());
+        assert_eq!(
+            update.graph.initial_changeset(),
+            GraphChangeSet {
+                anchors: [
+                    (block(1, 1), bitcoin::Txid::hash(&[0])),
+                    (block(2, 2), bitcoin::Txid::hash(&[1]))
+                ]
+                .into(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            update
+                .chain
+                .tip
+                .iter()
+                .map(|cp| cp.block_id())
+                .collect::<Vec<_>>(),
+            vec![block(2, 2), block(1, 1), block(0, 0)]
+        );
+
+        // conflicting blocks result in an error and leave the update unchanged
+        let expected = update.clone();
+        let conflicting = WalletUpdate::<u8, BlockId> {
+            last_active_indices: [(0, 10)].into(),
+            graph: TxGraph::default(),
+            chain: chain_update(&[block(0, 0), block(1, 42)]),
+        };
+        assert_eq!(
+            update.merge(conflicting),
+            Err(MergeUpdateError {
+                height: 1,
+                this_hash: block(1, 1).hash,
+                other_hash: block(1, 42).hash,
+            })
+        );
+        assert_eq!(update.last_active_indices, expected.last_active_indices);
+    }
+
+    #[test]
+    fn balance_ratios() {
+        let balance = Balance {
+            immature: 1_000,
+            trusted_pending: 2_000,
+            untrusted_pending: 2_000,
+            confirmed: 15_000,
+            immature_coinbase: [(100, 1_000)].into(),
+            locked_immature_coinbase: BTreeMap::new(),
+            locked: 0,
+        };
+        assert_eq!(balance.confirmed_ratio(), 0.75);
+        assert_eq!(balance.pending_ratio(), 0.25);
+
+        // a zero total balance yields zero ratios rather than NaN
+        let empty = Balance::default();
+        assert_eq!(empty.confirmed_ratio(), 0.0);
+        assert_eq!(empty.pending_ratio(), 0.0);
+    }
+
+    #[test]
+    fn sub_balance_saturates() {
+        let before = Balance {
+            immature: 5_000,
+            trusted_pending: 1_000,
+            untrusted_pending: 0,
+            confirmed: 20_000,
+            immature_coinbase: [(100, 5_000)].into(),
+            locked_immature_coinbase: BTreeMap::new(),
+            locked: 0,
+        };
+        let after = Balance {
+            immature: 0,
+            trusted_pending: 3_000,
+            untrusted_pending: 2_000,
+            confirmed: 25_000,
+            immature_coinbase: BTreeMap::new(),
+            locked_immature_coinbase: BTreeMap::new(),
+            locked: 0,
+        };
+
+        assert_eq!(
+            after.clone() - before.clone(),
+            Balance {
+                immature: 0,
+                trusted_pending: 2_000,
+                untrusted_pending: 2_000,
+                confirmed: 5_000,
+                immature_coinbase: BTreeMap::new(),
+                locked_immature_coinbase: BTreeMap::new(),
+                locked: 0,
+            }
+        );
+        // Categories which decreased saturate at zero instead of underflowing.
+        assert_eq!(
+            before.clone() - after,
+            Balance {
+                immature: 5_000,
+                immature_coinbase: [(100, 5_000)].into(),
+                ..Default::default()
+            }
+        );
+        assert_eq!(before.clone() - before, Balance::default());
+    }
+
+    #[test]
+    fn checked_add_balance() {
+        let a = Balance {
+            immature: 1_000,
+            trusted_pending: 2_000,
+            untrusted_pending: 3_000,
+            confirmed: 4_000,
+            immature_coinbase: [(100, 1_000)].into(),
+            locked_immature_coinbase: BTreeMap::new(),
+            locked: 0,
+        };
+        let b = Balance {
+            immature: 500,
+            confirmed: 6_000,
+            immature_coinbase: [(100, 200), (101, 300)].into(),
+            ..Default::default()
+        };
+        let sum = Balance {
+            immature: 1_500,
+            trusted_pending: 2_000,
+            untrusted_pending: 3_000,
+            confirmed: 10_000,
+            immature_coinbase: [(100, 1_200), (101, 300)].into(),
+            locked_immature_coinbase: BTreeMap::new(),
+            locked: 0,
+        };
+        assert_eq!(a.clone().checked_add(b.clone()), Some(sum.clone()));
+        assert_eq!(a.clone() + b, sum);
+
+        // overflow of any category (including immature coinbase amounts) is detected
+        let near_max = Balance {
+            confirmed: u64::MAX - 1,
+            ..Default::default()
+        };
+        assert_eq!(near_max.clone().checked_add(a.clone()), None);
+        assert_eq!(
+            near_max.clone().checked_add(Balance {
+                confirmed: 1,
+                ..Default::default()
+            }),
+            Some(Balance {
+                confirmed: u64::MAX,
+                ..Default::default()
+            })
+        );
+        let near_max_coinbase = Balance {
+            immature_coinbase: [(100, u64::MAX)].into(),
+            ..Default::default()
+        };
+        assert_eq!(near_max_coinbase.checked_add(a), None);
+    }
+
+    #[test]
+    fn keychain_kind_order_and_display() {
+        assert!(KeychainKind::External < KeychainKind::Internal);
+        let keychains = [KeychainKind::Internal, KeychainKind::External]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            keychains.into_iter().collect::<alloc::vec::Vec<_>>(),
+            [KeychainKind::External, KeychainKind::Internal]
+        );
+        assert_eq!(alloc::format!("{}", KeychainKind::External), "external");
+        assert_eq!(alloc::format!("{}", KeychainKind::Internal), "internal");
+        assert_eq!(KeychainKind::External.as_byte(), b'e');
+        assert_eq!(KeychainKind::Internal.as_ref(), b"i");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn keychain_kind_serde() {
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            let json = serde_json::to_string(&keychain).unwrap();
+            assert_eq!(
+                serde_json::from_str::<KeychainKind>(&json).unwrap(),
+                keychain
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&KeychainKind::External).unwrap(),
+            "\"External\""
+        );
+    }
 }
@@ -1,13 +1,19 @@
 use crate::{
     collections::*,
     indexed_tx_graph::Indexer,
+    keychain::Balance,
     miniscript::{Descriptor, DescriptorPublicKey},
     spk_iter::BIP32_MAX_INDEX,
-    ForEachTxOut, SpkIterator, SpkTxOutIndex,
+    tx_graph::TxGraph,
+    Anchor, BlockId, ChainOracle, ForEachTxOut, SpkIterator, SpkTxOutIndex,
 };
 use alloc::vec::Vec;
-use bitcoin::{OutPoint, Script, TxOut};
-use core::{fmt::Debug, ops::Deref};
+use bitcoin::{OutPoint, Script, ScriptBuf, TxOut};
+use core::{
+    convert::Infallible,
+    fmt::Debug,
+    ops::{Deref, RangeInclusive},
+};
 
 use crate::Append;
 
@@ -68,6 +74,33 @@ pub struct KeychainTxOutIndex<K> {
     lookahead: BTreeMap<K, u32>,
 }
 
+/// An error returned by [`KeychainTxOutIndex::replace_descriptor`] when the new descriptor
+/// derives different script pubkeys at revealed indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaceDescriptorError {
+    /// The revealed derivation indices where the script pubkeys of the new descriptor differ.
+    pub mismatched_indices: Vec<u32>,
+}
+
+impl core::fmt::Display for ReplaceDescriptorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "the new descriptor derives different script pubkeys at revealed indices {:?}",
+            self.mismatched_indices
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReplaceDescriptorError {}
+
+/// Unspent outpoints grouped by the `(keychain, derivation index)` of the script pubkey they pay
+/// to.
+///
+/// This is returned by [`KeychainTxOutIndex::group_unspents_by_spk`].
+pub type UnspentsBySpk<K> = BTreeMap<(K, u32), Vec<OutPoint>>;
+
 impl<K> Default for KeychainTxOutIndex<K> {
     fn default() -> Self {
         Self {
@@ -109,6 +142,10 @@ impl<K: Clone + Ord + Debug> Indexer for KeychainTxOutIndex<K> {
     fn is_tx_relevant(&self, tx: &bitcoin::Transaction) -> bool {
         self.is_relevant(tx)
     }
+
+    fn unindex_tx(&mut self, txid: bitcoin::Txid) {
+        self.inner.remove_txouts_of(txid)
+    }
 }
 
 impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
@@ -155,6 +192,9 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
     }
 
     /// Return a reference to the internal map of the keychain to descriptors.
+    ///
+    /// Iterating the map yields the keychains (and their descriptors) in `K` order. No script
+    /// pubkeys are derived.
     pub fn keychains(&self) -> &BTreeMap<K, Descriptor<DescriptorPublicKey>> {
         &self.keychains
     }
@@ -164,6 +204,10 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
     /// Adding a keychain means you will be able to derive new script pubkeys under that keychain
     /// and the txout index will discover transaction outputs with those script pubkeys.
     ///
+    /// A `descriptor` without a wildcard (e.g. a fixed `pkh(<pubkey>)`) is treated as a keychain
+    /// with a single script pubkey at derivation index 0. Revealing past index 0 is a no-op and the
+    /// lookahead never stores more than that one script pubkey.
+    ///
     /// # Panics
     ///
     /// This will panic if a different `descriptor` is introduced to the same `keychain`.
@@ -178,6 +222,97 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         );
     }
 
+    /// Replace the descriptor of `keychain` with `descriptor`, keeping the revealed indices.
+    ///
+    /// The script pubkeys at the revealed indices of `keychain` are re-derived with `descriptor`.
+    /// If they are all the same (e.g. only the key origin of the descriptor is fixed), the
+    /// descriptor is replaced and the indexed txouts of `keychain` are kept. Stored lookahead
+    /// script pubkeys are re-derived with `descriptor`.
+    ///
+    /// Otherwise, replacing the descriptor invalidates the index of `keychain`, so a
+    /// [`ReplaceDescriptorError`] listing the mismatched indices is returned and nothing is
+    /// changed, unless `force` is `true`. With `force`, every stored script pubkey and indexed
+    /// txout of `keychain` is cleared and the script pubkeys are re-derived with `descriptor`.
+    /// Transactions that are already known need to be scanned again (i.e. with
+    /// [`IndexedTxGraph::reindex`]) to index their outputs under the new descriptor.
+    ///
+    /// The returned [`ChangeSet`] contains the revealed index of `keychain` under `descriptor`
+    /// (and is empty if `descriptor` is the current descriptor). It is to be persisted alongside
+    /// `descriptor`. A `descriptor` without a wildcard only has index 0, so the revealed index
+    /// becomes 0. This is the same index that [`apply_changeset`] results in when reloading with
+    /// `descriptor` and a changeset which recorded a higher index for `keychain`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the `keychain` does not exist.
+    ///
+    /// [`IndexedTxGraph::reindex`]: crate::indexed_tx_graph::IndexedTxGraph::reindex
+    /// [`ChangeSet`]: super::ChangeSet
+    /// [`apply_changeset`]: Self::apply_changeset
+    pub fn replace_descriptor(
+        &mut self,
+        keychain: &K,
+        descriptor: Descriptor<DescriptorPublicKey>,
+        force: bool,
+    ) -> Result<super::ChangeSet<K>, ReplaceDescriptorError> {
+        let old_descriptor = self.keychains.get(keychain).expect("keychain must exist");
+        if old_descriptor == &descriptor {
+            return Ok(super::ChangeSet::default());
+        }
+
+        let mismatched_indices = match self.last_revealed.get(keychain) {
+            Some(&last_revealed) => {
+                let mut new_spks = SpkIterator::new_with_range(&descriptor, 0..=last_revealed);
+                (0..=last_revealed)
+                    .filter(|&index| {
+                        let new_spk = new_spks.next();
+                        debug_assert!(new_spk.as_ref().map_or(true, |(i, _)| *i == index));
+                        new_spk.map(|(_, spk)| spk).as_deref()
+                            != self.inner.spk_at_index(&(keychain.clone(), index))
+                    })
+                    .collect::<Vec<_>>()
+            }
+            None => Vec::new(),
+        };
+        if !mismatched_indices.is_empty() && !force {
+            return Err(ReplaceDescriptorError { mismatched_indices });
+        }
+
+        // only the lookahead needs to be re-derived if the revealed spks are the same
+        let first_removed_index = match self.last_revealed.get(keychain) {
+            Some(&last_revealed) if mismatched_indices.is_empty() => last_revealed + 1,
+            _ => 0,
+        };
+        self.inner.remove_spks_in_range(
+            (keychain.clone(), first_removed_index)..=(keychain.clone(), u32::MAX),
+        );
+        if !descriptor.has_wildcard() {
+            if let Some(last_revealed) = self.last_revealed.get_mut(keychain) {
+                *last_revealed = 0;
+            }
+        }
+        self.keychains.insert(keychain.clone(), descriptor);
+
+        let descriptor = self.keychains.get(keychain).expect("just inserted");
+        let next_store_index = self.next_store_index(keychain);
+        if let Some(&last_revealed) = self.last_revealed.get(keychain) {
+            for (index, spk) in
+                SpkIterator::new_with_range(descriptor, next_store_index..=last_revealed)
+            {
+                let _inserted = self.inner.insert_spk((keychain.clone(), index), spk);
+                debug_assert!(_inserted, "must not have existing spk");
+            }
+        }
+        self.replenish_lookahead(keychain);
+        Ok(super::ChangeSet(
+            self.last_revealed
+                .get(keychain)
+                .map(|&last_revealed| (keychain.clone(), last_revealed))
+                .into_iter()
+                .collect(),
+        ))
+    }
+
     /// Return the lookahead setting for each keychain.
     ///
     /// Refer to [`set_lookahead`] for a deeper explanation of the `lookahead`.
@@ -187,6 +322,16 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         &self.lookahead
     }
 
+    /// Return the lookahead setting of `keychain`.
+    ///
+    /// This is `0` if the lookahead of `keychain` was never set. Refer to [`set_lookahead`] for a
+    /// deeper explanation of the `lookahead`.
+    ///
+    /// [`set_lookahead`]: Self::set_lookahead
+    pub fn lookahead_for(&self, keychain: &K) -> u32 {
+        self.lookahead.get(keychain).copied().unwrap_or(0)
+    }
+
     /// Convenience method to call [`set_lookahead`] for all keychains.
     ///
     /// [`set_lookahead`]: Self::set_lookahead
@@ -222,20 +367,30 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         }
     }
 
-    /// Store lookahead scripts until `target_index`.
+    /// Store lookahead scripts up to and including `target_index`.
+    ///
+    /// The stored scripts are not revealed, but outputs with these scripts will be indexed by
+    /// [`scan`]. This is useful to derive scripts just before a scan which queries up to
+    /// `target_index`, instead of having a large `lookahead` setting. Scripts that are already
+    /// stored are not derived again, so this is cheap if `target_index` is already covered.
     ///
     /// This does not change the `lookahead` setting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `keychain` does not exist.
+    ///
+    /// [`scan`]: Self::scan
     pub fn lookahead_to_target(&mut self, keychain: &K, target_index: u32) {
-        let next_index = self.next_store_index(keychain);
-        if let Some(temp_lookahead) = target_index.checked_sub(next_index).filter(|&v| v > 0) {
-            let old_lookahead = self.lookahead.insert(keychain.clone(), temp_lookahead);
-            self.replenish_lookahead(keychain);
-
-            // revert
-            match old_lookahead {
-                Some(lookahead) => self.lookahead.insert(keychain.clone(), lookahead),
-                None => self.lookahead.remove(keychain),
-            };
+        let descriptor = self.keychains.get(keychain).expect("keychain must exist");
+        let next_store_index = self.next_store_index(keychain);
+        for (new_index, new_spk) in
+            SpkIterator::new_with_range(descriptor, next_store_index..=target_index)
+        {
+            let _inserted = self
+                .inner
+                .insert_spk((keychain.clone(), new_index), new_spk);
+            debug_assert!(_inserted, "must not have existing spk");
         }
     }
 
@@ -243,7 +398,7 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         let descriptor = self.keychains.get(keychain).expect("keychain must exist");
         let next_store_index = self.next_store_index(keychain);
         let next_reveal_index = self.last_revealed.get(keychain).map_or(0, |v| *v + 1);
-        let lookahead = self.lookahead.get(keychain).map_or(0, |v| *v);
+        let lookahead = self.lookahead_for(keychain);
 
         for (new_index, new_spk) in
             SpkIterator::new_with_range(descriptor, next_store_index..next_reveal_index + lookahead)
@@ -294,6 +449,33 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         SpkIterator::new_with_range(descriptor, 0..)
     }
 
+    /// Get the script pubkey of `keychain` at derivation `index`.
+    ///
+    /// The script pubkey is derived on demand if it is not already stored, so `index` does not
+    /// need to be revealed or within the lookahead. Nothing is stored or revealed by this method.
+    ///
+    /// Returns `None` if the `keychain` does not exist or the descriptor cannot be derived at
+    /// `index` (i.e. `index` is above [`BIP32_MAX_INDEX`], or is greater than 0 for a descriptor
+    /// without a wildcard).
+    ///
+    /// The reverse lookup is [`index_of_spk`] (available through [`Deref`]), which returns the
+    /// keychain and derivation index of a script pubkey that is stored by the index (i.e. revealed
+    /// or within the lookahead). This can be used to check that an address belongs to the wallet.
+    ///
+    /// [`index_of_spk`]: SpkTxOutIndex::index_of_spk
+    pub fn spk_at_index(&self, keychain: &K, index: u32) -> Option<ScriptBuf> {
+        let descriptor = self.keychains.get(keychain)?;
+        if let Some(spk) = self.inner.spk_at_index(&(keychain.clone(), index)) {
+            return Some(ScriptBuf::from(spk));
+        }
+        if index > BIP32_MAX_INDEX {
+            return None;
+        }
+        SpkIterator::new_with_range(descriptor, index..=index)
+            .next()
+            .map(|(_, spk)| spk)
+    }
+
     /// Convenience method to get [`revealed_spks_of_keychain`] of all keychains.
     ///
     /// [`revealed_spks_of_keychain`]: Self::revealed_spks_of_keychain
@@ -307,6 +489,9 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
     }
 
     /// Iterates over the script pubkeys revealed by this index under `keychain`.
+    ///
+    /// Script pubkeys are returned in derivation index order, up to and including the last
+    /// revealed index. Script pubkeys only stored as part of the lookahead are not included.
     pub fn revealed_spks_of_keychain(
         &self,
         keychain: &K,
@@ -388,6 +573,39 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         (spks, changeset)
     }
 
+    /// Previews the changes of calling [`reveal_to_target_multi`] with `keychains` without
+    /// mutating `self`.
+    ///
+    /// For each keychain in `keychains`, this returns the current last revealed index and the last
+    /// revealed index after the reveal. An index is `None` if no script pubkeys are (or would be)
+    /// revealed for that keychain. Keychains that have not been added to the index cannot have
+    /// script pubkeys revealed, so the projected index stays the same as the current index.
+    ///
+    /// [`reveal_to_target_multi`]: Self::reveal_to_target_multi
+    pub fn peek_reveal_to_target_multi(
+        &self,
+        keychains: &BTreeMap<K, u32>,
+    ) -> BTreeMap<K, (Option<u32>, Option<u32>)> {
+        keychains
+            .iter()
+            .map(|(keychain, &target_index)| {
+                let current_index = self.last_revealed.get(keychain).cloned();
+                let projected_index = match self.keychains.get(keychain) {
+                    Some(descriptor) => {
+                        let target_index = if descriptor.has_wildcard() {
+                            target_index.min(BIP32_MAX_INDEX)
+                        } else {
+                            0
+                        };
+                        Some(current_index.map_or(target_index, |index| index.max(target_index)))
+                    }
+                    None => current_index,
+                };
+                (keychain.clone(), (current_index, projected_index))
+            })
+            .collect()
+    }
+
     /// Reveals script pubkeys of the `keychain`'s descriptor **up to and including** the
     /// `target_index`.
     ///
@@ -415,11 +633,18 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
 
         let target_index = if has_wildcard { target_index } else { 0 };
         let next_reveal_index = self.last_revealed.get(keychain).map_or(0, |v| *v + 1);
-        let lookahead = self.lookahead.get(keychain).map_or(0, |v| *v);
+        let lookahead = self.lookahead_for(keychain);
 
-        debug_assert_eq!(
-            next_reveal_index + lookahead,
-            self.next_store_index(keychain)
+        // more spks than the lookahead can be stored with `lookahead_to_target`, and descriptors
+        // without wildcards can only ever store the spk at index 0
+        let next_store_index = self.next_store_index(keychain);
+        debug_assert!(
+            next_store_index
+                >= if has_wildcard {
+                    next_reveal_index + lookahead
+                } else {
+                    (next_reveal_index + lookahead).min(1)
+                }
         );
 
         // if we need to reveal new indices, the latest revealed index goes here
@@ -428,12 +653,12 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         // if the target is not yet revealed, but is already stored (due to lookahead), we need to
         // set the `reveal_to_index` as target here (as the `for` loop below only updates
         // `reveal_to_index` for indexes that are NOT stored)
-        if next_reveal_index <= target_index && target_index < next_reveal_index + lookahead {
+        if next_reveal_index <= target_index && target_index < next_store_index {
             reveal_to_index = Some(target_index);
         }
 
         // we range over indexes that are not stored
-        let range = next_reveal_index + lookahead..=target_index + lookahead;
+        let range = next_store_index..=target_index + lookahead;
         for (new_index, new_spk) in SpkIterator::new_with_range(descriptor, range) {
             let _inserted = self
                 .inner
@@ -465,6 +690,33 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         }
     }
 
+    /// Reveals script pubkeys of the `keychain`'s descriptor for indices in `range`.
+    ///
+    /// Revealed indices are always contiguous, so indices below the start of `range` that are not
+    /// yet revealed are also revealed. This returns the newly revealed script pubkeys which are in
+    /// `range` (alongside their indices) and a single [`super::ChangeSet`] for the whole range.
+    /// Like [`reveal_to_target`], this makes a best-effort if the end of `range` cannot be reached.
+    ///
+    /// If the last revealed index is already at or beyond the end of `range`, nothing is revealed
+    /// and both of the returned values are empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keychain` does not exist.
+    ///
+    /// [`reveal_to_target`]: Self::reveal_to_target
+    pub fn reveal_range(
+        &mut self,
+        keychain: &K,
+        range: RangeInclusive<u32>,
+    ) -> (Vec<(u32, ScriptBuf)>, super::ChangeSet<K>) {
+        let (spks, changeset) = self.reveal_to_target(keychain, *range.end());
+        let spks = spks
+            .filter(|(index, _)| range.contains(index))
+            .collect::<Vec<_>>();
+        (spks, changeset)
+    }
+
     /// Attempts to reveal the next script pubkey for `keychain`.
     ///
     /// Returns the derivation index of the revealed script pubkey, the revealed script pubkey and a
@@ -502,20 +754,31 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
     ///
     /// Panics if `keychain` has never been added to the index
     pub fn next_unused_spk(&mut self, keychain: &K) -> ((u32, &Script), super::ChangeSet<K>) {
-        let need_new = self.unused_spks_of_keychain(keychain).next().is_none();
+        let need_new = self.peek_next_unused_spk(keychain).is_none();
         // this rather strange branch is needed because of some lifetime issues
         if need_new {
             self.reveal_next_spk(keychain)
         } else {
             (
-                self.unused_spks_of_keychain(keychain)
-                    .next()
+                self.peek_next_unused_spk(keychain)
                     .expect("we already know next exists"),
                 super::ChangeSet::default(),
             )
         }
     }
 
+    /// Gets the revealed script pubkey of `keychain` with the lowest index that has not been used
+    /// yet.
+    ///
+    /// Unlike [`next_unused_spk`], this never reveals a new script pubkey. Script pubkeys which are
+    /// only stored as part of the lookahead are not considered. `None` is returned if every
+    /// revealed script pubkey of `keychain` is used (or if none are revealed).
+    ///
+    /// [`next_unused_spk`]: Self::next_unused_spk
+    pub fn peek_next_unused_spk(&self, keychain: &K) -> Option<(u32, &Script)> {
+        self.unused_spks_of_keychain(keychain).next()
+    }
+
     /// Marks the script pubkey at `index` as used even though the tracker hasn't seen an output with it.
     /// This only has an effect when the `index` had been added to `self` already and was unused.
     ///
@@ -553,6 +816,20 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
             .map(|((_, i), script)| (*i, script))
     }
 
+    /// Get the number of revealed script pubkeys of `keychain` which are unused.
+    ///
+    /// This is the number of script pubkeys returned by [`unused_spks_of_keychain`]. Script pubkeys
+    /// within the lookahead are not counted, neither are script pubkeys marked as used with
+    /// [`mark_used`]. This can be used to reveal more script pubkeys (i.e. with
+    /// [`reveal_next_spk`]) when the pool of unused script pubkeys runs low.
+    ///
+    /// [`unused_spks_of_keychain`]: Self::unused_spks_of_keychain
+    /// [`mark_used`]: Self::mark_used
+    /// [`reveal_next_spk`]: Self::reveal_next_spk
+    pub fn unused_count(&self, keychain: &K) -> usize {
+        self.unused_spks_of_keychain(keychain).count()
+    }
+
     /// Iterates over all the [`OutPoint`] that have a `TxOut` with a script pubkey derived from
     /// `keychain`.
     pub fn txouts_of_keychain(
@@ -570,6 +847,23 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
         self.txouts_of_keychain(keychain).last().map(|(i, _)| i)
     }
 
+    /// Iterates over the derivation indices of the `keychain` where [`KeychainTxOutIndex`] has
+    /// found a [`TxOut`] with it's script pubkey, in ascending order.
+    ///
+    /// Indices which are only marked as used with [`mark_used`] are not included.
+    ///
+    /// [`mark_used`]: Self::mark_used
+    pub fn used_indices(&self, keychain: &K) -> impl Iterator<Item = u32> + '_ {
+        let mut last_index = None;
+        self.txouts_of_keychain(keychain)
+            .map(|(i, _)| i)
+            .filter(move |&i| {
+                let is_new = last_index != Some(i);
+                last_index = Some(i);
+                is_new
+            })
+    }
+
     /// Returns the highest derivation index of each keychain that [`KeychainTxOutIndex`] has found
     /// a [`TxOut`] with it's script pubkey.
     pub fn last_used_indices(&self) -> BTreeMap<K, u32> {
@@ -582,9 +876,140 @@ impl<K: Clone + Ord + Debug> KeychainTxOutIndex<K> {
             .collect()
     }
 
+    /// Get the [`Balance`] of each keychain from the outputs indexed by [`KeychainTxOutIndex`]
+    /// that are in `graph` and `chain` of `chain_tip`.
+    ///
+    /// Every keychain of [`KeychainTxOutIndex`] is included in the returned map. Outputs with a
+    /// script pubkey that is not derived from a keychain are not counted.
+    ///
+    /// The output of `trust_predicate` should return `true` for scripts that we trust. Refer to
+    /// [`TxGraph::try_balance`] for more.
+    ///
+    /// # Error
+    ///
+    /// An error will occur only if the [`ChainOracle`] implementation (`chain`) fails.
+    pub fn try_balance_per_keychain<A: Anchor, C: ChainOracle>(
+        &self,
+        graph: &TxGraph<A>,
+        chain: &C,
+        chain_tip: BlockId,
+        mut trust_predicate: impl FnMut(&K, &Script) -> bool,
+    ) -> Result<BTreeMap<K, Balance>, C::Error> {
+        self.keychains
+            .keys()
+            .map(|keychain| {
+                let balance = graph.try_balance(
+                    chain,
+                    chain_tip,
+                    self.txouts_of_keychain(keychain).map(|(_, op)| ((), op)),
+                    |_, spk| trust_predicate(keychain, spk),
+                )?;
+                Ok((keychain.clone(), balance))
+            })
+            .collect()
+    }
+
+    /// Get the [`Balance`] of each keychain.
+    ///
+    /// This is the infallible version of [`try_balance_per_keychain`].
+    ///
+    /// [`try_balance_per_keychain`]: Self::try_balance_per_keychain
+    pub fn balance_per_keychain<A: Anchor, C: ChainOracle<Error = Infallible>>(
+        &self,
+        graph: &TxGraph<A>,
+        chain: &C,
+        chain_tip: BlockId,
+        trust_predicate: impl FnMut(&K, &Script) -> bool,
+    ) -> BTreeMap<K, Balance> {
+        self.try_balance_per_keychain(graph, chain, chain_tip, trust_predicate)
+            .expect("oracle is infallible")
+    }
+
+    /// Group the unspent outpoints of `graph` by the derivation index of the script pubkey they pay
+    /// to.
+    ///
+    /// Only outpoints that are indexed by `self` (and are unspent in `chain` of `chain_tip`) are
+    /// included, so outputs paying to script pubkeys that are not derived by any keychain of
+    /// `self` are omitted. Script pubkeys with no unspent outpoints are not included in the
+    /// returned map. The outpoints of each script pubkey are in [`OutPoint`] order.
+    ///
+    /// This is useful for coin control, where UTXOs are grouped by their receiving address.
+    ///
+    /// # Error
+    ///
+    /// An error will occur only if the [`ChainOracle`] implementation (`chain`) fails.
+    pub fn try_group_unspents_by_spk<A: Anchor, C: ChainOracle>(
+        &self,
+        graph: &TxGraph<A>,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Result<UnspentsBySpk<K>, C::Error> {
+        let mut groups = UnspentsBySpk::<K>::new();
+        for res in
+            graph.try_filter_chain_unspents(chain, chain_tip, self.outpoints().iter().cloned())
+        {
+            let (index, txout) = res?;
+            groups.entry(index).or_default().push(txout.outpoint);
+        }
+        Ok(groups)
+    }
+
+    /// Group the unspent outpoints of `graph` by the derivation index of the script pubkey they pay
+    /// to.
+    ///
+    /// This is the infallible version of [`try_group_unspents_by_spk`].
+    ///
+    /// [`try_group_unspents_by_spk`]: Self::try_group_unspents_by_spk
+    pub fn group_unspents_by_spk<A: Anchor, C: ChainOracle<Error = Infallible>>(
+        &self,
+        graph: &TxGraph<A>,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> UnspentsBySpk<K> {
+        self.try_group_unspents_by_spk(graph, chain, chain_tip)
+            .expect("oracle is infallible")
+    }
+
     /// Applies the derivation changeset to the [`KeychainTxOutIndex`], extending the number of
     /// derived scripts per keychain, as specified in the `changeset`.
+    ///
+    /// Applying a changeset is idempotent and revealed indices never decrease. Applying the same
+    /// changeset twice results in the same state as applying it once, and applying multiple
+    /// changesets in any order results in the same state as applying their [`Append`]ed
+    /// changeset. This includes the lookahead script pubkeys that are stored.
+    ///
+    /// [`Append`]: crate::Append
     pub fn apply_changeset(&mut self, changeset: super::ChangeSet<K>) {
+        #[cfg(debug_assertions)]
+        let last_revealed_before = self.last_revealed.clone();
+
         let _ = self.reveal_to_target_multi(&changeset.0);
+
+        #[cfg(debug_assertions)]
+        for (keychain, index) in last_revealed_before {
+            debug_assert!(
+                self.last_revealed.get(&keychain) >= Some(&index),
+                "revealed index of keychain {:?} must not decrease",
+                keychain
+            );
+        }
+    }
+
+    /// Same as [`apply_changeset`], but returns the script pubkeys that were newly revealed by
+    /// applying the `changeset` (alongside their derivation indices).
+    ///
+    /// Keychains with no newly revealed script pubkeys (i.e. they were already revealed up to the
+    /// index in the `changeset`) are not included in the returned map.
+    ///
+    /// [`apply_changeset`]: Self::apply_changeset
+    pub fn apply_changeset_and_reveal(
+        &mut self,
+        changeset: super::ChangeSet<K>,
+    ) -> BTreeMap<K, Vec<(u32, ScriptBuf)>> {
+        self.reveal_to_target_multi(&changeset.0)
+            .0
+            .into_iter()
+            .map(|(keychain, spks)| (keychain, spks.collect()))
+            .collect()
     }
 }
@@ -60,6 +60,9 @@ extern crate alloc;
 #[cfg(feature = "serde")]
 pub extern crate serde_crate as serde;
 
+#[cfg(any(feature = "serde_json", test))]
+extern crate serde_json_crate as serde_json;
+
 #[cfg(feature = "bincode")]
 extern crate bincode;
 
@@ -5,7 +5,8 @@ use core::convert::Infallible;
 use crate::collections::BTreeMap;
 use crate::{BlockId, ChainOracle};
 use alloc::sync::Arc;
-use bitcoin::BlockHash;
+use alloc::vec::Vec;
+use bitcoin::{block::Header, BlockHash};
 
 /// A structure that represents changes to [`LocalChain`].
 ///
@@ -13,6 +14,36 @@ use bitcoin::BlockHash;
 /// (if [`Some`]), or removing a [`CheckPoint`] (if [`None`]).
 pub type ChangeSet = BTreeMap<u32, Option<BlockHash>>;
 
+/// Get a value which displays a summary of the `changeset`, for diagnostics.
+///
+/// The summary lists the heights of added (or replaced) blocks and the heights of removed blocks,
+/// e.g. `added heights [1, 2], removed heights [3]`. Use the [`Debug`](core::fmt::Debug)
+/// representation of the `changeset` to also see the block hashes.
+pub fn display_changeset(changeset: &ChangeSet) -> ChangeSetDisplay<'_> {
+    ChangeSetDisplay(changeset)
+}
+
+/// Displays a summary of a [`ChangeSet`].
+///
+/// This is returned by [`display_changeset`].
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeSetDisplay<'a>(&'a ChangeSet);
+
+impl<'a> core::fmt::Display for ChangeSetDisplay<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let heights = |added: bool| {
+            self.0
+                .iter()
+                .filter(move |(_, hash)| hash.is_some() == added)
+                .map(|(height, _)| height)
+        };
+        f.write_str("added heights ")?;
+        f.debug_list().entries(heights(true)).finish()?;
+        f.write_str(", removed heights ")?;
+        f.debug_list().entries(heights(false)).finish()
+    }
+}
+
 /// A [`LocalChain`] checkpoint is used to find the agreement point between two chains and as a
 /// transaction anchor.
 ///
@@ -66,6 +97,47 @@ impl CheckPoint {
         Ok(curr)
     }
 
+    /// Inserts `block` into the checkpoint linked list and returns the resulting tip.
+    ///
+    /// * If a checkpoint of the same height and hash exists, this is a no-op and `self` is
+    ///   returned.
+    /// * If a checkpoint of the same height but a different hash exists, it is replaced by `block`
+    ///   and all checkpoints above it are dropped (this is treated as a reorg). `block` becomes the
+    ///   tip.
+    /// * If `block` is higher than the tip, the list is extended and `block` becomes the tip.
+    /// * Otherwise, `block` is inserted between the checkpoints below and above it, which are kept.
+    pub fn insert(self, block: BlockId) -> Self {
+        let mut tail = Vec::<BlockId>::new();
+        let mut base = None;
+        for cp in self.iter() {
+            if cp.height() > block.height {
+                tail.push(cp.block_id());
+            } else {
+                base = Some(cp);
+                break;
+            }
+        }
+
+        let base = match base {
+            Some(base) if base.height() == block.height => {
+                if base.hash() == block.hash {
+                    return self;
+                }
+                // a conflicting block invalidates all blocks above it
+                tail.clear();
+                base.prev()
+            }
+            base => base,
+        };
+
+        let cp = match base {
+            Some(base) => base.push(block).expect("block must be higher than base"),
+            None => CheckPoint::new(block),
+        };
+        cp.extend(tail.into_iter().rev())
+            .expect("tail must be higher than block")
+    }
+
     /// Get the [`BlockId`] of the checkpoint.
     pub fn block_id(&self) -> BlockId {
         self.0.block
@@ -107,6 +179,34 @@ impl Iterator for CheckPointIter {
     }
 }
 
+/// A [`CheckPoint`] is serialized as the list of its [`BlockId`]s in ascending height order.
+#[cfg(feature = "serde")]
+impl serde::Serialize for CheckPoint {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut blocks = self
+            .iter()
+            .map(|cp| cp.block_id())
+            .collect::<alloc::vec::Vec<_>>();
+        blocks.reverse();
+        blocks.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CheckPoint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let mut blocks = alloc::vec::Vec::<BlockId>::deserialize(deserializer)?.into_iter();
+        let base = blocks
+            .next()
+            .ok_or_else(|| D::Error::custom("checkpoint must contain at least one block"))?;
+        CheckPoint::new(base)
+            .extend(blocks)
+            .map_err(|_| D::Error::custom("checkpoint blocks must be in ascending height order"))
+    }
+}
+
 impl IntoIterator for CheckPoint {
     type Item = CheckPoint;
     type IntoIter = CheckPointIter;
@@ -131,6 +231,11 @@ impl IntoIterator for CheckPoint {
 /// so some updates require introducing older blocks (to anchor older transactions). For
 /// script-pubkey based syncing, `introduce_older_blocks` would typically be `true`.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(crate = "serde_crate")
+)]
 pub struct Update {
     /// The update chain's new tip.
     pub tip: CheckPoint,
@@ -143,6 +248,50 @@ pub struct Update {
     pub introduce_older_blocks: bool,
 }
 
+impl Update {
+    /// Construct an [`Update`] from a sparse set of `(height, hash)` pairs.
+    ///
+    /// The `tip` of the update is the block of the greatest height. `blocks` do not need to be
+    /// sorted and duplicate pairs are ignored. As the blocks may be sparse,
+    /// `introduce_older_blocks` is set to `true`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UpdateFromBlocksError::ConflictingBlocks`] if `blocks` contains different hashes
+    /// for the same height, and [`UpdateFromBlocksError::Empty`] if `blocks` is empty.
+    pub fn from_blocks(
+        blocks: impl IntoIterator<Item = (u32, BlockHash)>,
+    ) -> Result<Self, UpdateFromBlocksError> {
+        let mut block_map = BTreeMap::<u32, BlockHash>::new();
+        for (height, hash) in blocks {
+            match block_map.get(&height) {
+                Some(&first_hash) if first_hash != hash => {
+                    return Err(UpdateFromBlocksError::ConflictingBlocks {
+                        height,
+                        first_hash,
+                        second_hash: hash,
+                    })
+                }
+                _ => {
+                    block_map.insert(height, hash);
+                }
+            }
+        }
+
+        let mut blocks = block_map
+            .into_iter()
+            .map(|(height, hash)| BlockId { height, hash });
+        let first = blocks.next().ok_or(UpdateFromBlocksError::Empty)?;
+        let tip = CheckPoint::new(first)
+            .extend(blocks)
+            .expect("blocks are sorted by height and have no duplicate heights");
+        Ok(Self {
+            tip,
+            introduce_older_blocks: true,
+        })
+    }
+}
+
 /// This is a local implementation of [`ChainOracle`].
 #[derive(Debug, Default, Clone)]
 pub struct LocalChain {
@@ -218,6 +367,41 @@ impl LocalChain {
         chain
     }
 
+    /// Construct a [`LocalChain`] from a given `checkpoint` tip, checking that the chain of
+    /// checkpoints reaches the genesis block (height 0).
+    ///
+    /// This is useful when the checkpoints are assembled from a stream of headers. Use
+    /// [`from_tip`] if the chain is not required to contain the genesis block.
+    ///
+    /// [`from_tip`]: Self::from_tip
+    pub fn try_from_tip(tip: CheckPoint) -> Result<Self, MissingGenesisError> {
+        if tip.iter().last().map(|cp| cp.height()) != Some(0) {
+            return Err(MissingGenesisError);
+        }
+        Ok(Self::from_tip(tip))
+    }
+
+    /// Construct a [`LocalChain`] with the genesis block and an `assume_valid` block which is
+    /// trusted to be in the best chain.
+    ///
+    /// This mirrors Bitcoin Core's `assumevalid` and speeds up the initial sync of light clients.
+    /// The blocks between the genesis block and `assume_valid` are absent, so updates only need to
+    /// connect to `assume_valid` (or a block above it). Reorgs above `assume_valid` are handled as
+    /// usual, and blocks below `assume_valid` can still be introduced later.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `assume_valid` is at height 0 but is not the genesis block.
+    pub fn new_with_assume_valid(genesis_hash: BlockHash, assume_valid: BlockId) -> Self {
+        if assume_valid.height == 0 {
+            assert_eq!(
+                assume_valid.hash, genesis_hash,
+                "assume-valid block at height 0 must be the genesis block"
+            );
+        }
+        Self::from_blocks([(0, genesis_hash), (assume_valid.height, assume_valid.hash)].into())
+    }
+
     /// Constructs a [`LocalChain`] from a [`BTreeMap`] of height to [`BlockHash`].
     ///
     /// The [`BTreeMap`] enforces the height order. However, the caller must ensure the blocks are
@@ -277,6 +461,20 @@ impl LocalChain {
     ///
     /// [module-level documentation]: crate::local_chain
     pub fn apply_update(&mut self, update: Update) -> Result<ChangeSet, CannotConnectError> {
+        self.merge_update(update).map(|(changeset, _)| changeset)
+    }
+
+    /// Applies the given `update` to the chain, and reports the depth of the reorg (if any).
+    ///
+    /// This is the same as [`apply_update`], but also returns the height of the lowest block of
+    /// `self` that was invalidated by the update. [`None`] is returned if the update did not
+    /// invalidate any blocks (i.e. it only introduced new blocks).
+    ///
+    /// [`apply_update`]: Self::apply_update
+    pub fn merge_update(
+        &mut self,
+        update: Update,
+    ) -> Result<(ChangeSet, Option<u32>), CannotConnectError> {
         match self.tip() {
             Some(original_tip) => {
                 let changeset = merge_chains(
@@ -284,10 +482,17 @@ impl LocalChain {
                     update.tip.clone(),
                     update.introduce_older_blocks,
                 )?;
+                let invalidated_height = changeset
+                    .iter()
+                    .find(|(height, hash)| match self.index.get(height) {
+                        Some(original_hash) => Some(*original_hash) != **hash,
+                        None => false,
+                    })
+                    .map(|(&height, _)| height);
                 self.apply_changeset(&changeset);
 
                 // return early as `apply_changeset` already calls `check_consistency`
-                Ok(changeset)
+                Ok((changeset, invalidated_height))
             }
             None => {
                 *self = Self::from_tip(update.tip);
@@ -295,7 +500,7 @@ impl LocalChain {
 
                 debug_assert!(self._check_index_is_consistent_with_tip());
                 debug_assert!(self._check_changeset_is_applied(&changeset));
-                Ok(changeset)
+                Ok((changeset, None))
             }
         }
     }
@@ -363,6 +568,59 @@ impl LocalChain {
         Ok(changeset)
     }
 
+    /// Insert a block of the given `height` and `header`.
+    ///
+    /// If there is a checkpoint at `height - 1`, the `prev_blockhash` of `header` must match its
+    /// hash.
+    ///
+    /// # Errors
+    ///
+    /// If the `prev_blockhash` of `header` does not match the checkpoint at `height - 1`, or there
+    /// is already a checkpoint at `height` with a different hash, the block is not inserted and an
+    /// error is returned. Both indicate that a reorg has happened, and the caller may want to call
+    /// [`disconnect_from`] before trying again.
+    ///
+    /// [`disconnect_from`]: Self::disconnect_from
+    pub fn apply_header(
+        &mut self,
+        height: u32,
+        header: &Header,
+    ) -> Result<ChangeSet, ApplyHeaderError> {
+        if let Some(prev_height) = height.checked_sub(1) {
+            if let Some(&prev_hash) = self.index.get(&prev_height) {
+                if prev_hash != header.prev_blockhash {
+                    return Err(ApplyHeaderError::PrevBlockHashMismatch {
+                        height,
+                        original_prev_hash: prev_hash,
+                        header_prev_hash: header.prev_blockhash,
+                    });
+                }
+            }
+        }
+        self.insert_block(BlockId {
+            height,
+            hash: header.block_hash(),
+        })
+        .map_err(ApplyHeaderError::InsertBlock)
+    }
+
+    /// Removes all checkpoints at and above `height`.
+    ///
+    /// This is useful when we discover that the block at `height` is no longer in the best chain.
+    /// The genesis checkpoint (at height 0) is never removed, so `height` of 0 only removes the
+    /// checkpoints above genesis.
+    ///
+    /// The returned [`ChangeSet`] describes the removed checkpoints.
+    pub fn disconnect_from(&mut self, height: u32) -> ChangeSet {
+        let changeset = self
+            .index
+            .range(height.max(1)..)
+            .map(|(&height, _)| (height, None))
+            .collect::<ChangeSet>();
+        self.apply_changeset(&changeset);
+        changeset
+    }
+
     /// Reindex the heights in the chain from (and including) `from` height
     fn reindex(&mut self, from: u32) {
         let _ = self.index.split_off(&from);
@@ -387,11 +645,42 @@ impl LocalChain {
         }
     }
 
+    /// Get the checkpoint at exactly `height`, if `height` is tracked by the chain.
+    ///
+    /// This walks the checkpoints from the tip, so it is `O(n)` in the number of checkpoints above
+    /// `height`. `None` is returned if there is no checkpoint at `height` (the nearest checkpoint is
+    /// never returned).
+    pub fn checkpoint_at(&self, height: u32) -> Option<CheckPoint> {
+        if !self.index.contains_key(&height) {
+            return None;
+        }
+        self.iter_checkpoints()
+            .take_while(|cp| cp.height() >= height)
+            .find(|cp| cp.height() == height)
+    }
+
     /// Get a reference to the internal index mapping the height to block hash.
     pub fn blocks(&self) -> &BTreeMap<u32, BlockHash> {
         &self.index
     }
 
+    /// Returns whether `block` is contained in the chain.
+    ///
+    /// This returns `Some(true)` if there is a checkpoint at `block`'s height with the same hash,
+    /// and `Some(false)` if the checkpoint at that height has a different hash. `None` is returned
+    /// when there is no checkpoint at `block`'s height (i.e. it is above the tip or we do not
+    /// track that height), meaning we cannot tell whether `block` is in the chain.
+    pub fn contains_block(&self, block: BlockId) -> Option<bool> {
+        self.index
+            .get(&block.height)
+            .map(|&hash| hash == block.hash)
+    }
+
+    /// Iterate over the heights of all checkpoints in descending height order.
+    pub fn heights(&self) -> impl DoubleEndedIterator<Item = u32> + '_ {
+        self.index.keys().rev().copied()
+    }
+
     fn _check_index_is_consistent_with_tip(&self) -> bool {
         let tip_history = self
             .tip
@@ -436,6 +725,93 @@ impl core::fmt::Display for InsertBlockError {
 #[cfg(feature = "std")]
 impl std::error::Error for InsertBlockError {}
 
+/// Represents a failure when trying to apply a block header to [`LocalChain`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApplyHeaderError {
+    /// The header's `prev_blockhash` conflicts with the checkpoint below it.
+    PrevBlockHashMismatch {
+        /// The height of the header.
+        height: u32,
+        /// Original checkpoint's block hash at `height - 1`.
+        original_prev_hash: BlockHash,
+        /// The header's `prev_blockhash`.
+        header_prev_hash: BlockHash,
+    },
+    /// The header's block hash conflicts with the checkpoint at the same height.
+    InsertBlock(InsertBlockError),
+}
+
+impl core::fmt::Display for ApplyHeaderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ApplyHeaderError::PrevBlockHashMismatch {
+                height,
+                original_prev_hash,
+                header_prev_hash,
+            } => write!(
+                f,
+                "failed to apply header at height {} as previous blockhashes conflict: original={}, header={}",
+                height, original_prev_hash, header_prev_hash
+            ),
+            ApplyHeaderError::InsertBlock(err) => err.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ApplyHeaderError {}
+
+/// Represents a failure when trying to construct an [`Update`] with [`Update::from_blocks`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum UpdateFromBlocksError {
+    /// No blocks were provided, so the update has no tip.
+    Empty,
+    /// Two different block hashes were provided for the same height.
+    ConflictingBlocks {
+        /// The height of the conflicting blocks.
+        height: u32,
+        /// The block hash which was provided first.
+        first_hash: BlockHash,
+        /// The conflicting block hash which was provided later.
+        second_hash: BlockHash,
+    },
+}
+
+impl core::fmt::Display for UpdateFromBlocksError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            UpdateFromBlocksError::Empty => write!(f, "cannot create an update without blocks"),
+            UpdateFromBlocksError::ConflictingBlocks {
+                height,
+                first_hash,
+                second_hash,
+            } => write!(
+                f,
+                "conflicting blockhashes at height {}: first={}, second={}",
+                height, first_hash, second_hash
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UpdateFromBlocksError {}
+
+/// Occurs when a chain of checkpoints does not contain the genesis block (height 0).
+///
+/// This is returned by [`LocalChain::try_from_tip`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingGenesisError;
+
+impl core::fmt::Display for MissingGenesisError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "chain of checkpoints does not contain a genesis block")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MissingGenesisError {}
+
 /// Occurs when an update does not have a common checkpoint with the original chain.
 #[derive(Clone, Debug, PartialEq)]
 pub struct CannotConnectError {
@@ -55,8 +55,136 @@ where
             // if written successfully, take and return `self.stage`
             .map(|_| Some(core::mem::take(&mut self.stage)))
     }
+
+    /// Stage a `changeset` and [`commit`] all staged changes.
+    ///
+    /// Nothing is written to the backend if there are no staged changes. Returns whether anything
+    /// was written.
+    ///
+    /// # Error
+    ///
+    /// Returns a backend-defined error if this fails.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn stage_and_commit(&mut self, changeset: C) -> Result<bool, B::WriteError> {
+        self.stage(changeset);
+        self.commit().map(|committed| committed.is_some())
+    }
 }
 
+#[cfg(feature = "async")]
+impl<B, C> Persist<B, C>
+where
+    B: PersistBackend<C> + Clone + Send + 'static,
+    B::WriteError: Send + 'static,
+    C: Default + Append + Clone + Send + Sync + 'static,
+{
+    /// Commit the staged changes of the shared `persist` to the underlying persistance backend
+    /// without blocking the async executor.
+    ///
+    /// This is the async version of [`commit`]. The staged changes are taken out of `persist` and
+    /// written on tokio's blocking thread pool, so `persist` is only locked briefly and more changes
+    /// can be staged while the write is in progress. Those changes go to the next commit.
+    ///
+    /// The write is done with a clone of the backend, so `B` is expected to be a cheap handle to
+    /// shared storage (such as `Arc<Mutex<_>>`) as the backend is cloned for every commit. The
+    /// staged changes are not cloned, unless the returned future is dropped before the write
+    /// completes. In that case (and if the write fails), the taken changes are staged again in
+    /// front of the changes staged since.
+    ///
+    /// Changes that are committed (if any) are returned.
+    ///
+    /// # Error
+    ///
+    /// Returns [`CommitAsyncError::Write`] with the backend-defined error if the write fails, or
+    /// [`CommitAsyncError::Cancelled`] if the write task is cancelled (e.g. as the runtime shuts
+    /// down). In both cases, the changes remain staged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called within a tokio runtime, or if the mutex of `persist` is poisoned. If
+    /// the backend panics while writing, the panic is resumed on the calling task.
+    ///
+    /// [`commit`]: Self::commit
+    pub async fn commit_async(
+        persist: &std::sync::Mutex<Self>,
+    ) -> Result<Option<C>, CommitAsyncError<B::WriteError>> {
+        let (mut backend, changeset) = {
+            let mut persist = persist.lock().expect("persist mutex must not be poisoned");
+            if persist.stage.is_empty() {
+                return Ok(None);
+            }
+            let changeset = std::sync::Arc::new(core::mem::take(&mut persist.stage));
+            (persist.backend.clone(), changeset)
+        };
+        // stages the changes again if the write does not succeed
+        let mut pending = PendingCommit {
+            persist,
+            changeset: Some(changeset.clone()),
+        };
+        let written =
+            match tokio::task::spawn_blocking(move || backend.write_changes(&changeset)).await {
+                Ok(written) => written,
+                Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+                Err(_) => return Err(CommitAsyncError::Cancelled),
+            };
+        written.map_err(CommitAsyncError::Write)?;
+        let changeset = pending
+            .changeset
+            .take()
+            .expect("changeset is only taken once written");
+        Ok(Some(
+            std::sync::Arc::try_unwrap(changeset).unwrap_or_else(|changeset| (*changeset).clone()),
+        ))
+    }
+}
+
+/// The changes taken out of [`Persist`] by [`Persist::commit_async`], which are staged again if
+/// dropped before they are written.
+#[cfg(feature = "async")]
+struct PendingCommit<'p, B, C: Default + Append + Clone> {
+    persist: &'p std::sync::Mutex<Persist<B, C>>,
+    changeset: Option<std::sync::Arc<C>>,
+}
+
+#[cfg(feature = "async")]
+impl<'p, B, C: Default + Append + Clone> Drop for PendingCommit<'p, B, C> {
+    fn drop(&mut self) {
+        if let Some(changeset) = self.changeset.take() {
+            let mut changeset = std::sync::Arc::try_unwrap(changeset)
+                .unwrap_or_else(|changeset| (*changeset).clone());
+            if let Ok(mut persist) = self.persist.lock() {
+                // the changes staged since go after the taken changes
+                changeset.append(core::mem::take(&mut persist.stage));
+                persist.stage = changeset;
+            }
+        }
+    }
+}
+
+/// An error returned by [`Persist::commit_async`].
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitAsyncError<E> {
+    /// The backend failed to write the changes.
+    Write(E),
+    /// The task writing the changes was cancelled before it completed.
+    Cancelled,
+}
+
+#[cfg(feature = "async")]
+impl<E: core::fmt::Display> core::fmt::Display for CommitAsyncError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CommitAsyncError::Write(error) => write!(f, "failed to write changes: {}", error),
+            CommitAsyncError::Cancelled => write!(f, "the task writing changes was cancelled"),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<E: core::fmt::Debug + core::fmt::Display> std::error::Error for CommitAsyncError<E> {}
+
 /// A persistence backend for [`Persist`].
 ///
 /// `C` represents the changeset; a datatype that records changes made to in-memory data structures
@@ -82,6 +210,25 @@ pub trait PersistBackend<C> {
     fn load_from_persistence(&mut self) -> Result<C, Self::LoadError>;
 }
 
+#[cfg(feature = "std")]
+impl<C, B: PersistBackend<C>> PersistBackend<C> for std::sync::Arc<std::sync::Mutex<B>> {
+    type WriteError = B::WriteError;
+
+    type LoadError = B::LoadError;
+
+    fn write_changes(&mut self, changeset: &C) -> Result<(), Self::WriteError> {
+        self.lock()
+            .expect("backend mutex must not be poisoned")
+            .write_changes(changeset)
+    }
+
+    fn load_from_persistence(&mut self) -> Result<C, Self::LoadError> {
+        self.lock()
+            .expect("backend mutex must not be poisoned")
+            .load_from_persistence()
+    }
+}
+
 impl<C: Default> PersistBackend<C> for () {
     type WriteError = Infallible;
 
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use core::ops::RangeBounds;
 
 use crate::{
@@ -18,14 +19,16 @@ use bitcoin::{self, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid};
 /// combination of `(keychain, derivation_index)`.
 ///
 /// Note there is no harm in scanning transactions that disappear from the blockchain or were never
-/// in there in the first place. `SpkTxOutIndex` is intentionally *monotone* -- you cannot delete or
-/// modify txouts that have been indexed. To find out which txouts from the index are actually in the
-/// chain or unspent, you must use other sources of information like a [`TxGraph`].
+/// in there in the first place. `SpkTxOutIndex` is intentionally *monotone* -- you cannot modify
+/// txouts that have been indexed, and they are only deleted alongside their transaction (with
+/// [`remove_txouts_of`]). To find out which txouts from the index are actually in the chain or
+/// unspent, you must use other sources of information like a [`TxGraph`].
 ///
 /// [`TxOut`]: bitcoin::TxOut
 /// [`insert_spk`]: Self::insert_spk
 /// [`Ord`]: core::cmp::Ord
 /// [`scan`]: Self::scan
+/// [`remove_txouts_of`]: Self::remove_txouts_of
 /// [`TxGraph`]: crate::tx_graph::TxGraph
 #[derive(Clone, Debug)]
 pub struct SpkTxOutIndex<I> {
@@ -75,6 +78,10 @@ impl<I: Clone + Ord> Indexer for SpkTxOutIndex<I> {
     fn is_tx_relevant(&self, tx: &Transaction) -> bool {
         self.is_relevant(tx)
     }
+
+    fn unindex_tx(&mut self, txid: Txid) {
+        self.remove_txouts_of(txid);
+    }
 }
 
 /// This macro is used instead of a member function of `SpkTxOutIndex`, which would result in a
@@ -122,6 +129,22 @@ impl<I: Clone + Ord> SpkTxOutIndex<I> {
         scan_txout!(self, op, txout)
     }
 
+    /// Remove the indexed txouts of the transaction of `txid` (e.g. as the transaction is pruned
+    /// from the graph).
+    ///
+    /// A script pubkey stays used even when it no longer has any indexed txouts, so that it is
+    /// never handed out again as an unused script pubkey.
+    pub fn remove_txouts_of(&mut self, txid: Txid) {
+        let removed = self
+            .txouts_in_tx(txid)
+            .map(|(spk_i, op, _)| (spk_i.clone(), op))
+            .collect::<Vec<_>>();
+        for (spk_i, op) in removed {
+            self.txouts.remove(&op);
+            self.spk_txouts.remove(&(spk_i, op));
+        }
+    }
+
     /// Get a reference to the set of indexed outpoints.
     pub fn outpoints(&self) -> &BTreeSet<(I, OutPoint)> {
         &self.spk_txouts
@@ -177,6 +200,33 @@ impl<I: Clone + Ord> SpkTxOutIndex<I> {
         self.spk_txouts.range((start, end)).map(|(i, op)| (i, *op))
     }
 
+    /// Iterates over all indexed txouts that pay to the script pubkey at `index`.
+    ///
+    /// This includes both spent and unspent txouts, since the index does not know which txouts are
+    /// spent. The txouts are ordered by outpoint.
+    pub fn txouts_of_spk_index(
+        &self,
+        index: &I,
+    ) -> impl DoubleEndedIterator<Item = (OutPoint, &TxOut)> {
+        self.outputs_in_range(index.clone()..=index.clone())
+            .map(move |(_, op)| {
+                let (_, txout) = self.txouts.get(&op).expect("must have txout of outpoint");
+                (op, txout)
+            })
+    }
+
+    /// Returns the total value ever received by the script pubkey at `index`.
+    ///
+    /// This is the sum of all indexed txouts paying to the script pubkey, including those which have
+    /// since been spent. Refer to [`txouts_of_spk_index`] for the txouts which are summed.
+    ///
+    /// [`txouts_of_spk_index`]: Self::txouts_of_spk_index
+    pub fn total_received(&self, index: &I) -> u64 {
+        self.txouts_of_spk_index(index)
+            .map(|(_, txout)| txout.value)
+            .sum()
+    }
+
     /// Returns the txout and script pubkey index of the `TxOut` at `OutPoint`.
     ///
     /// Returns `None` if the `TxOut` hasn't been scanned or if nothing matching was found there.
@@ -213,6 +263,16 @@ impl<I: Clone + Ord> SpkTxOutIndex<I> {
         }
     }
 
+    /// Removes the script pubkeys with an index in `range`, alongside the txouts indexed under
+    /// them.
+    pub(crate) fn remove_spks_in_range(&mut self, range: impl RangeBounds<I>) {
+        self.spks.retain(|index, _| !range.contains(index));
+        self.spk_indices.retain(|_, index| !range.contains(index));
+        self.unused.retain(|index| !range.contains(index));
+        self.txouts.retain(|_, (index, _)| !range.contains(index));
+        self.spk_txouts.retain(|(index, _)| !range.contains(index));
+    }
+
     /// Iterates over all unused script pubkeys in an index range.
     ///
     /// Here, "unused" means that after the script pubkey was stored in the index, the index has
@@ -262,12 +322,13 @@ impl<I: Clone + Ord> SpkTxOutIndex<I> {
     }
 
     /// Undoes the effect of [`mark_used`]. Returns whether the `index` is inserted back into
-    /// `unused`.
+    /// `unused` (i.e. whether it was previously marked as used with [`mark_used`]).
     ///
     /// Note that if `self` has scanned an output with this script pubkey then this will have no
-    /// effect.
+    /// effect, and [`is_used`] will still return `true` for `index`.
     ///
     /// [`mark_used`]: Self::mark_used
+    /// [`is_used`]: Self::is_used
     pub fn unmark_used(&mut self, index: &I) -> bool {
         // we cannot set the index as unused when it does not exist
         if !self.spks.contains_key(index) {
@@ -318,6 +379,36 @@ impl<I: Clone + Ord> SpkTxOutIndex<I> {
         received as i64 - sent as i64
     }
 
+    /// Computes the net value that this transaction gives to the script pubkeys in the index.
+    ///
+    /// Unlike [`net_value`], the outputs being spent are looked up with `get_txout` rather than
+    /// from the txouts already scanned by the index. A spent output only counts towards the sent
+    /// value if its script pubkey is in the index, and outputs of `tx` only count towards the
+    /// received value if their script pubkey is in the index. Inputs for which `get_txout` returns
+    /// `None` are ignored.
+    ///
+    /// [`net_value`]: Self::net_value
+    pub fn net_value_with(
+        &self,
+        tx: &Transaction,
+        get_txout: impl Fn(OutPoint) -> Option<TxOut>,
+    ) -> i64 {
+        let sent = tx
+            .input
+            .iter()
+            .filter_map(|txin| get_txout(txin.previous_output))
+            .filter(|txout| self.index_of_spk(&txout.script_pubkey).is_some())
+            .map(|txout| txout.value)
+            .sum::<u64>();
+        let received = tx
+            .output
+            .iter()
+            .filter(|txout| self.index_of_spk(&txout.script_pubkey).is_some())
+            .map(|txout| txout.value)
+            .sum::<u64>();
+        received as i64 - sent as i64
+    }
+
     /// Whether any of the inputs of this transaction spend a txout tracked or whether any output
     /// matches one of our script pubkeys.
     ///
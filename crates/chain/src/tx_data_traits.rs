@@ -102,17 +102,41 @@ pub trait Anchor: core::fmt::Debug + Clone + Eq + PartialOrd + Ord + core::hash:
 
     /// Get the upper bound of the chain data's confirmation height.
     ///
+    /// The chain data is confirmed at or below the returned height, but it is not necessarily
+    /// the exact confirmation height. Anchors that only know an anchor block (such as [`BlockId`])
+    /// return the anchor block's height, which can be higher than the actual confirmation height.
+    /// Anchors that record the confirmation height (such as [`ConfirmationHeightAnchor`]) return
+    /// the exact height.
+    ///
+    /// Code that needs an exact confirmation height (e.g. to count confirmations) should only treat
+    /// this value as exact for anchor types that record the confirmation height. Otherwise, the
+    /// number of confirmations computed from this value is a lower bound.
+    ///
     /// The default definition gives a pessimistic answer. This can be overridden by the `Anchor`
     /// implementation for a more accurate value.
+    ///
+    /// [`ConfirmationHeightAnchor`]: crate::ConfirmationHeightAnchor
     fn confirmation_height_upper_bound(&self) -> u32 {
         self.anchor_block().height
     }
+
+    /// Get the [`BlockId`] of the block that the chain data is confirmed in (if known).
+    ///
+    /// When this returns `Some`, the confirmation block is checked against the chain in addition
+    /// to the anchor block. The default implementation returns `None`.
+    fn confirmation_block(&self) -> Option<BlockId> {
+        None
+    }
 }
 
 impl<A: Anchor> Anchor for &'static A {
     fn anchor_block(&self) -> BlockId {
         <A as Anchor>::anchor_block(self)
     }
+
+    fn confirmation_block(&self) -> Option<BlockId> {
+        <A as Anchor>::confirmation_block(self)
+    }
 }
 
 /// Trait that makes an object appendable.
@@ -52,13 +52,13 @@
 
 use crate::{
     collections::*, keychain::Balance, local_chain::LocalChain, Anchor, Append, BlockId,
-    ChainOracle, ChainPosition, ForEachTxOut, FullTxOut,
+    ChainOracle, ChainPosition, ForEachTxOut, FullTxOut, COINBASE_MATURITY,
 };
 use alloc::vec::Vec;
-use bitcoin::{OutPoint, Script, Transaction, TxOut, Txid};
+use bitcoin::{Amount, FeeRate, OutPoint, Script, ScriptBuf, Transaction, TxOut, Txid};
 use core::{
     convert::Infallible,
-    ops::{Deref, RangeInclusive},
+    ops::{Deref, RangeBounds, RangeInclusive},
 };
 
 /// A graph of transactions and spends.
@@ -135,6 +135,25 @@ pub struct CanonicalTx<'a, T, A> {
     pub tx_node: TxNode<'a, T, A>,
 }
 
+/// A [`TxGraph`] is serialized as its [`initial_changeset`].
+///
+/// [`initial_changeset`]: TxGraph::initial_changeset
+#[cfg(feature = "serde")]
+impl<A: Clone + Ord + serde::Serialize> serde::Serialize for TxGraph<A> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.initial_changeset().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, A: Clone + Ord + serde::Deserialize<'de>> serde::Deserialize<'de> for TxGraph<A> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut graph = TxGraph::default();
+        graph.apply_changeset(ChangeSet::deserialize(deserializer)?);
+        Ok(graph)
+    }
+}
+
 /// Errors returned by `TxGraph::calculate_fee`.
 #[derive(Debug, PartialEq, Eq)]
 pub enum CalculateFeeError {
@@ -144,6 +163,90 @@ pub enum CalculateFeeError {
     NegativeFee(i64),
 }
 
+impl core::fmt::Display for CalculateFeeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CalculateFeeError::MissingTxOut(outpoints) => write!(
+                f,
+                "missing `TxOut` for one or more of the inputs of the tx: {:?}",
+                outpoints
+            ),
+            CalculateFeeError::NegativeFee(fee) => write!(
+                f,
+                "transaction is invalid according to the graph and has negative fee: {}",
+                fee
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CalculateFeeError {}
+
+/// Error returned by `TxGraph::merge_disjoint` when the two graphs are not disjoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlapError {
+    /// A txid which exists in both graphs.
+    pub txid: Txid,
+}
+
+impl core::fmt::Display for OverlapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "transaction {} exists in both graphs", self.txid)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OverlapError {}
+
+/// Errors returned by `TxGraph::try_insert_txout`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InsertTxOutError {
+    /// The full transaction of the outpoint is known, but it has no output at the outpoint's vout
+    MissingOutput {
+        /// The outpoint that was inserted
+        outpoint: OutPoint,
+        /// The number of outputs of the full transaction
+        output_count: usize,
+    },
+    /// The full transaction of the outpoint is known, but its output differs from the inserted one
+    TxOutMismatch {
+        /// The outpoint that was inserted
+        outpoint: OutPoint,
+        /// The output of the full transaction at the outpoint
+        expected: TxOut,
+        /// The output that was inserted
+        got: TxOut,
+    },
+}
+
+impl core::fmt::Display for InsertTxOutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InsertTxOutError::MissingOutput {
+                outpoint,
+                output_count,
+            } => write!(
+                f,
+                "outpoint {} does not exist as the transaction only has {} outputs",
+                outpoint, output_count
+            ),
+            InsertTxOutError::TxOutMismatch {
+                outpoint,
+                expected,
+                got,
+            } => write!(
+                f,
+                "txout at outpoint {} does not match the transaction: expected {:?}, got {:?}",
+                outpoint, expected, got
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InsertTxOutError {}
+
 impl<A> TxGraph<A> {
     /// Iterate over all tx outputs known by [`TxGraph`].
     ///
@@ -163,6 +266,27 @@ impl<A> TxGraph<A> {
         })
     }
 
+    /// Iterate over all tx outputs known by [`TxGraph`] with a script pubkey in `range`.
+    ///
+    /// The range is over the raw script pubkey bytes (in lexicographic order). Outputs are ordered
+    /// by script pubkey and then by outpoint, so partitioning the script pubkey space into
+    /// disjoint ranges yields deterministic, non-overlapping results.
+    ///
+    /// This does not use an index of script pubkeys, so all txouts of the graph are scanned.
+    pub fn txouts_with_spk_in_range<R: RangeBounds<ScriptBuf>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (OutPoint, &TxOut)> {
+        let mut txouts = self
+            .all_txouts()
+            .filter(|(_, txout)| range.contains(&txout.script_pubkey))
+            .collect::<Vec<_>>();
+        txouts.sort_unstable_by(|(op_a, txout_a), (op_b, txout_b)| {
+            (&txout_a.script_pubkey, op_a).cmp(&(&txout_b.script_pubkey, op_b))
+        });
+        txouts.into_iter()
+    }
+
     /// Iterate over floating txouts known by [`TxGraph`].
     ///
     /// Floating txouts are txouts that do not have the residing full transaction contained in the
@@ -181,7 +305,67 @@ impl<A> TxGraph<A> {
             .flatten()
     }
 
+    /// Iterate over prevouts that are spent by transactions in the graph but whose [`TxOut`] is
+    /// not known by [`TxGraph`].
+    ///
+    /// These are the outputs that are needed to calculate fees (refer to [`calculate_fee`]). Unlike
+    /// [`floating_txouts`], outpoints whose txout is known (either from a full transaction or from
+    /// [`insert_txout`]) are not returned. Outpoints are returned in order and without duplicates.
+    ///
+    /// [`calculate_fee`]: Self::calculate_fee
+    /// [`floating_txouts`]: Self::floating_txouts
+    /// [`insert_txout`]: Self::insert_txout
+    pub fn missing_prevouts(&self) -> impl Iterator<Item = OutPoint> + '_ {
+        self.spends
+            .keys()
+            .filter(move |&&outpoint| self.get_txout(outpoint).is_none())
+            .copied()
+    }
+
+    /// Iterate over the txids of transactions that fund or spend any of the given `spks`.
+    ///
+    /// A transaction funds a script pubkey if it has an output to it (this includes floating txouts
+    /// inserted with [`insert_txout`]). A full transaction spends a script pubkey if one of its
+    /// inputs spends an outpoint whose known txout pays to it. Prevouts that are not known to the
+    /// graph are not considered. Each txid is returned once, in no particular order.
+    ///
+    /// [`insert_txout`]: Self::insert_txout
+    pub fn txs_spending_or_funding(
+        &self,
+        spks: impl IntoIterator<Item = ScriptBuf>,
+    ) -> impl Iterator<Item = Txid> + '_ {
+        let spks = spks.into_iter().collect::<HashSet<_>>();
+        self.txs.iter().filter_map(move |(&txid, (tx, _, _))| {
+            let is_relevant = match tx {
+                TxNodeInternal::Whole(tx) => {
+                    tx.output
+                        .iter()
+                        .any(|txout| spks.contains(&txout.script_pubkey))
+                        || tx.input.iter().any(|txin| {
+                            self.get_txout(txin.previous_output)
+                                .map_or(false, |txout| spks.contains(&txout.script_pubkey))
+                        })
+                }
+                TxNodeInternal::Partial(txouts) => txouts
+                    .values()
+                    .any(|txout| spks.contains(&txout.script_pubkey)),
+            };
+            if is_relevant {
+                Some(txid)
+            } else {
+                None
+            }
+        })
+    }
+
     /// Iterate over all full transactions in the graph.
+    ///
+    /// Transactions are yielded in no particular order. Transactions which are only known through
+    /// floating txouts (inserted with [`insert_txout`]) are skipped; use [`floating_txouts`] to
+    /// iterate over those.
+    ///
+    /// [`insert_txout`]: Self::insert_txout
+    /// [`floating_txouts`]: Self::floating_txouts
     pub fn full_txs(&self) -> impl Iterator<Item = TxNode<'_, Transaction, A>> {
         self.txs
             .iter()
@@ -206,6 +390,12 @@ impl<A> TxGraph<A> {
     }
 
     /// Get a transaction node by txid. This only returns `Some` for full transactions.
+    ///
+    /// The [`TxNode`] bundles the transaction with its anchors and last-seen timestamp, so a single
+    /// lookup is enough to display the details of a transaction. Transactions that are only known
+    /// by their txouts (inserted with [`insert_txout`]) return `None`.
+    ///
+    /// [`insert_txout`]: Self::insert_txout
     pub fn get_tx_node(&self, txid: Txid) -> Option<TxNode<'_, Transaction, A>> {
         match &self.txs.get(&txid)? {
             (TxNodeInternal::Whole(tx), anchors, last_seen) => Some(TxNode {
@@ -218,6 +408,20 @@ impl<A> TxGraph<A> {
         }
     }
 
+    /// Get the latest timestamp at which the transaction of `txid` was seen unconfirmed.
+    ///
+    /// Last-seen timestamps only ever increase, so this is the highest timestamp recorded with
+    /// [`insert_seen_at`]. Returns `None` if the transaction was never seen unconfirmed (or is not
+    /// in the graph). This works for transactions which are only known by their txouts.
+    ///
+    /// [`insert_seen_at`]: Self::insert_seen_at
+    pub fn last_seen(&self, txid: Txid) -> Option<u64> {
+        self.txs
+            .get(&txid)
+            .map(|(_, _, last_seen)| *last_seen)
+            .filter(|&last_seen| last_seen > 0)
+    }
+
     /// Obtains a single tx output (if any) at the specified outpoint.
     pub fn get_txout(&self, outpoint: OutPoint) -> Option<&TxOut> {
         match &self.txs.get(&outpoint.txid)?.0 {
@@ -291,6 +495,19 @@ impl<A> TxGraph<A> {
         }
     }
 
+    /// Calculates the [`FeeRate`] of a given transaction.
+    ///
+    /// The fee is obtained with [`calculate_fee`] and divided by the virtual size of `tx` (the
+    /// weight divided by 4, rounded up). Returns the same errors as [`calculate_fee`].
+    ///
+    /// [`calculate_fee`]: Self::calculate_fee
+    pub fn calculate_fee_rate(&self, tx: &Transaction) -> Result<FeeRate, CalculateFeeError> {
+        let fee = self.calculate_fee(tx)?;
+        let vsize = tx.vsize() as u64;
+        // 1 vbyte is 4 weight units, so a sat/vb rate is 250 times a sat/kwu rate
+        Ok(FeeRate::from_sat_per_kwu(fee.saturating_mul(250) / vsize))
+    }
+
     /// The transactions spending from this output.
     ///
     /// `TxGraph` allows conflicting transactions within the graph. Obviously the transactions in
@@ -336,6 +553,34 @@ impl<A> TxGraph<A> {
         TxDescendants::new_exclude_root(self, txid, walk_map)
     }
 
+    /// Iterates over the txids of all descendants of `txid` in breadth-first order.
+    ///
+    /// The starting `txid` is never returned, and each descendant is only returned once (even if
+    /// it can be reached through multiple spend paths).
+    pub fn descendants(&self, txid: Txid) -> impl Iterator<Item = Txid> + '_ {
+        let mut visited = HashSet::<Txid>::new();
+        visited.insert(txid);
+        let mut queue = VecDeque::<Txid>::new();
+        let mut enqueue_children = move |queue: &mut VecDeque<Txid>, parent: Txid| {
+            for &child in self
+                .spends
+                .range(tx_outpoint_range(parent))
+                .flat_map(|(_, spends)| spends)
+            {
+                if visited.insert(child) {
+                    queue.push_back(child);
+                }
+            }
+        };
+        enqueue_children(&mut queue, txid);
+
+        core::iter::from_fn(move || {
+            let descendant = queue.pop_front()?;
+            enqueue_children(&mut queue, descendant);
+            Some(descendant)
+        })
+    }
+
     /// Creates an iterator that both filters and maps conflicting transactions (this includes
     /// descendants of directly-conflicting transactions, which are also considered conflicts).
     ///
@@ -348,7 +593,7 @@ impl<A> TxGraph<A> {
     where
         F: FnMut(usize, Txid) -> Option<O> + 'g,
     {
-        let txids = self.direct_conflicts_of_tx(tx).map(|(_, txid)| txid);
+        let txids = self.direct_conflicts(tx).map(|(_, txid)| txid);
         TxDescendants::from_multiple_include_root(self, txids, walk_map)
     }
 
@@ -358,7 +603,7 @@ impl<A> TxGraph<A> {
     ///
     /// Note that this only returns directly conflicting txids and does not include descendants of
     /// those txids (which are technically also conflicting).
-    pub fn direct_conflicts_of_tx<'g>(
+    pub fn direct_conflicts<'g>(
         &'g self,
         tx: &'g Transaction,
     ) -> impl Iterator<Item = (usize, Txid)> + '_ {
@@ -371,11 +616,28 @@ impl<A> TxGraph<A> {
             .filter(move |(_, conflicting_txid)| *conflicting_txid != txid)
     }
 
+    /// Given a transaction, return an iterator of txids that directly conflict with the given
+    /// transaction's inputs (spends).
+    #[deprecated(since = "0.5.0", note = "Use `direct_conflicts` instead")]
+    pub fn direct_conflicts_of_tx<'g>(
+        &'g self,
+        tx: &'g Transaction,
+    ) -> impl Iterator<Item = (usize, Txid)> + 'g {
+        self.direct_conflicts(tx)
+    }
+
     /// Get all transaction anchors known by [`TxGraph`].
     pub fn all_anchors(&self) -> &BTreeSet<(A, Txid)> {
         &self.anchors
     }
 
+    /// Iterate over all `(txid, anchor)` pairs known by [`TxGraph`].
+    ///
+    /// A transaction with multiple anchors is yielded once per anchor. Pairs are ordered by anchor.
+    pub fn iter_anchors(&self) -> impl Iterator<Item = (Txid, &A)> {
+        self.anchors.iter().map(|(anchor, txid)| (*txid, anchor))
+    }
+
     /// Whether the graph has any transactions or outputs in it.
     pub fn is_empty(&self) -> bool {
         self.txs.is_empty()
@@ -414,6 +676,41 @@ impl<A: Clone + Ord> TxGraph<A> {
         self.apply_update(update)
     }
 
+    /// Same as [`insert_txout`], but checks the `txout` against the full transaction of `outpoint`
+    /// (if it exists in the graph).
+    ///
+    /// If the full transaction is known, the `txout` must be the transaction's output at
+    /// `outpoint.vout`, in which case nothing is inserted and the [`ChangeSet`] is empty. Otherwise,
+    /// the `txout` is inserted as a floating txout, just like [`insert_txout`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`InsertTxOutError`] if the known full transaction does not have the `txout` at
+    /// `outpoint.vout`. Nothing is inserted in this case.
+    ///
+    /// [`insert_txout`]: Self::insert_txout
+    pub fn try_insert_txout(
+        &mut self,
+        outpoint: OutPoint,
+        txout: TxOut,
+    ) -> Result<ChangeSet<A>, InsertTxOutError> {
+        if let Some((TxNodeInternal::Whole(tx), _, _)) = self.txs.get(&outpoint.txid) {
+            return match tx.output.get(outpoint.vout as usize) {
+                Some(expected) if *expected == txout => Ok(ChangeSet::default()),
+                Some(expected) => Err(InsertTxOutError::TxOutMismatch {
+                    outpoint,
+                    expected: expected.clone(),
+                    got: txout,
+                }),
+                None => Err(InsertTxOutError::MissingOutput {
+                    outpoint,
+                    output_count: tx.output.len(),
+                }),
+            };
+        }
+        Ok(self.insert_txout(outpoint, txout))
+    }
+
     /// Inserts the given transaction into [`TxGraph`].
     ///
     /// The [`ChangeSet`] returned will be empty if `tx` already exists.
@@ -445,6 +742,25 @@ impl<A: Clone + Ord> TxGraph<A> {
         self.apply_update(update)
     }
 
+    /// Inserts multiple `seen_at`s (for each txid) into [`TxGraph`].
+    ///
+    /// This results in a single [`ChangeSet`]. As with [`insert_seen_at`], only the latest
+    /// `seen_at` of each txid is kept, so a `seen_at` lower than what is already recorded is
+    /// ignored.
+    ///
+    /// [`insert_seen_at`]: Self::insert_seen_at
+    pub fn batch_insert_seen_at(
+        &mut self,
+        seen: impl IntoIterator<Item = (Txid, u64)>,
+    ) -> ChangeSet<A> {
+        let mut update = Self::default();
+        for (txid, seen_at) in seen {
+            let (_, _, update_last_seen) = update.txs.entry(txid).or_default();
+            *update_last_seen = seen_at.max(*update_last_seen);
+        }
+        self.apply_update(update)
+    }
+
     /// Extends this graph with another so that `self` becomes the union of the two sets of
     /// transactions.
     ///
@@ -456,6 +772,49 @@ impl<A: Clone + Ord> TxGraph<A> {
         changeset
     }
 
+    /// Extends this graph with `other`, assuming that the two graphs share no transactions.
+    ///
+    /// This is a faster alternative to [`apply_update`] for graphs which are known to be disjoint
+    /// (such as graphs from scans of different keychains), as the data of `other` is moved into
+    /// `self` without being compared against existing transactions. The returned [`ChangeSet`] is
+    /// the same as what [`apply_update`] would return.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`OverlapError`] if a txid (of a full transaction or of a txout) exists in both
+    /// graphs. `self` is left unchanged, so the caller can fall back to [`apply_update`].
+    ///
+    /// [`apply_update`]: Self::apply_update
+    pub fn merge_disjoint(&mut self, other: TxGraph<A>) -> Result<ChangeSet<A>, OverlapError> {
+        if let Some(&txid) = other.txs.keys().find(|&txid| self.txs.contains_key(txid)) {
+            return Err(OverlapError { txid });
+        }
+
+        let mut changeset = ChangeSet::default();
+        for (txid, (tx_node, anchors, last_seen)) in other.txs {
+            match &tx_node {
+                TxNodeInternal::Whole(tx) => {
+                    changeset.txs.insert(tx.clone());
+                }
+                TxNodeInternal::Partial(txouts) => changeset.txouts.extend(
+                    txouts
+                        .iter()
+                        .map(|(&vout, txout)| (OutPoint::new(txid, vout), txout.clone())),
+                ),
+            }
+            if last_seen > 0 {
+                changeset.last_seen.insert(txid, last_seen);
+            }
+            self.txs.insert(txid, (tx_node, anchors, last_seen));
+        }
+        for (outpoint, spends) in other.spends {
+            self.spends.entry(outpoint).or_default().extend(spends);
+        }
+        changeset.anchors = other.anchors.clone();
+        self.anchors.extend(other.anchors);
+        Ok(changeset)
+    }
+
     /// Determines the [`ChangeSet`] between `self` and an empty [`TxGraph`].
     pub fn initial_changeset(&self) -> ChangeSet<A> {
         Self::default().determine_changeset(self.clone())
@@ -576,9 +935,104 @@ impl<A: Clone + Ord> TxGraph<A> {
 
         changeset
     }
+
+    /// Applies a [`ChangeSetWithRemovals`] to [`TxGraph`].
+    ///
+    /// The removed transactions are removed first, alongside their txouts, anchors and last-seen
+    /// timestamps. Then the removed anchors are removed. Lastly, the additions are applied (as in
+    /// [`apply_changeset`]), so a floating txout (or anchor) added after a removal is kept.
+    ///
+    /// [`apply_changeset`]: Self::apply_changeset
+    pub fn apply_changeset_with_removals(&mut self, changeset: ChangeSetWithRemovals<A>) {
+        for txid in changeset.removed {
+            self.remove_tx(txid);
+        }
+        for (anchor, txid) in changeset.removed_anchors {
+            if let Some((_, anchors, _)) = self.txs.get_mut(&txid) {
+                anchors.remove(&anchor);
+            }
+            self.anchors.remove(&(anchor, txid));
+        }
+        self.apply_changeset(changeset.graph);
+    }
+
+    /// Removes the given `anchor` of `txid` from [`TxGraph`], without removing the transaction.
+    ///
+    /// This is useful to evict a stale anchor (i.e. one which disagrees with the best chain after a
+    /// reorg), so that the transaction is no longer considered confirmed by it. Other anchors of
+    /// the transaction are kept. The returned [`ChangeSetWithRemovals`] records the removal so that it
+    /// can be persisted, and is empty if the graph does not have the `anchor` for `txid`. Use
+    /// [`IndexedTxGraph::remove_anchor`] to obtain a changeset which can be persisted alongside the
+    /// index.
+    ///
+    /// [`IndexedTxGraph::remove_anchor`]: crate::indexed_tx_graph::IndexedTxGraph::remove_anchor
+    pub fn remove_anchor(&mut self, txid: Txid, anchor: &A) -> ChangeSetWithRemovals<A> {
+        let anchor = (anchor.clone(), txid);
+        if !self.anchors.contains(&anchor) {
+            return ChangeSetWithRemovals::default();
+        }
+        let changeset = ChangeSetWithRemovals {
+            removed_anchors: [anchor].into(),
+            ..Default::default()
+        };
+        self.apply_changeset_with_removals(changeset.clone());
+        changeset
+    }
+
+    fn remove_tx(&mut self, txid: Txid) {
+        let (tx_node, anchors, _) = match self.txs.remove(&txid) {
+            Some(v) => v,
+            None => return,
+        };
+        for anchor in anchors {
+            self.anchors.remove(&(anchor, txid));
+        }
+        if let TxNodeInternal::Whole(tx) = tx_node {
+            for txin in &tx.input {
+                if let Some(spends) = self.spends.get_mut(&txin.previous_output) {
+                    spends.remove(&txid);
+                    if spends.is_empty() {
+                        self.spends.remove(&txin.previous_output);
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl<A: Anchor> TxGraph<A> {
+    /// Iterate over transactions anchored within the given `range` of heights.
+    ///
+    /// The height of a transaction is the [`confirmation_height_upper_bound`] of its lowest
+    /// anchor. Each transaction is therefore emitted at most once, together with that anchor.
+    /// Transactions without anchors are excluded. Transactions are ordered by height, then txid.
+    ///
+    /// Note that this does not check whether anchors are part of the best chain.
+    ///
+    /// [`confirmation_height_upper_bound`]: Anchor::confirmation_height_upper_bound
+    pub fn txs_with_anchor_in_range(
+        &self,
+        range: impl RangeBounds<u32>,
+    ) -> impl Iterator<Item = (Txid, &A)> + '_ {
+        let mut txs = self
+            .txs
+            .iter()
+            .filter_map(|(txid, (_, anchors, _))| {
+                let anchor = anchors
+                    .iter()
+                    .min_by_key(|&anchor| A::confirmation_height_upper_bound(anchor))?;
+                let height = anchor.confirmation_height_upper_bound();
+                if range.contains(&height) {
+                    Some((height, *txid, anchor))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        txs.sort_unstable_by_key(|(height, txid, _)| (*height, *txid));
+        txs.into_iter().map(|(_, txid, anchor)| (txid, anchor))
+    }
+
     /// Find missing block heights of `chain`.
     ///
     /// This works by scanning through anchors, and seeing whether the anchor block of the anchor
@@ -666,7 +1120,15 @@ impl<A: Anchor> TxGraph<A> {
 
         for anchor in anchors {
             match chain.is_block_in_chain(anchor.anchor_block(), chain_tip)? {
-                Some(true) => return Ok(Some(ChainPosition::Confirmed(anchor))),
+                Some(true) => {
+                    // The anchor is invalid if the chain disagrees with its confirmation block.
+                    if let Some(confirmation_block) = anchor.confirmation_block() {
+                        if chain.is_block_in_chain(confirmation_block, chain_tip)? == Some(false) {
+                            continue;
+                        }
+                    }
+                    return Ok(Some(ChainPosition::Confirmed(anchor)));
+                }
                 _ => continue,
             }
         }
@@ -807,6 +1269,194 @@ impl<A: Anchor> TxGraph<A> {
             .map(|r| r.expect("oracle is infallible"))
     }
 
+    /// List graph transactions that are conflicted out of `chain` with `chain_tip`, alongside the
+    /// transaction that replaced them.
+    ///
+    /// Each item is an `(evicted_txid, winning_txid)` pair. The evicted transaction is a full
+    /// transaction which is not in the best chain (refer to [`try_get_chain_position`]), and the
+    /// winning transaction is a conflicting transaction which is. Direct conflicts are preferred
+    /// over descendants of direct conflicts as the winner. Evicted transactions which are only
+    /// conflicted out by transactions that are themselves not in the best chain are not listed.
+    ///
+    /// This complements [`try_list_chain_txs`], i.e. to display a history of replaced
+    /// transactions.
+    ///
+    /// # Error
+    ///
+    /// If the [`ChainOracle`] implementation (`chain`) fails, an error will be returned with the
+    /// returned item.
+    ///
+    /// If the [`ChainOracle`] is infallible, [`list_conflicted_txs`] can be used instead.
+    ///
+    /// [`try_get_chain_position`]: Self::try_get_chain_position
+    /// [`try_list_chain_txs`]: Self::try_list_chain_txs
+    /// [`list_conflicted_txs`]: Self::list_conflicted_txs
+    pub fn try_list_conflicted_txs<'a, C: ChainOracle + 'a>(
+        &'a self,
+        chain: &'a C,
+        chain_tip: BlockId,
+    ) -> impl Iterator<Item = Result<(Txid, Txid), C::Error>> + 'a {
+        self.full_txs().filter_map(move |tx_node| {
+            match self.try_get_chain_position(chain, chain_tip, tx_node.txid) {
+                Ok(Some(_)) => return None,
+                Ok(None) => {}
+                Err(err) => return Some(Err(err)),
+            }
+            let mut conflicts = self
+                .walk_conflicts(tx_node.tx, |depth, txid| Some((depth, txid)))
+                .collect::<Vec<_>>();
+            conflicts.sort_by_key(|&(depth, _)| depth);
+            for (_, txid) in conflicts {
+                match self.try_get_chain_position(chain, chain_tip, txid) {
+                    Ok(Some(_)) => return Some(Ok((tx_node.txid, txid))),
+                    Ok(None) => {}
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+            None
+        })
+    }
+
+    /// List graph transactions that are conflicted out of `chain` with `chain_tip`, alongside the
+    /// transaction that replaced them.
+    ///
+    /// This is the infallible version of [`try_list_conflicted_txs`].
+    ///
+    /// [`try_list_conflicted_txs`]: Self::try_list_conflicted_txs
+    pub fn list_conflicted_txs<'a, C: ChainOracle<Error = Infallible> + 'a>(
+        &'a self,
+        chain: &'a C,
+        chain_tip: BlockId,
+    ) -> impl Iterator<Item = (Txid, Txid)> + 'a {
+        self.try_list_conflicted_txs(chain, chain_tip)
+            .map(|r| r.expect("oracle is infallible"))
+    }
+
+    /// Get the transaction of `txid` as a [`CanonicalTx`], if it is in `chain` with `chain_tip`.
+    ///
+    /// This is a point lookup of [`try_list_chain_txs`]. `None` is returned if the transaction is
+    /// not a full transaction in the graph, or if it is not in the best chain (i.e. it is
+    /// conflicted out).
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. If the
+    /// [`ChainOracle`] is infallible, [`canonical_tx`] can be used instead.
+    ///
+    /// [`try_list_chain_txs`]: Self::try_list_chain_txs
+    /// [`canonical_tx`]: Self::canonical_tx
+    pub fn try_canonical_tx<C: ChainOracle>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        txid: Txid,
+    ) -> Result<Option<CanonicalTx<'_, Transaction, A>>, C::Error> {
+        let tx_node = match self.get_tx_node(txid) {
+            Some(tx_node) => tx_node,
+            None => return Ok(None),
+        };
+        Ok(self
+            .try_get_chain_position(chain, chain_tip, txid)?
+            .map(|chain_position| CanonicalTx {
+                chain_position,
+                tx_node,
+            }))
+    }
+
+    /// Get the transaction of `txid` as a [`CanonicalTx`], if it is in `chain` with `chain_tip`.
+    ///
+    /// This is the infallible version of [`try_canonical_tx`].
+    ///
+    /// [`try_canonical_tx`]: Self::try_canonical_tx
+    pub fn canonical_tx<C: ChainOracle<Error = Infallible>>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        txid: Txid,
+    ) -> Option<CanonicalTx<'_, Transaction, A>> {
+        self.try_canonical_tx(chain, chain_tip, txid)
+            .expect("oracle is infallible")
+    }
+
+    /// Applies `update` to `self` (like [`apply_update`]) and returns the txids of transactions
+    /// that became confirmed in `chain` with `chain_tip` as a result.
+    ///
+    /// A transaction becomes confirmed when `update` introduces an anchor of it that is in the best
+    /// chain, and it was not already confirmed. Only transactions of which the full transaction is
+    /// known (in `self` or `update`) are returned, so a transaction which was anchored before its
+    /// full transaction is known is returned once `update` introduces it. The returned txids are
+    /// sorted.
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. `self` is not
+    /// changed in this case. If the [`ChainOracle`] is infallible,
+    /// [`apply_update_with_transitions`] can be used instead.
+    ///
+    /// [`apply_update`]: Self::apply_update
+    /// [`apply_update_with_transitions`]: Self::apply_update_with_transitions
+    pub fn try_apply_update_with_transitions<C: ChainOracle>(
+        &mut self,
+        chain: &C,
+        chain_tip: BlockId,
+        update: TxGraph<A>,
+    ) -> Result<(ChangeSet<A>, Vec<Txid>), C::Error> {
+        let changeset = self.determine_changeset(update);
+
+        // all chain oracle calls happen before the changeset is applied, so that `self` is not
+        // changed on error
+        let added_txids = changeset
+            .txs
+            .iter()
+            .map(Transaction::txid)
+            .collect::<BTreeSet<_>>();
+        let mut newly_confirmed = BTreeSet::new();
+        for txid in &added_txids {
+            // a previously anchored tx of which the full tx was not known is only visible now
+            if self.get_tx(*txid).is_none()
+                && matches!(
+                    self.try_get_chain_position(chain, chain_tip, *txid)?,
+                    Some(ChainPosition::Confirmed(_))
+                )
+            {
+                newly_confirmed.insert(*txid);
+            }
+        }
+        for (anchor, txid) in &changeset.anchors {
+            if newly_confirmed.contains(txid)
+                || (self.get_tx(*txid).is_none() && !added_txids.contains(txid))
+                || chain.is_block_in_chain(anchor.anchor_block(), chain_tip)? != Some(true)
+            {
+                continue;
+            }
+            match self.try_get_chain_position(chain, chain_tip, *txid)? {
+                Some(ChainPosition::Confirmed(_)) => {}
+                _ => {
+                    newly_confirmed.insert(*txid);
+                }
+            }
+        }
+
+        self.apply_changeset(changeset.clone());
+        Ok((changeset, newly_confirmed.into_iter().collect()))
+    }
+
+    /// Applies `update` to `self` and returns the txids of transactions that became confirmed in
+    /// `chain` with `chain_tip` as a result.
+    ///
+    /// This is the infallible version of [`try_apply_update_with_transitions`].
+    ///
+    /// [`try_apply_update_with_transitions`]: Self::try_apply_update_with_transitions
+    pub fn apply_update_with_transitions<C: ChainOracle<Error = Infallible>>(
+        &mut self,
+        chain: &C,
+        chain_tip: BlockId,
+        update: TxGraph<A>,
+    ) -> (ChangeSet<A>, Vec<Txid>) {
+        self.try_apply_update_with_transitions(chain, chain_tip, update)
+            .expect("oracle is infallible")
+    }
+
     /// Get a filtered list of outputs from the given `outpoints` that are in `chain` with
     /// `chain_tip`.
     ///
@@ -935,9 +1585,83 @@ impl<A: Anchor> TxGraph<A> {
             .map(|r| r.expect("oracle is infallible"))
     }
 
+    /// Get a filtered list of economical unspent outputs from the given `outpoints` that are in
+    /// `chain` with `chain_tip`.
+    ///
+    /// This is the same as [`try_filter_chain_unspents`], but excludes unspent outputs with a value
+    /// below the fee of spending them at `feerate`. `input_weight` returns the weight of an input
+    /// spending the output of the given outpoint identifier (`OI`), as this depends on the script
+    /// type (e.g. the keychain's descriptor).
+    ///
+    /// # Error
+    ///
+    /// An [`Iterator::Item`] can be an [`Err`] if the [`ChainOracle`] implementation (`chain`)
+    /// fails.
+    ///
+    /// If the [`ChainOracle`] implementation is infallible, [`filter_chain_unspents_economical`]
+    /// can be used instead.
+    ///
+    /// [`try_filter_chain_unspents`]: Self::try_filter_chain_unspents
+    /// [`filter_chain_unspents_economical`]: Self::filter_chain_unspents_economical
+    pub fn try_filter_chain_unspents_economical<'a, C: ChainOracle + 'a, OI: Clone + 'a>(
+        &'a self,
+        chain: &'a C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)> + 'a,
+        feerate: FeeRate,
+        input_weight: impl Fn(&OI) -> usize + 'a,
+    ) -> impl Iterator<Item = Result<(OI, FullTxOut<A>), C::Error>> + 'a {
+        self.try_filter_chain_unspents(chain, chain_tip, outpoints)
+            .filter(move |r| match r {
+                Ok((spk_i, full_txo)) => {
+                    // round the fee up, as the weight is in weight units
+                    let spend_fee = (feerate
+                        .to_sat_per_kwu()
+                        .saturating_mul(input_weight(spk_i) as u64)
+                        + 999)
+                        / 1000;
+                    full_txo.txout.value >= spend_fee
+                }
+                // keep errors
+                Err(_) => true,
+            })
+    }
+
+    /// Get a filtered list of economical unspent outputs from the given `outpoints` that are in
+    /// `chain` with `chain_tip`.
+    ///
+    /// This is the infallible version of [`try_filter_chain_unspents_economical`].
+    ///
+    /// [`try_filter_chain_unspents_economical`]: Self::try_filter_chain_unspents_economical
+    pub fn filter_chain_unspents_economical<
+        'a,
+        C: ChainOracle<Error = Infallible> + 'a,
+        OI: Clone + 'a,
+    >(
+        &'a self,
+        chain: &'a C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)> + 'a,
+        feerate: FeeRate,
+        input_weight: impl Fn(&OI) -> usize + 'a,
+    ) -> impl Iterator<Item = (OI, FullTxOut<A>)> + 'a {
+        self.try_filter_chain_unspents_economical(
+            chain,
+            chain_tip,
+            outpoints,
+            feerate,
+            input_weight,
+        )
+        .map(|r| r.expect("oracle is infallible"))
+    }
+
     /// Get the total balance of `outpoints` that are in `chain` of `chain_tip`.
     ///
-    /// The output of `trust_predicate` should return `true` for scripts that we trust.
+    /// The output of `trust_predicate` should return `true` for scripts that we trust. Unconfirmed
+    /// outputs are counted as [`Balance::trusted_pending`] if `trust_predicate` returns `true` for
+    /// them, and as [`Balance::untrusted_pending`] otherwise. For example, a wallet may trust the
+    /// outputs of its internal keychain (its own change) while treating unconfirmed incoming
+    /// payments as untrusted. Confirmed outputs are not affected by `trust_predicate`.
     ///
     /// `outpoints` is a list of outpoints we are interested in, coupled with an outpoint identifier
     /// (`OI`) for convenience. If `OI` is not necessary, the caller can use `()`, or
@@ -948,31 +1672,130 @@ impl<A: Anchor> TxGraph<A> {
     ///
     /// [`balance`]: Self::balance
     pub fn try_balance<C: ChainOracle, OI: Clone>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)>,
+        trust_predicate: impl FnMut(&OI, &Script) -> bool,
+    ) -> Result<Balance, C::Error> {
+        self.try_balance_with_coinbase_maturity(
+            chain,
+            chain_tip,
+            outpoints,
+            trust_predicate,
+            COINBASE_MATURITY,
+        )
+    }
+
+    /// Get the total balance of `outpoints` that are in `chain` of `chain_tip`, where coinbase
+    /// outputs require `coinbase_maturity` confirmations to be spendable (instead of
+    /// [`COINBASE_MATURITY`]).
+    ///
+    /// This is useful for chains with non-standard coinbase maturity. Refer to [`try_balance`] for
+    /// more.
+    ///
+    /// [`try_balance`]: Self::try_balance
+    pub fn try_balance_with_coinbase_maturity<C: ChainOracle, OI: Clone>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)>,
+        trust_predicate: impl FnMut(&OI, &Script) -> bool,
+        coinbase_maturity: u32,
+    ) -> Result<Balance, C::Error> {
+        self.try_balance_inner(
+            chain,
+            chain_tip,
+            outpoints,
+            trust_predicate,
+            coinbase_maturity,
+            |_| false,
+        )
+    }
+
+    /// Get the total balance of `outpoints` that are in `chain` of `chain_tip`, where the values
+    /// of outpoints for which `is_locked` returns `true` are counted in [`Balance::locked`].
+    ///
+    /// Locked outpoints which would otherwise be `confirmed` or `trusted_pending` are moved into
+    /// `locked`. Immature and untrusted pending outputs are not spendable anyway, so they are
+    /// categorized as usual. Coinbase outputs require `coinbase_maturity` confirmations to be
+    /// spendable (pass [`COINBASE_MATURITY`] for the standard maturity). Refer to [`try_balance`]
+    /// for more.
+    ///
+    /// [`try_balance`]: Self::try_balance
+    pub fn try_balance_with_locked<C: ChainOracle, OI: Clone>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)>,
+        trust_predicate: impl FnMut(&OI, &Script) -> bool,
+        coinbase_maturity: u32,
+        is_locked: impl Fn(&OutPoint) -> bool,
+    ) -> Result<Balance, C::Error> {
+        self.try_balance_inner(
+            chain,
+            chain_tip,
+            outpoints,
+            trust_predicate,
+            coinbase_maturity,
+            is_locked,
+        )
+    }
+
+    fn try_balance_inner<C: ChainOracle, OI: Clone>(
         &self,
         chain: &C,
         chain_tip: BlockId,
         outpoints: impl IntoIterator<Item = (OI, OutPoint)>,
         mut trust_predicate: impl FnMut(&OI, &Script) -> bool,
+        coinbase_maturity: u32,
+        is_locked: impl Fn(&OutPoint) -> bool,
     ) -> Result<Balance, C::Error> {
         let mut immature = 0;
         let mut trusted_pending = 0;
         let mut untrusted_pending = 0;
         let mut confirmed = 0;
+        let mut immature_coinbase = BTreeMap::<u32, u64>::new();
+        let mut locked_immature_coinbase = BTreeMap::<u32, u64>::new();
+        let mut locked = 0;
 
         for res in self.try_filter_chain_unspents(chain, chain_tip, outpoints) {
             let (spk_i, txout) = res?;
+            let is_locked = is_locked(&txout.outpoint);
 
             match &txout.chain_position {
-                ChainPosition::Confirmed(_) => {
-                    if txout.is_confirmed_and_spendable(chain_tip.height) {
-                        confirmed += txout.txout.value;
-                    } else if !txout.is_mature(chain_tip.height) {
+                ChainPosition::Confirmed(anchor) => {
+                    if txout.is_confirmed_and_spendable_with_coinbase_maturity(
+                        chain_tip.height,
+                        coinbase_maturity,
+                    ) {
+                        if is_locked {
+                            locked += txout.txout.value;
+                        } else {
+                            confirmed += txout.txout.value;
+                        }
+                    } else if !txout
+                        .is_mature_with_coinbase_maturity(chain_tip.height, coinbase_maturity)
+                    {
                         immature += txout.txout.value;
+                        // keep locked amounts apart so that they stay locked once matured
+                        let immature_coinbase = if is_locked {
+                            &mut locked_immature_coinbase
+                        } else {
+                            &mut immature_coinbase
+                        };
+                        *immature_coinbase
+                            .entry(anchor.confirmation_height_upper_bound())
+                            .or_default() += txout.txout.value;
                     }
                 }
                 ChainPosition::Unconfirmed(_) => {
                     if trust_predicate(&spk_i, &txout.txout.script_pubkey) {
-                        trusted_pending += txout.txout.value;
+                        if is_locked {
+                            locked += txout.txout.value;
+                        } else {
+                            trusted_pending += txout.txout.value;
+                        }
                     } else {
                         untrusted_pending += txout.txout.value;
                     }
@@ -985,6 +1808,9 @@ impl<A: Anchor> TxGraph<A> {
             trusted_pending,
             untrusted_pending,
             confirmed,
+            immature_coinbase,
+            locked_immature_coinbase,
+            locked,
         })
     }
 
@@ -1003,8 +1829,315 @@ impl<A: Anchor> TxGraph<A> {
         self.try_balance(chain, chain_tip, outpoints, trust_predicate)
             .expect("oracle is infallible")
     }
+
+    /// Get the total balance of `outpoints` that are in `chain` of `chain_tip`, where coinbase
+    /// outputs require `coinbase_maturity` confirmations to be spendable.
+    ///
+    /// This is the infallible version of [`try_balance_with_coinbase_maturity`].
+    ///
+    /// [`try_balance_with_coinbase_maturity`]: Self::try_balance_with_coinbase_maturity
+    pub fn balance_with_coinbase_maturity<C: ChainOracle<Error = Infallible>, OI: Clone>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)>,
+        trust_predicate: impl FnMut(&OI, &Script) -> bool,
+        coinbase_maturity: u32,
+    ) -> Balance {
+        self.try_balance_with_coinbase_maturity(
+            chain,
+            chain_tip,
+            outpoints,
+            trust_predicate,
+            coinbase_maturity,
+        )
+        .expect("oracle is infallible")
+    }
+
+    /// Get the total balance of `outpoints` that are in `chain` of `chain_tip`, where the values
+    /// of outpoints for which `is_locked` returns `true` are counted in [`Balance::locked`].
+    ///
+    /// This is the infallible version of [`try_balance_with_locked`].
+    ///
+    /// [`try_balance_with_locked`]: Self::try_balance_with_locked
+    pub fn balance_with_locked<C: ChainOracle<Error = Infallible>, OI: Clone>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        outpoints: impl IntoIterator<Item = (OI, OutPoint)>,
+        trust_predicate: impl FnMut(&OI, &Script) -> bool,
+        coinbase_maturity: u32,
+        is_locked: impl Fn(&OutPoint) -> bool,
+    ) -> Balance {
+        self.try_balance_with_locked(
+            chain,
+            chain_tip,
+            outpoints,
+            trust_predicate,
+            coinbase_maturity,
+            is_locked,
+        )
+        .expect("oracle is infallible")
+    }
+
+    /// Get the total fee and total virtual size of the transaction of `txid` together with all of
+    /// its unconfirmed ancestors in `chain` of `chain_tip`.
+    ///
+    /// This is useful for determining the effective fee rate of a transaction package when
+    /// fee-bumping with CPFP. Ancestors which are confirmed in `chain`, or which are not canonical
+    /// (i.e. conflicted or evicted), are not part of the package (and their ancestors are not
+    /// visited). Each ancestor is only counted once, even if it is reachable through multiple
+    /// inputs.
+    ///
+    /// # Error
+    ///
+    /// An error is returned if the [`ChainOracle`] implementation (`chain`) fails, if the full
+    /// transaction of `txid` or of an unconfirmed ancestor is missing, or if the fee of a
+    /// transaction in the package cannot be calculated (refer to [`calculate_fee`]).
+    ///
+    /// [`calculate_fee`]: Self::calculate_fee
+    pub fn unconfirmed_ancestor_package<C: ChainOracle>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        txid: Txid,
+    ) -> Result<(Amount, usize), AncestorPackageError<C::Error>> {
+        let mut visited = HashSet::<Txid>::new();
+        let mut stack = vec![txid];
+        let mut total_fee = 0_u64;
+        let mut total_vsize = 0_usize;
+
+        while let Some(txid) = stack.pop() {
+            if !visited.insert(txid) {
+                continue;
+            }
+            let tx = self
+                .get_tx(txid)
+                .ok_or(AncestorPackageError::MissingTx(txid))?;
+            total_fee += self
+                .calculate_fee(tx)
+                .map_err(AncestorPackageError::CalculateFee)?;
+            total_vsize += tx.vsize();
+
+            if tx.is_coin_base() {
+                continue;
+            }
+            for txin in &tx.input {
+                let parent_txid = txin.previous_output.txid;
+                if visited.contains(&parent_txid) {
+                    continue;
+                }
+                let parent_position = self
+                    .try_get_chain_position(chain, chain_tip, parent_txid)
+                    .map_err(AncestorPackageError::ChainOracle)?;
+                // confirmed parents are not part of the package, and non-canonical (conflicted or
+                // evicted) parents cannot be mined together with it
+                if matches!(parent_position, Some(ChainPosition::Unconfirmed(_))) {
+                    stack.push(parent_txid);
+                }
+            }
+        }
+
+        Ok((Amount::from_sat(total_fee), total_vsize))
+    }
+
+    /// Prune old transactions that are confirmed and fully spent.
+    ///
+    /// A transaction is pruned if all of the following hold:
+    ///
+    /// 1. It is confirmed in `chain` below `height`.
+    /// 2. Every one of its outputs is spent, and every spending transaction is confirmed.
+    /// 3. Every transaction spending it also satisfies (2). Otherwise, the transaction is kept as
+    ///    it is the prevout of an unspent or unconfirmed output.
+    ///
+    /// As the outputs of a pruned transaction are spent by confirmed transactions, a pruned
+    /// transaction never funds an unspent output. However, the spending transactions will no
+    /// longer have the prevouts needed to calculate fees.
+    ///
+    /// The returned [`ChangeSetWithRemovals`] records the removed transactions so that they can be
+    /// removed from persistence as well (refer to [`apply_changeset_with_removals`]). Use
+    /// [`IndexedTxGraph::try_prune_spent_before`] to also remove the txouts from the index.
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. If the
+    /// [`ChainOracle`] is infallible, [`prune_spent_before`] can be used instead.
+    ///
+    /// [`apply_changeset_with_removals`]: Self::apply_changeset_with_removals
+    /// [`IndexedTxGraph::try_prune_spent_before`]: crate::indexed_tx_graph::IndexedTxGraph::try_prune_spent_before
+    /// [`prune_spent_before`]: Self::prune_spent_before
+    pub fn try_prune_spent_before<C: ChainOracle>(
+        &mut self,
+        height: u32,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Result<ChangeSetWithRemovals<A>, C::Error> {
+        let mut removed = BTreeSet::new();
+        for tx_node in self.full_txs() {
+            let is_old = match self.try_get_chain_position(chain, chain_tip, tx_node.txid)? {
+                Some(ChainPosition::Confirmed(anchor)) => {
+                    anchor.confirmation_height_upper_bound() < height
+                }
+                _ => false,
+            };
+            if !is_old || !self.try_is_spent_by_confirmed(chain, chain_tip, tx_node.tx)? {
+                continue;
+            }
+            let mut is_needed = false;
+            for vout in 0..tx_node.tx.output.len() {
+                for &spend_txid in self.outspends(OutPoint::new(tx_node.txid, vout as _)) {
+                    let spend_tx = self.get_tx(spend_txid).expect("spends are of full txs");
+                    if !self.try_is_spent_by_confirmed(chain, chain_tip, spend_tx)? {
+                        is_needed = true;
+                    }
+                }
+            }
+            if !is_needed {
+                removed.insert(tx_node.txid);
+            }
+        }
+
+        let changeset = ChangeSetWithRemovals {
+            removed,
+            ..Default::default()
+        };
+        self.apply_changeset_with_removals(changeset.clone());
+        Ok(changeset)
+    }
+
+    /// Prune old transactions that are confirmed and fully spent.
+    ///
+    /// This is the infallible version of [`try_prune_spent_before`].
+    ///
+    /// [`try_prune_spent_before`]: Self::try_prune_spent_before
+    pub fn prune_spent_before<C: ChainOracle<Error = Infallible>>(
+        &mut self,
+        height: u32,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> ChangeSetWithRemovals<A> {
+        self.try_prune_spent_before(height, chain, chain_tip)
+            .expect("oracle is infallible")
+    }
+
+    /// Evict unconfirmed transactions which were last seen before `last_seen_cutoff`.
+    ///
+    /// This is intended to remove transactions which have likely been dropped from mempools. A
+    /// transaction is evicted if it is not confirmed in `chain`, its last-seen timestamp is lower
+    /// than `last_seen_cutoff`, and every one of its descendants is evicted as well. In other
+    /// words, the descendants of an evicted transaction are also evicted, and a transaction is
+    /// kept if it has a descendant which was seen recently (or is confirmed). Confirmed
+    /// transactions are never evicted. Transactions which were never seen (with a last-seen
+    /// timestamp of 0, such as transactions created locally and not yet broadcast) are never
+    /// evicted either, and also keep their ancestors.
+    ///
+    /// The returned [`ChangeSetWithRemovals`] records the removed transactions so that they can be
+    /// removed from persistence as well (refer to [`apply_changeset_with_removals`]). Use
+    /// [`IndexedTxGraph::try_evict_unconfirmed_before`] to also remove the txouts from the index.
+    ///
+    /// # Error
+    ///
+    /// An error will occur if the [`ChainOracle`] implementation (`chain`) fails. If the
+    /// [`ChainOracle`] is infallible, [`evict_unconfirmed_before`] can be used instead.
+    ///
+    /// [`apply_changeset_with_removals`]: Self::apply_changeset_with_removals
+    /// [`IndexedTxGraph::try_evict_unconfirmed_before`]: crate::indexed_tx_graph::IndexedTxGraph::try_evict_unconfirmed_before
+    /// [`evict_unconfirmed_before`]: Self::evict_unconfirmed_before
+    pub fn try_evict_unconfirmed_before<C: ChainOracle>(
+        &mut self,
+        last_seen_cutoff: u64,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Result<ChangeSetWithRemovals<A>, C::Error> {
+        let mut stale = BTreeSet::new();
+        for tx_node in self.full_txs() {
+            if tx_node.last_seen_unconfirmed == 0
+                || tx_node.last_seen_unconfirmed >= last_seen_cutoff
+            {
+                continue;
+            }
+            let position = self.try_get_chain_position(chain, chain_tip, tx_node.txid)?;
+            if !matches!(position, Some(ChainPosition::Confirmed(_))) {
+                stale.insert(tx_node.txid);
+            }
+        }
+
+        let removed = stale
+            .iter()
+            .filter(|&&txid| self.descendants(txid).all(|txid| stale.contains(&txid)))
+            .copied()
+            .collect();
+        let changeset = ChangeSetWithRemovals {
+            removed,
+            ..Default::default()
+        };
+        self.apply_changeset_with_removals(changeset.clone());
+        Ok(changeset)
+    }
+
+    /// Evict unconfirmed transactions which were last seen before `last_seen_cutoff`.
+    ///
+    /// This is the infallible version of [`try_evict_unconfirmed_before`].
+    ///
+    /// [`try_evict_unconfirmed_before`]: Self::try_evict_unconfirmed_before
+    pub fn evict_unconfirmed_before<C: ChainOracle<Error = Infallible>>(
+        &mut self,
+        last_seen_cutoff: u64,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> ChangeSetWithRemovals<A> {
+        self.try_evict_unconfirmed_before(last_seen_cutoff, chain, chain_tip)
+            .expect("oracle is infallible")
+    }
+
+    /// Whether every output of `tx` is spent by a transaction that is confirmed in `chain`.
+    fn try_is_spent_by_confirmed<C: ChainOracle>(
+        &self,
+        chain: &C,
+        chain_tip: BlockId,
+        tx: &Transaction,
+    ) -> Result<bool, C::Error> {
+        let txid = tx.txid();
+        for vout in 0..tx.output.len() {
+            let spends = self.outspends(OutPoint::new(txid, vout as _));
+            if spends.is_empty() {
+                return Ok(false);
+            }
+            for &spend_txid in spends {
+                let position = self.try_get_chain_position(chain, chain_tip, spend_txid)?;
+                if !matches!(position, Some(ChainPosition::Confirmed(_))) {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
 }
 
+/// Errors returned by `TxGraph::unconfirmed_ancestor_package`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AncestorPackageError<E> {
+    /// The [`ChainOracle`] failed.
+    ChainOracle(E),
+    /// The full transaction of the given txid is missing from the graph
+    MissingTx(Txid),
+    /// The fee of a transaction in the package cannot be calculated
+    CalculateFee(CalculateFeeError),
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for AncestorPackageError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::ChainOracle(err) => write!(f, "chain oracle error: {}", err),
+            Self::MissingTx(txid) => write!(f, "missing full transaction {}", txid),
+            Self::CalculateFee(err) => write!(f, "cannot calculate fee: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: core::fmt::Debug + core::fmt::Display> std::error::Error for AncestorPackageError<E> {}
+
 /// A structure that represents changes to a [`TxGraph`].
 ///
 /// Since [`TxGraph`] is monotone "changeset" can only contain transactions to be added and
@@ -1131,6 +2264,103 @@ impl<A: Ord> Append for ChangeSet<A> {
     }
 }
 
+/// A [`ChangeSet`] that can also record the removal of transactions and anchors.
+///
+/// This is returned by [`TxGraph::prune_spent_before`] (and [`TxGraph::remove_anchor`]) and can be
+/// applied with [`TxGraph::apply_changeset_with_removals`]. To persist the removals alongside the
+/// index, use the equivalent methods of [`IndexedTxGraph`] instead.
+///
+/// [`IndexedTxGraph`]: crate::indexed_tx_graph::IndexedTxGraph
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(
+        crate = "serde_crate",
+        bound(
+            deserialize = "A: Ord + serde::Deserialize<'de>",
+            serialize = "A: Ord + serde::Serialize",
+        )
+    )
+)]
+#[must_use]
+pub struct ChangeSetWithRemovals<A = ()> {
+    /// Additions to the graph.
+    pub graph: ChangeSet<A>,
+    /// Txids of removed transactions.
+    pub removed: BTreeSet<Txid>,
+    /// Removed anchors of transactions which are kept.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub removed_anchors: BTreeSet<(A, Txid)>,
+}
+
+impl<A: Ord> Append for ChangeSetWithRemovals<A> {
+    /// Append another [`ChangeSetWithRemovals`] into self.
+    ///
+    /// Additions are merged as in [`ChangeSet::append`]. A transaction removed in `other` is
+    /// removed from the additions of self, and a transaction re-added in full in `other` is no
+    /// longer considered removed. Adding a floating txout does not un-remove its transaction, as
+    /// removals are applied before additions. Removed anchors are handled the same way.
+    fn append(&mut self, mut other: Self) {
+        if !other.removed.is_empty() {
+            let removed = &other.removed;
+            self.graph.txs.retain(|tx| !removed.contains(&tx.txid()));
+            self.graph
+                .txouts
+                .retain(|op, _| !removed.contains(&op.txid));
+            self.graph
+                .anchors
+                .retain(|(_, txid)| !removed.contains(txid));
+            self.graph
+                .last_seen
+                .retain(|txid, _| !removed.contains(txid));
+        }
+        if !other.removed_anchors.is_empty() {
+            let removed_anchors = &other.removed_anchors;
+            self.graph
+                .anchors
+                .retain(|anchor| !removed_anchors.contains(anchor));
+        }
+
+        for tx in &other.graph.txs {
+            self.removed.remove(&tx.txid());
+        }
+        if !self.removed_anchors.is_empty() {
+            let added_anchors = &other.graph.anchors;
+            self.removed_anchors
+                .retain(|anchor| !added_anchors.contains(anchor));
+        }
+
+        self.graph.append(other.graph);
+        self.removed.append(&mut other.removed);
+        self.removed_anchors.append(&mut other.removed_anchors);
+    }
+
+    fn is_empty(&self) -> bool {
+        Append::is_empty(&self.graph) && self.removed.is_empty() && self.removed_anchors.is_empty()
+    }
+}
+
+impl<A> Default for ChangeSetWithRemovals<A> {
+    fn default() -> Self {
+        Self {
+            graph: Default::default(),
+            removed: Default::default(),
+            removed_anchors: Default::default(),
+        }
+    }
+}
+
+impl<A> From<ChangeSet<A>> for ChangeSetWithRemovals<A> {
+    fn from(graph: ChangeSet<A>) -> Self {
+        Self {
+            graph,
+            removed: Default::default(),
+            removed_anchors: Default::default(),
+        }
+    }
+}
+
 impl<A> AsRef<TxGraph<A>> for TxGraph<A> {
     fn as_ref(&self) -> &TxGraph<A> {
         self
@@ -1263,6 +2493,65 @@ where
     }
 }
 
+/// List the transactions of `graph` which lose their anchors when blocks of `chain` at or above
+/// `from_height` are invalidated (such as with [`LocalChain::disconnect_from`]).
+///
+/// A transaction is listed if it has an anchor at or above `from_height`, and it has no other
+/// anchor below `from_height` that is in `chain`. Txids are returned in order.
+pub fn invalidated_anchors<A: Anchor>(
+    graph: &TxGraph<A>,
+    chain: &LocalChain,
+    from_height: u32,
+) -> Vec<Txid> {
+    let mut txids = graph
+        .txs
+        .iter()
+        .filter(|(_, (_, anchors, _))| {
+            let mut is_invalidated = false;
+            for anchor_block in anchors.iter().map(Anchor::anchor_block) {
+                if anchor_block.height >= from_height {
+                    is_invalidated = true;
+                } else if chain.blocks().get(&anchor_block.height) == Some(&anchor_block.hash) {
+                    return false;
+                }
+            }
+            is_invalidated
+        })
+        .map(|(&txid, _)| txid)
+        .collect::<Vec<_>>();
+    txids.sort_unstable();
+    txids
+}
+
+/// List the anchors of `graph` which disagree with `chain`.
+///
+/// An anchor disagrees with `chain` if `chain` has a block at the height of the anchor block (or of
+/// the [`confirmation_block`], if known) but with a different hash. These anchors are stale (i.e.
+/// left behind by a reorg) and should be evicted. Anchors at heights which `chain` has no block
+/// for are not listed, as they cannot be checked. Anchors are returned in `(txid, anchor)` order.
+/// They can be evicted with [`TxGraph::remove_anchor`].
+///
+/// [`confirmation_block`]: Anchor::confirmation_block
+pub fn anchors_inconsistent_with<A: Anchor>(
+    graph: &TxGraph<A>,
+    chain: &LocalChain,
+) -> Vec<(Txid, A)> {
+    let disagrees = |block: BlockId| match chain.blocks().get(&block.height) {
+        Some(hash) => *hash != block.hash,
+        None => false,
+    };
+    let mut inconsistent = graph
+        .anchors
+        .iter()
+        .filter(|(anchor, _)| {
+            disagrees(anchor.anchor_block()) || anchor.confirmation_block().map_or(false, disagrees)
+        })
+        .map(|(anchor, txid)| (*txid, anchor.clone()))
+        .collect::<Vec<_>>();
+    inconsistent.sort_unstable();
+    inconsistent
+}
+
 fn tx_outpoint_range(txid: Txid) -> RangeInclusive<OutPoint> {
     OutPoint::new(txid, u32::MIN)..=OutPoint::new(txid, u32::MAX)
 }
@@ -7,10 +7,11 @@ use bdk_chain::{
     indexed_tx_graph::{self, IndexedTxGraph},
     keychain::{self, Balance, KeychainTxOutIndex},
     local_chain::LocalChain,
-    tx_graph, BlockId, ChainPosition, ConfirmationHeightAnchor,
+    tx_graph, Append, BlockId, ChainPosition, ConfirmationHeightAnchor,
 };
 use bitcoin::{
-    secp256k1::Secp256k1, BlockHash, OutPoint, Script, ScriptBuf, Transaction, TxIn, TxOut,
+    hashes::Hash, secp256k1::Secp256k1, BlockHash, OutPoint, Script, ScriptBuf, Transaction, TxIn,
+    TxOut,
 };
 use miniscript::Descriptor;
 
@@ -71,6 +72,7 @@ fn insert_relevant_txs() {
             ..Default::default()
         },
         indexer: keychain::ChangeSet([((), 9_u32)].into()),
+        ..Default::default()
     };
 
     assert_eq!(
@@ -352,7 +354,10 @@ fn test_list_owned_txouts() {
                 immature: 70000,          // immature coinbase
                 trusted_pending: 25000,   // tx3 + tx5
                 untrusted_pending: 20000, // tx4
-                confirmed: 0              // Nothing is confirmed yet
+                confirmed: 0,             // Nothing is confirmed yet
+                immature_coinbase: [(0, 70000)].into(),
+                locked_immature_coinbase: BTreeMap::new(),
+                locked: 0,
             }
         );
     }
@@ -387,7 +392,10 @@ fn test_list_owned_txouts() {
                 immature: 70000,          // immature coinbase
                 trusted_pending: 25000,   // tx3 + tx5
                 untrusted_pending: 20000, // tx4
-                confirmed: 0              // Nothing is confirmed yet
+                confirmed: 0,             // Nothing is confirmed yet
+                immature_coinbase: [(0, 70000)].into(),
+                locked_immature_coinbase: BTreeMap::new(),
+                locked: 0,
             }
         );
     }
@@ -419,7 +427,10 @@ fn test_list_owned_txouts() {
                 immature: 70000,          // immature coinbase
                 trusted_pending: 15000,   // tx5
                 untrusted_pending: 20000, // tx4
-                confirmed: 10000          // tx3 got confirmed
+                confirmed: 10000,         // tx3 got confirmed
+                immature_coinbase: [(0, 70000)].into(),
+                locked_immature_coinbase: BTreeMap::new(),
+                locked: 0,
             }
         );
     }
@@ -450,7 +461,10 @@ fn test_list_owned_txouts() {
                 immature: 70000,          // immature coinbase
                 trusted_pending: 15000,   // tx5
                 untrusted_pending: 20000, // tx4
-                confirmed: 10000          // tx1 got matured
+                confirmed: 10000,         // tx1 got matured
+                immature_coinbase: [(0, 70000)].into(),
+                locked_immature_coinbase: BTreeMap::new(),
+                locked: 0,
             }
         );
     }
@@ -466,8 +480,711 @@ fn test_list_owned_txouts() {
                 immature: 0,              // coinbase matured
                 trusted_pending: 15000,   // tx5
                 untrusted_pending: 20000, // tx4
-                confirmed: 80000          // tx1 + tx3
+                confirmed: 80000,         // tx1 + tx3
+                immature_coinbase: BTreeMap::new(),
+                locked_immature_coinbase: BTreeMap::new(),
+                locked: 0,
             }
         );
     }
 }
+
+#[test]
+fn test_batch_insert_relevant() {
+    const DESCRIPTOR: &str = "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)";
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), DESCRIPTOR)
+        .expect("must be valid");
+    let spk = descriptor.at_derivation_index(0).unwrap().script_pubkey();
+
+    let mut graph = IndexedTxGraph::<BlockId, KeychainTxOutIndex<()>>::default();
+    graph.index.add_keychain((), descriptor);
+    graph.index.set_lookahead(&(), 10);
+
+    // funds `tx_relevant`, but does not pay to an owned spk
+    let tx_parent = Transaction {
+        output: vec![TxOut {
+            value: 30_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..common::new_tx(0)
+    };
+    let tx_relevant = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_parent.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 20_000,
+            script_pubkey: spk,
+        }],
+        ..common::new_tx(1)
+    };
+    let tx_irrelevant = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..common::new_tx(2)
+    };
+
+    let anchor = BlockId {
+        height: 100,
+        hash: h!("block"),
+    };
+    // transactions are not in topological order
+    let changeset = graph.batch_insert_relevant(
+        anchor,
+        [
+            tx_relevant.clone(),
+            tx_irrelevant.clone(),
+            tx_parent.clone(),
+        ],
+    );
+
+    assert_eq!(
+        changeset.graph.txs,
+        [tx_relevant.clone(), tx_parent.clone()].into()
+    );
+    assert_eq!(
+        changeset.graph.anchors,
+        [(anchor, tx_relevant.txid()), (anchor, tx_parent.txid())].into()
+    );
+    assert_eq!(changeset.indexer, keychain::ChangeSet([((), 0)].into()));
+    assert!(graph.graph().get_tx(tx_irrelevant.txid()).is_none());
+    assert!(graph.graph().get_tx(tx_parent.txid()).is_some());
+    assert_eq!(graph.graph().calculate_fee(&tx_relevant), Ok(10_000));
+}
+
+#[test]
+fn test_unconfirmed_txids() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let spend = |previous_output: OutPoint, lt: u32| Transaction {
+        input: vec![TxIn {
+            previous_output,
+            ..Default::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(lt)
+    };
+
+    let tx_confirmed = spend(OutPoint::new(h!("op_a"), 0), 0);
+    let tx_unconfirmed = spend(OutPoint::new(h!("op_b"), 0), 1);
+    // conflicts with `tx_unconfirmed`, but is seen earlier, so it is not canonical
+    let tx_conflicted = spend(OutPoint::new(h!("op_b"), 0), 2);
+
+    let mut graph = IndexedTxGraph::<BlockId, KeychainTxOutIndex<()>>::default();
+    let _ = graph.insert_tx(&tx_confirmed, [tip], None);
+    let _ = graph.insert_tx(&tx_unconfirmed, [], Some(20));
+    let _ = graph.insert_tx(&tx_conflicted, [], Some(10));
+
+    assert_eq!(
+        graph.unconfirmed_txids(&chain, tip),
+        vec![tx_unconfirmed.txid()]
+    );
+}
+
+#[test]
+fn test_group_unspents_by_spk() {
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)")
+        .expect("must be valid");
+    let spk_0 = descriptor.at_derivation_index(0).unwrap().script_pubkey();
+    let spk_1 = descriptor.at_derivation_index(1).unwrap().script_pubkey();
+
+    let mut graph = IndexedTxGraph::<BlockId, KeychainTxOutIndex<()>>::default();
+    graph.index.add_keychain((), descriptor);
+    graph.index.set_lookahead_for_all(5);
+
+    let chain = local_chain![(0, h!("A")), (1, h!("B"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+
+    let txout = |value: u64, script_pubkey: &ScriptBuf| TxOut {
+        value,
+        script_pubkey: script_pubkey.clone(),
+    };
+    let tx_a = Transaction {
+        output: vec![
+            txout(10_000, &spk_0),
+            txout(20_000, &spk_1),
+            txout(30_000, &spk_0),
+            // not owned, so it is omitted
+            txout(40_000, &ScriptBuf::new()),
+        ],
+        ..common::new_tx(0)
+    };
+    // spends the second output of `tx_a`, so spk 1 has no unspent outpoints left
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 1),
+            ..Default::default()
+        }],
+        output: vec![txout(19_000, &ScriptBuf::new())],
+        ..common::new_tx(1)
+    };
+    let _ = graph.insert_tx(&tx_a, [tip], None);
+    let _ = graph.insert_tx(&tx_b, [tip], None);
+
+    let mut expected_ops = vec![OutPoint::new(tx_a.txid(), 0), OutPoint::new(tx_a.txid(), 2)];
+    expected_ops.sort();
+    assert_eq!(
+        graph
+            .index
+            .group_unspents_by_spk(graph.graph(), &chain, tip),
+        [(((), 0), expected_ops)].into()
+    );
+}
+
+#[test]
+fn test_stage_tx() {
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)")
+        .expect("must be valid");
+    let spk_3 = descriptor.at_derivation_index(3).unwrap().script_pubkey();
+
+    let mut graph = IndexedTxGraph::<BlockId, KeychainTxOutIndex<()>>::default();
+    graph.index.add_keychain((), descriptor);
+    graph.index.set_lookahead_for_all(5);
+    let mut direct_graph = IndexedTxGraph::new(graph.index.clone());
+
+    // the tx pays to a lookahead spk, so it reveals up to index 3
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: spk_3,
+        }],
+        ..common::new_tx(0)
+    };
+    let anchor = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+
+    let staged = graph.stage_tx(&tx, Some(anchor));
+    assert_eq!(staged.indexer, keychain::ChangeSet([((), 3)].into()));
+    // nothing is applied by staging
+    assert!(graph.graph().get_tx(tx.txid()).is_none());
+    assert_eq!(graph.index.last_revealed_index(&()), None);
+
+    // applying the staged changeset is the same as inserting the tx directly
+    let direct = direct_graph.insert_tx(&tx, [anchor], None);
+    assert_eq!(staged, direct);
+    graph.apply_changeset(staged);
+    assert_eq!(graph.initial_changeset(), direct_graph.initial_changeset());
+    assert_eq!(graph.index.last_revealed_index(&()), Some(3));
+
+    // staging a known tx only stages the new anchor
+    let new_anchor = BlockId {
+        height: 2,
+        hash: h!("C"),
+    };
+    let staged = graph.stage_tx(&tx, Some(new_anchor));
+    assert!(staged.graph.txs.is_empty());
+    assert_eq!(staged.graph.anchors, [(new_anchor, tx.txid())].into());
+    assert_eq!(staged.indexer, keychain::ChangeSet::default());
+}
+
+#[test]
+fn test_reindex() {
+    let parse = |descriptor: &str| {
+        Descriptor::parse_descriptor(&Secp256k1::signing_only(), descriptor)
+            .expect("must be valid")
+            .0
+    };
+    let descriptor_a = parse("tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)");
+    let descriptor_b = parse("tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/1/*)");
+    let spk_b = descriptor_b.at_derivation_index(2).unwrap().script_pubkey();
+
+    let mut graph = IndexedTxGraph::<BlockId, KeychainTxOutIndex<u8>>::default();
+    graph.index.add_keychain(0, descriptor_a);
+
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: 20_000,
+            script_pubkey: spk_b.clone(),
+        }],
+        ..common::new_tx(0)
+    };
+    let floating_op = OutPoint::new(h!("floating"), 0);
+    let _ = graph.insert_tx(&tx, [], Some(100));
+    let _ = graph.insert_txout(
+        floating_op,
+        &TxOut {
+            value: 5_000,
+            script_pubkey: spk_b,
+        },
+    );
+
+    let chain = local_chain![(0, h!("A"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let balance = |graph: &IndexedTxGraph<BlockId, KeychainTxOutIndex<u8>>| {
+        graph.graph().balance(
+            &chain,
+            tip,
+            graph.index.outpoints().iter().cloned(),
+            |_, _| true,
+        )
+    };
+    assert_eq!(balance(&graph), Balance::default());
+
+    // the new keychain only applies to outputs indexed from now on
+    graph.index.add_keychain(1, descriptor_b);
+    graph.index.set_lookahead(&1, 5);
+    assert_eq!(balance(&graph), Balance::default());
+
+    let changeset = graph.reindex();
+    assert!(changeset.graph.is_empty());
+    assert_eq!(changeset.indexer, keychain::ChangeSet([(1, 2)].into()));
+    assert_eq!(
+        graph.index.txouts_of_keychain(&1).collect::<BTreeSet<_>>(),
+        [(2, floating_op), (2, OutPoint::new(tx.txid(), 0))].into()
+    );
+    // floating txouts are not part of the canonical balance
+    assert_eq!(
+        balance(&graph),
+        Balance {
+            trusted_pending: 20_000,
+            ..Default::default()
+        }
+    );
+
+    // reindexing again does not result in further changes
+    assert!(graph.reindex().is_empty());
+}
+
+#[test]
+fn test_prune_spent_before() {
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)")
+        .expect("must be valid");
+    let spk_0 = descriptor.at_derivation_index(0).unwrap().script_pubkey();
+    let spk_1 = descriptor.at_derivation_index(1).unwrap().script_pubkey();
+
+    let mut index = KeychainTxOutIndex::<()>::default();
+    index.add_keychain((), descriptor);
+    index.set_lookahead_for_all(5);
+    let mut graph = IndexedTxGraph::<BlockId, _>::new(index.clone());
+
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let anchor = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+
+    // tx_a <- tx_b <- tx_c (unspent): `tx_a` is pruned, `tx_b` is kept as the prevout of `tx_c`
+    let tx_a = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: spk_0,
+        }],
+        ..common::new_tx(0)
+    };
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 9_000,
+            script_pubkey: spk_1,
+        }],
+        ..common::new_tx(1)
+    };
+    let tx_c = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_b.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(2)
+    };
+    let mut persisted = graph.insert_tx(&tx_a, [anchor], None);
+    for tx in [&tx_b, &tx_c] {
+        persisted.append(graph.insert_tx(tx, [anchor], None));
+    }
+
+    let changeset = graph.prune_spent_before(2, &chain, tip);
+    assert_eq!(changeset.removed, [tx_a.txid()].into());
+    assert!(changeset.graph.is_empty());
+    assert!(graph.graph().get_tx(tx_a.txid()).is_none());
+    // the txouts of the pruned tx are no longer indexed
+    assert_eq!(
+        graph.index.outpoints(),
+        &[(((), 1), OutPoint::new(tx_b.txid(), 0))].into()
+    );
+    // the script pubkey stays used so that it is not handed out again
+    assert!(graph.index.is_used(&((), 0)));
+
+    // the removal is persisted, so the loaded graph is the same as the pruned graph
+    persisted.append(changeset);
+    assert_eq!(persisted.removed, [tx_a.txid()].into());
+    assert!(persisted
+        .graph
+        .txs
+        .iter()
+        .all(|tx| tx.txid() != tx_a.txid()));
+    let mut loaded = IndexedTxGraph::<BlockId, _>::new(index);
+    loaded.apply_changeset(persisted);
+    assert_eq!(loaded.initial_changeset(), graph.initial_changeset());
+    assert_eq!(loaded.index.outpoints(), graph.index.outpoints());
+}
+
+#[test]
+fn test_evict_unconfirmed_before() {
+    let (descriptor, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)")
+        .expect("must be valid");
+    let mut index = KeychainTxOutIndex::<()>::default();
+    index.add_keychain((), descriptor.clone());
+    index.set_lookahead_for_all(5);
+    let mut graph = IndexedTxGraph::<BlockId, _>::new(index.clone());
+
+    let chain = local_chain![(0, h!("A"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+
+    let tx = |i: u32| Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: descriptor.at_derivation_index(i).unwrap().script_pubkey(),
+        }],
+        ..common::new_tx(i)
+    };
+    // `tx_stale` is dropped, `tx_unseen` was never seen (e.g. it is not yet broadcast)
+    let (tx_stale, tx_recent, tx_unseen) = (tx(0), tx(1), tx(2));
+    let mut persisted = graph.insert_tx(&tx_stale, [], Some(10));
+    persisted.append(graph.insert_tx(&tx_recent, [], Some(100)));
+    persisted.append(graph.insert_tx(&tx_unseen, [], None));
+
+    let changeset = graph.evict_unconfirmed_before(50, &chain, tip);
+    assert_eq!(changeset.removed, [tx_stale.txid()].into());
+    assert_eq!(
+        graph.index.outpoints(),
+        &[
+            (((), 1), OutPoint::new(tx_recent.txid(), 0)),
+            (((), 2), OutPoint::new(tx_unseen.txid(), 0)),
+        ]
+        .into()
+    );
+    // the script pubkey of the evicted tx stays used so that it is not handed out again
+    assert!(graph.index.is_used(&((), 0)));
+    assert_ne!(
+        graph.index.clone().next_unused_spk(&()).0 .0,
+        0,
+        "must not reuse the spk of the evicted tx"
+    );
+
+    // the eviction is persisted, so the loaded graph is the same as the evicted graph
+    persisted.append(changeset);
+    let mut loaded = IndexedTxGraph::<BlockId, _>::new(index);
+    loaded.apply_changeset(persisted);
+    assert_eq!(loaded.initial_changeset(), graph.initial_changeset());
+    assert_eq!(loaded.index.outpoints(), graph.index.outpoints());
+}
+
+#[test]
+fn test_remove_anchor() {
+    let mut graph = IndexedTxGraph::<BlockId, KeychainTxOutIndex<()>>::default();
+    let tx = common::new_tx(0);
+    let stale = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+    let reorged = BlockId {
+        height: 1,
+        hash: h!("B'"),
+    };
+    let mut persisted = graph.insert_tx(&tx, [stale, reorged], None);
+
+    let changeset = graph.remove_anchor(tx.txid(), &stale);
+    assert_eq!(changeset.removed_anchors, [(stale, tx.txid())].into());
+    assert_eq!(graph.graph().all_anchors(), &[(reorged, tx.txid())].into());
+    // removing it again does nothing
+    assert!(graph.remove_anchor(tx.txid(), &stale).is_empty());
+
+    // the removal is persisted, so the loaded graph is the same
+    persisted.append(changeset);
+    assert_eq!(persisted.graph.anchors, [(reorged, tx.txid())].into());
+    let mut loaded = IndexedTxGraph::<BlockId, KeychainTxOutIndex<()>>::default();
+    loaded.apply_changeset(persisted);
+    assert_eq!(loaded.initial_changeset(), graph.initial_changeset());
+}
+
+#[test]
+fn test_changeset_check_consistency() {
+    let txid = common::new_tx(0).txid();
+    let block = |height: u32, tag: &str| BlockId {
+        height,
+        hash: BlockHash::hash(tag.as_bytes()),
+    };
+    let changeset_with_anchors = |anchors: &[BlockId]| {
+        indexed_tx_graph::ChangeSet::<BlockId, ()>::from(tx_graph::ChangeSet {
+            anchors: anchors.iter().map(|&anchor| (anchor, txid)).collect(),
+            ..Default::default()
+        })
+    };
+
+    assert_eq!(changeset_with_anchors(&[]).check_consistency(), Ok(()));
+    assert_eq!(
+        changeset_with_anchors(&[block(2, "A")]).check_consistency(),
+        Ok(())
+    );
+    // anchors of the same height (as a result of a reorg) are consistent
+    assert_eq!(
+        changeset_with_anchors(&[block(2, "A"), block(2, "B")]).check_consistency(),
+        Ok(())
+    );
+    assert_eq!(
+        changeset_with_anchors(&[block(2, "A"), block(5, "B")]).check_consistency(),
+        Err(indexed_tx_graph::ConsistencyError {
+            txid,
+            first_height: 2,
+            second_height: 5,
+        })
+    );
+
+    // `append` is not affected
+    let mut changeset = changeset_with_anchors(&[block(2, "A")]);
+    changeset.append(changeset_with_anchors(&[block(5, "B")]));
+    assert!(changeset.check_consistency().is_err());
+}
+
+#[test]
+fn test_balance_per_keychain() {
+    let local_chain = LocalChain::from(
+        (0..10)
+            .map(|i| (i as u32, h!("random")))
+            .collect::<BTreeMap<u32, BlockHash>>(),
+    );
+    let chain_tip = local_chain.tip().expect("must have tip").block_id();
+
+    let (desc_1, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), "tr(tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj/86'/1'/0'/0/*)").unwrap();
+    let (desc_2, _) = Descriptor::parse_descriptor(&Secp256k1::signing_only(), "tr(tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj/86'/1'/0'/1/*)").unwrap();
+
+    let mut graph =
+        IndexedTxGraph::<ConfirmationHeightAnchor, KeychainTxOutIndex<String>>::default();
+    graph.index.add_keychain("keychain_1".into(), desc_1);
+    graph.index.add_keychain("keychain_2".into(), desc_2);
+
+    let ((_, spk_1), _) = graph.index.reveal_next_spk(&"keychain_1".to_string());
+    let spk_1 = spk_1.to_owned();
+    let ((_, spk_2), _) = graph.index.reveal_next_spk(&"keychain_2".to_string());
+    let spk_2 = spk_2.to_owned();
+
+    // immature coinbase paying to keychain_1, confirmed at height 1
+    let tx_coinbase = Transaction {
+        input: vec![TxIn::default()],
+        output: vec![TxOut {
+            value: 50_000,
+            script_pubkey: spk_1.clone(),
+        }],
+        ..common::new_tx(0)
+    };
+    // confirmed tx paying to both keychains and to an unrelated spk
+    let tx_confirmed = Transaction {
+        output: vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: spk_1,
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: spk_2.clone(),
+            },
+            TxOut {
+                value: 40_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+        ],
+        ..common::new_tx(1)
+    };
+    // unconfirmed tx paying to keychain_2
+    let tx_unconfirmed = Transaction {
+        output: vec![TxOut {
+            value: 5_000,
+            script_pubkey: spk_2,
+        }],
+        ..common::new_tx(2)
+    };
+
+    let anchor_at = |height: u32| ConfirmationHeightAnchor {
+        anchor_block: chain_tip,
+        confirmation_height: height,
+    };
+    let _ = graph.insert_relevant_txs(
+        [
+            (&tx_coinbase, Some(anchor_at(1))),
+            (&tx_confirmed, Some(anchor_at(2))),
+        ],
+        None,
+    );
+    let _ = graph.insert_relevant_txs([(&tx_unconfirmed, None)], Some(100));
+
+    let balances =
+        graph
+            .index
+            .balance_per_keychain(graph.graph(), &local_chain, chain_tip, |keychain, _| {
+                keychain == "keychain_1"
+            });
+    assert_eq!(
+        balances,
+        [
+            (
+                "keychain_1".to_string(),
+                Balance {
+                    immature: 50_000,
+                    immature_coinbase: [(1, 50_000)].into(),
+                    locked_immature_coinbase: BTreeMap::new(),
+                    trusted_pending: 0,
+                    untrusted_pending: 0,
+                    confirmed: 10_000,
+                    locked: 0,
+                }
+            ),
+            (
+                "keychain_2".to_string(),
+                Balance {
+                    immature: 0,
+                    immature_coinbase: BTreeMap::new(),
+                    locked_immature_coinbase: BTreeMap::new(),
+                    trusted_pending: 0,
+                    untrusted_pending: 5_000,
+                    confirmed: 20_000,
+                    locked: 0,
+                }
+            ),
+        ]
+        .into()
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_wallet_update_serde_round_trip() {
+    use bdk_chain::{keychain::WalletUpdate, local_chain::CheckPoint};
+    use serde_json_crate as serde_json;
+
+    let tx_a = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..common::new_tx(0)
+    };
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(1)
+    };
+    let tip = CheckPoint::new(BlockId {
+        height: 1,
+        hash: h!("B"),
+    })
+    .push(BlockId {
+        height: 3,
+        hash: h!("D"),
+    })
+    .expect("must push");
+
+    let mut update =
+        WalletUpdate::<String, ConfirmationHeightAnchor>::new(bdk_chain::local_chain::Update {
+            tip,
+            introduce_older_blocks: true,
+        });
+    update
+        .last_active_indices
+        .insert("keychain_1".to_string(), 5);
+    let _ = update.graph.insert_tx(tx_a.clone());
+    let _ = update.graph.insert_tx(tx_b.clone());
+    let _ = update
+        .graph
+        .insert_txout(OutPoint::new(h!("floating"), 1), TxOut::default());
+    let _ = update.graph.insert_anchor(
+        tx_a.txid(),
+        ConfirmationHeightAnchor {
+            anchor_block: BlockId {
+                height: 3,
+                hash: h!("D"),
+            },
+            confirmation_height: 2,
+        },
+    );
+    let _ = update.graph.insert_seen_at(tx_b.txid(), 42);
+
+    let serialized = serde_json::to_string(&update).expect("must serialize");
+    let deserialized: WalletUpdate<String, ConfirmationHeightAnchor> =
+        serde_json::from_str(&serialized).expect("must deserialize");
+    assert_eq!(deserialized.last_active_indices, update.last_active_indices);
+    assert_eq!(
+        deserialized.chain.introduce_older_blocks,
+        update.chain.introduce_older_blocks
+    );
+
+    // applying the deserialized update results in the same state and changesets
+    let apply = |update: WalletUpdate<String, ConfirmationHeightAnchor>| {
+        let mut chain = LocalChain::default();
+        let chain_changeset = chain.apply_update(update.chain).expect("must connect");
+        let mut graph = tx_graph::TxGraph::<ConfirmationHeightAnchor>::default();
+        let graph_changeset = graph.apply_update(update.graph);
+        (chain, chain_changeset, graph, graph_changeset)
+    };
+    assert_eq!(apply(deserialized), apply(update));
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_wallet_changeset_json_round_trip() {
+    use bdk_chain::keychain::WalletChangeSet;
+
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..common::new_tx(0)
+    };
+    let mut graph = tx_graph::ChangeSet::<ConfirmationHeightAnchor>::default();
+    graph.txs.insert(tx.clone());
+    graph
+        .txouts
+        .insert(OutPoint::new(h!("floating"), 1), TxOut::default());
+    graph.anchors.insert((
+        ConfirmationHeightAnchor {
+            anchor_block: BlockId {
+                height: 2,
+                hash: h!("C"),
+            },
+            confirmation_height: 1,
+        },
+        tx.txid(),
+    ));
+    graph.last_seen.insert(h!("floating"), 100);
+
+    let changeset = WalletChangeSet::<String, ConfirmationHeightAnchor> {
+        chain: [(1, Some(h!("B"))), (2, Some(h!("C"))), (3, None)].into(),
+        indexed_tx_graph: indexed_tx_graph::ChangeSet {
+            graph,
+            indexer: keychain::ChangeSet([("external".to_string(), 5)].into()),
+            removed: [h!("pruned")].into(),
+            removed_anchors: [(
+                ConfirmationHeightAnchor {
+                    anchor_block: BlockId {
+                        height: 1,
+                        hash: h!("stale"),
+                    },
+                    confirmation_height: 1,
+                },
+                tx.txid(),
+            )]
+            .into(),
+        },
+    };
+
+    let json = changeset.to_json().expect("must serialize");
+    assert!(json.contains('\n'), "json must be pretty-printed");
+    assert_eq!(
+        WalletChangeSet::<String, ConfirmationHeightAnchor>::from_json(&json)
+            .expect("must deserialize"),
+        changeset
+    );
+    assert!(WalletChangeSet::<String, ConfirmationHeightAnchor>::from_json("{}").is_err());
+}
@@ -4,8 +4,8 @@
 mod common;
 use bdk_chain::{
     collections::BTreeMap,
-    keychain::{self, KeychainTxOutIndex},
-    Append,
+    keychain::{self, KeychainTxOutIndex, ReplaceDescriptorError},
+    Append, BIP32_MAX_INDEX,
 };
 
 use bitcoin::{secp256k1::Secp256k1, OutPoint, ScriptBuf, Transaction, TxOut};
@@ -61,6 +61,27 @@ fn test_set_all_derivation_indices() {
     assert_eq!(txout_index.initial_changeset().as_inner(), &derive_to);
 }
 
+#[test]
+fn test_keychains() {
+    let mut txout_index = KeychainTxOutIndex::<TestKeychain>::default();
+    assert_eq!(txout_index.keychains().len(), 0);
+
+    let (_, external_desc, internal_desc) = init_txout_index();
+    // add keychains in reverse order
+    txout_index.add_keychain(TestKeychain::Internal, internal_desc.clone());
+    txout_index.add_keychain(TestKeychain::External, external_desc.clone());
+
+    assert_eq!(
+        txout_index.keychains().iter().collect::<Vec<_>>(),
+        vec![
+            (&TestKeychain::External, &external_desc),
+            (&TestKeychain::Internal, &internal_desc)
+        ],
+    );
+    // no spks are derived by adding keychains
+    assert_eq!(txout_index.inner().all_spks().len(), 0);
+}
+
 #[test]
 fn test_lookahead() {
     let (mut txout_index, external_desc, internal_desc) = init_txout_index();
@@ -218,6 +239,240 @@ fn test_lookahead() {
     }
 }
 
+#[test]
+fn test_revealed_spks_of_keychain_excludes_lookahead() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::External, 10);
+    assert_eq!(
+        txout_index
+            .revealed_spks_of_keychain(&TestKeychain::External)
+            .count(),
+        0
+    );
+
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 2);
+    assert_eq!(
+        txout_index
+            .revealed_spks_of_keychain(&TestKeychain::External)
+            .map(|(i, spk)| (i, spk.to_owned()))
+            .collect::<Vec<_>>(),
+        (0..=2)
+            .map(|i| (i, spk_at_index(&external_desc, i)))
+            .collect::<Vec<_>>(),
+    );
+    // the lookahead spks are still stored
+    assert_eq!(txout_index.inner().all_spks().len(), 13);
+}
+
+#[test]
+fn test_apply_changeset_and_reveal() {
+    let (mut txout_index, external_desc, internal_desc) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::External, 5);
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 1);
+
+    let revealed = txout_index.apply_changeset_and_reveal(keychain::ChangeSet(
+        [(TestKeychain::External, 3), (TestKeychain::Internal, 0)].into(),
+    ));
+    assert_eq!(
+        revealed,
+        [
+            (
+                TestKeychain::External,
+                (2..=3)
+                    .map(|i| (i, spk_at_index(&external_desc, i)))
+                    .collect::<Vec<_>>()
+            ),
+            (
+                TestKeychain::Internal,
+                vec![(0, spk_at_index(&internal_desc, 0))]
+            ),
+        ]
+        .into()
+    );
+    assert_eq!(
+        txout_index.last_revealed_indices(),
+        &[(TestKeychain::External, 3), (TestKeychain::Internal, 0)].into()
+    );
+
+    // a changeset that does not advance any index reveals nothing
+    let revealed = txout_index
+        .apply_changeset_and_reveal(keychain::ChangeSet([(TestKeychain::External, 2)].into()));
+    assert!(revealed.is_empty());
+}
+
+#[test]
+fn test_reveal_range() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 1);
+
+    // only spks in the range are returned, but the indices below it are revealed too
+    let (spks, changeset) = txout_index.reveal_range(&TestKeychain::External, 5..=8);
+    assert_eq!(
+        spks,
+        (5..=8)
+            .map(|i| (i, spk_at_index(&external_desc, i)))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(changeset.as_inner(), &[(TestKeychain::External, 8)].into());
+    assert_eq!(
+        txout_index
+            .revealed_spks_of_keychain(&TestKeychain::External)
+            .count(),
+        9
+    );
+
+    // a range overlapping revealed indices only returns the newly revealed spks
+    let (spks, changeset) = txout_index.reveal_range(&TestKeychain::External, 7..=9);
+    assert_eq!(spks, vec![(9, spk_at_index(&external_desc, 9))]);
+    assert_eq!(changeset.as_inner(), &[(TestKeychain::External, 9)].into());
+
+    // a range that is already revealed is a no-op
+    let (spks, changeset) = txout_index.reveal_range(&TestKeychain::External, 2..=4);
+    assert!(spks.is_empty());
+    assert!(changeset.is_empty());
+}
+
+#[test]
+fn test_lookahead_for() {
+    let (mut txout_index, _, _) = init_txout_index();
+    assert_eq!(txout_index.lookahead_for(&TestKeychain::External), 0);
+
+    txout_index.set_lookahead(&TestKeychain::External, 100);
+    txout_index.set_lookahead(&TestKeychain::Internal, 5);
+    assert_eq!(txout_index.lookahead_for(&TestKeychain::External), 100);
+    assert_eq!(txout_index.lookahead_for(&TestKeychain::Internal), 5);
+
+    let count_stored = |txout_index: &KeychainTxOutIndex<TestKeychain>, keychain| {
+        txout_index
+            .inner()
+            .all_spks()
+            .keys()
+            .filter(|(k, _)| *k == keychain)
+            .count()
+    };
+    assert_eq!(count_stored(&txout_index, TestKeychain::External), 100);
+    assert_eq!(count_stored(&txout_index, TestKeychain::Internal), 5);
+    assert_eq!(txout_index.inner().all_spks().len(), 105);
+
+    // reducing the lookahead should never remove stored spks
+    txout_index.set_lookahead(&TestKeychain::External, 10);
+    assert_eq!(txout_index.lookahead_for(&TestKeychain::External), 10);
+    assert_eq!(count_stored(&txout_index, TestKeychain::External), 100);
+}
+
+#[test]
+fn test_apply_changeset_is_idempotent_and_order_independent() {
+    use bdk_chain::Append;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let keychains = [TestKeychain::External, TestKeychain::Internal];
+
+    let new_index = || {
+        let (mut txout_index, _, _) = init_txout_index();
+        txout_index.set_lookahead_for_all(5);
+        txout_index
+    };
+    // the state of the index which must be canonical: revealed indices and all stored spks
+    let state = |txout_index: &KeychainTxOutIndex<TestKeychain>| {
+        (
+            txout_index.last_revealed_indices().clone(),
+            txout_index.inner().all_spks().clone(),
+        )
+    };
+
+    for _ in 0..20 {
+        let mut changesets = Vec::new();
+        for _ in 0..rng.gen_range(1..6) {
+            let mut changeset = keychain::ChangeSet::default();
+            for keychain in &keychains {
+                if rng.gen_bool(0.5) {
+                    changeset.0.insert(keychain.clone(), rng.gen_range(0..30));
+                }
+            }
+            changesets.push(changeset);
+        }
+
+        let mut aggregate = keychain::ChangeSet::default();
+        for changeset in &changesets {
+            aggregate.append(changeset.clone());
+        }
+        let mut expected_index = new_index();
+        expected_index.apply_changeset(aggregate.clone());
+        let expected = state(&expected_index);
+        assert_eq!(&expected.0, aggregate.as_inner());
+
+        // applying each changeset twice
+        let mut txout_index = new_index();
+        for changeset in &changesets {
+            txout_index.apply_changeset(changeset.clone());
+            txout_index.apply_changeset(changeset.clone());
+        }
+        assert_eq!(state(&txout_index), expected);
+
+        // applying the changesets in a different order
+        changesets.shuffle(&mut rng);
+        let mut txout_index = new_index();
+        for changeset in changesets {
+            txout_index.apply_changeset(changeset);
+        }
+        assert_eq!(state(&txout_index), expected);
+
+        // re-applying the aggregate changeset is a no-op
+        expected_index.apply_changeset(aggregate);
+        assert_eq!(state(&expected_index), expected);
+    }
+}
+
+#[test]
+fn test_lookahead_to_target() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::External, 5);
+
+    let count_stored = |txout_index: &KeychainTxOutIndex<TestKeychain>| {
+        txout_index
+            .inner()
+            .all_spks()
+            .keys()
+            .filter(|(k, _)| *k == TestKeychain::External)
+            .count()
+    };
+    assert_eq!(count_stored(&txout_index), 5);
+
+    // spks up to and including the target are stored, but not revealed
+    txout_index.lookahead_to_target(&TestKeychain::External, 20);
+    assert_eq!(count_stored(&txout_index), 21);
+    assert_eq!(
+        txout_index.last_revealed_index(&TestKeychain::External),
+        None
+    );
+    assert_eq!(
+        txout_index
+            .inner()
+            .spk_at_index(&(TestKeychain::External, 20))
+            .map(|spk| spk.to_owned()),
+        Some(spk_at_index(&external_desc, 20))
+    );
+
+    // a target which is already stored is a no-op
+    txout_index.lookahead_to_target(&TestKeychain::External, 10);
+    assert_eq!(count_stored(&txout_index), 21);
+
+    // revealing within the stored range does not derive more spks
+    let (spks, _) = txout_index.reveal_to_target(&TestKeychain::External, 15);
+    assert_eq!(spks.count(), 16);
+    assert_eq!(
+        txout_index.last_revealed_index(&TestKeychain::External),
+        Some(15)
+    );
+    assert_eq!(count_stored(&txout_index), 21);
+
+    // revealing past the stored range keeps the lookahead after the target
+    let (spks, _) = txout_index.reveal_to_target(&TestKeychain::External, 25);
+    assert_eq!(spks.count(), 10);
+    assert_eq!(count_stored(&txout_index), 31);
+}
+
 // when:
 // - scanning txouts with spks above last stored index
 // expect:
@@ -277,6 +532,81 @@ fn test_scan_with_lookahead() {
     assert!(changeset.is_empty());
 }
 
+#[test]
+fn test_peek_reveal_to_target_multi() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 5);
+
+    let mut other_index = KeychainTxOutIndex::<TestKeychain>::default();
+    other_index.add_keychain(TestKeychain::External, external_desc);
+
+    let targets: BTreeMap<_, _> =
+        [(TestKeychain::External, 3), (TestKeychain::Internal, 50)].into();
+
+    // the external keychain does not go backwards, the internal keychain has nothing revealed yet
+    assert_eq!(
+        txout_index.peek_reveal_to_target_multi(&targets),
+        [
+            (TestKeychain::External, (Some(5), Some(5))),
+            (TestKeychain::Internal, (None, Some(50))),
+        ]
+        .into()
+    );
+    // the internal keychain is not tracked by `other_index` so nothing can be revealed
+    assert_eq!(
+        other_index.peek_reveal_to_target_multi(&targets),
+        [
+            (TestKeychain::External, (None, Some(3))),
+            (TestKeychain::Internal, (None, None)),
+        ]
+        .into()
+    );
+
+    // peeking does not mutate and agrees with the actual reveal
+    assert_eq!(
+        txout_index.last_revealed_index(&TestKeychain::Internal),
+        None
+    );
+    let _ = txout_index.reveal_to_target_multi(&targets);
+    assert_eq!(
+        txout_index.last_revealed_indices(),
+        &[(TestKeychain::External, 5), (TestKeychain::Internal, 50)].into()
+    );
+}
+
+#[test]
+fn test_peek_next_unused_spk() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::External, 10);
+
+    // nothing is revealed, lookahead spks must not be returned
+    assert_eq!(
+        txout_index.peek_next_unused_spk(&TestKeychain::External),
+        None
+    );
+
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 2);
+    let spk_1 = spk_at_index(&external_desc, 1);
+    assert!(txout_index.mark_used(&TestKeychain::External, 0));
+    assert_eq!(
+        txout_index.peek_next_unused_spk(&TestKeychain::External),
+        Some((1, spk_1.as_script()))
+    );
+
+    // all revealed spks are used
+    assert!(txout_index.mark_used(&TestKeychain::External, 1));
+    assert!(txout_index.mark_used(&TestKeychain::External, 2));
+    assert_eq!(
+        txout_index.peek_next_unused_spk(&TestKeychain::External),
+        None
+    );
+    assert_eq!(
+        txout_index.last_revealed_index(&TestKeychain::External),
+        Some(2),
+        "peeking must not reveal new spks"
+    );
+}
+
 #[test]
 #[rustfmt::skip]
 fn test_wildcard_derivations() {
@@ -336,6 +666,172 @@ fn test_wildcard_derivations() {
     assert_eq!(changeset.as_inner(), &[(TestKeychain::External, 27)].into());
 }
 
+#[test]
+fn test_single_key_descriptor_alongside_ranged_keychain() {
+    let (_, external_desc, _) = init_txout_index();
+    let secp = Secp256k1::signing_only();
+    let (fixed_desc, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(
+        &secp,
+        "pkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+    )
+    .unwrap();
+    let fixed_spk = spk_at_index(&fixed_desc, 0);
+
+    let mut txout_index = KeychainTxOutIndex::<TestKeychain>::default();
+    txout_index.add_keychain(TestKeychain::External, external_desc.clone());
+    txout_index.add_keychain(TestKeychain::Internal, fixed_desc);
+    txout_index.set_lookahead_for_all(10);
+
+    let (revealed, changeset) = txout_index.reveal_to_target(&TestKeychain::Internal, 5);
+    assert_eq!(revealed.collect::<Vec<_>>(), vec![(0, fixed_spk.clone())]);
+    assert_eq!(changeset.as_inner(), &[(TestKeychain::Internal, 0)].into());
+
+    // revealing past index 0 is a no-op
+    let (spk, changeset) = txout_index.reveal_next_spk(&TestKeychain::Internal);
+    assert_eq!(spk, (0, fixed_spk.as_script()));
+    assert!(changeset.is_empty());
+    assert_eq!(txout_index.next_index(&TestKeychain::Internal), (0, false));
+
+    // only one spk is ever derived and stored, regardless of the lookahead
+    let mut all_spks = txout_index.spks_of_all_keychains();
+    assert_eq!(
+        all_spks
+            .remove(&TestKeychain::Internal)
+            .expect("must exist")
+            .collect::<Vec<_>>(),
+        vec![(0, fixed_spk)]
+    );
+    assert_eq!(
+        txout_index
+            .inner()
+            .all_spks()
+            .keys()
+            .filter(|(k, _)| k == &TestKeychain::Internal)
+            .count(),
+        1
+    );
+
+    // the ranged keychain is unaffected
+    let (revealed, _) = txout_index.reveal_to_target(&TestKeychain::External, 2);
+    assert_eq!(
+        revealed.collect::<Vec<_>>(),
+        (0..=2)
+            .map(|i| (i, spk_at_index(&external_desc, i)))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_used_indices() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 10);
+
+    let txout = |index: u32| TxOut {
+        value: 10_000,
+        script_pubkey: spk_at_index(&external_desc, index),
+    };
+    let tx = Transaction {
+        output: vec![txout(7), txout(2), txout(5), txout(2)],
+        ..common::new_tx(0)
+    };
+    let _ = txout_index.scan(&tx);
+    // only marked as used, there is no txout
+    txout_index.mark_used(&TestKeychain::External, 3);
+
+    assert_eq!(
+        txout_index
+            .used_indices(&TestKeychain::External)
+            .collect::<Vec<_>>(),
+        vec![2, 5, 7]
+    );
+    assert_eq!(
+        txout_index
+            .used_indices(&TestKeychain::Internal)
+            .collect::<Vec<_>>(),
+        Vec::<u32>::new()
+    );
+}
+
+#[test]
+fn test_unused_count() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::External, 10);
+    // the lookahead is not counted
+    assert_eq!(txout_index.unused_count(&TestKeychain::External), 0);
+
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 4);
+    assert_eq!(txout_index.unused_count(&TestKeychain::External), 5);
+    assert_eq!(txout_index.unused_count(&TestKeychain::Internal), 0);
+
+    // a txout to a revealed spk and a manually marked spk are used
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: spk_at_index(&external_desc, 1),
+        }],
+        ..common::new_tx(0)
+    };
+    let _ = txout_index.scan(&tx);
+    assert!(txout_index.mark_used(&TestKeychain::External, 3));
+    assert_eq!(txout_index.unused_count(&TestKeychain::External), 3);
+
+    // a txout to a spk in the lookahead reveals up to it
+    let tx = Transaction {
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: spk_at_index(&external_desc, 7),
+        }],
+        ..common::new_tx(1)
+    };
+    let _ = txout_index.scan(&tx);
+    assert_eq!(txout_index.unused_count(&TestKeychain::External), 5);
+}
+
+#[test]
+fn test_spk_at_index() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::External, 5);
+
+    // stored and unstored indices are both derived, without being stored or revealed
+    for index in [0, 3, 100, BIP32_MAX_INDEX] {
+        assert_eq!(
+            txout_index.spk_at_index(&TestKeychain::External, index),
+            Some(spk_at_index(&external_desc, index))
+        );
+    }
+    assert_eq!(txout_index.inner().all_spks().len(), 5);
+    assert_eq!(
+        txout_index.last_revealed_index(&TestKeychain::External),
+        None
+    );
+
+    // indices beyond the descriptor's range cannot be derived
+    assert_eq!(
+        txout_index.spk_at_index(&TestKeychain::External, BIP32_MAX_INDEX + 1),
+        None
+    );
+    assert_eq!(
+        txout_index.spk_at_index(&TestKeychain::External, u32::MAX),
+        None
+    );
+
+    // unknown keychains and non-wildcard descriptors
+    let secp = Secp256k1::signing_only();
+    let (fixed_desc, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(
+        &secp,
+        "pkh(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+    )
+    .unwrap();
+    let mut txout_index = KeychainTxOutIndex::<TestKeychain>::default();
+    txout_index.add_keychain(TestKeychain::Internal, fixed_desc.clone());
+    assert_eq!(txout_index.spk_at_index(&TestKeychain::External, 0), None);
+    assert_eq!(
+        txout_index.spk_at_index(&TestKeychain::Internal, 0),
+        Some(spk_at_index(&fixed_desc, 0))
+    );
+    assert_eq!(txout_index.spk_at_index(&TestKeychain::Internal, 1), None);
+}
+
 #[test]
 fn test_non_wildcard_derivations() {
     let mut txout_index = KeychainTxOutIndex::<TestKeychain>::default();
@@ -393,3 +889,155 @@ fn test_non_wildcard_derivations() {
         1,
     );
 }
+
+#[test]
+fn test_replace_descriptor() {
+    let (mut txout_index, external_desc, _) = init_txout_index();
+    txout_index.set_lookahead_for_all(5);
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 2);
+
+    let op = OutPoint::new(h!("tx"), 0);
+    let txout = TxOut {
+        value: 10_000,
+        script_pubkey: spk_at_index(&external_desc, 1),
+    };
+    let _ = txout_index.scan_txout(op, &txout);
+    let count_stored = |txout_index: &KeychainTxOutIndex<TestKeychain>| {
+        txout_index
+            .inner()
+            .all_spks()
+            .keys()
+            .filter(|(k, _)| *k == TestKeychain::External)
+            .count()
+    };
+
+    // the same keys without the key origin derive the same spks, so the txouts are kept
+    let (no_origin_desc, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&Secp256k1::signing_only(), "tr(xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/0/*)").unwrap();
+    assert_ne!(no_origin_desc, external_desc);
+    assert_eq!(
+        txout_index.replace_descriptor(&TestKeychain::External, no_origin_desc.clone(), false),
+        Ok(keychain::ChangeSet([(TestKeychain::External, 2)].into()))
+    );
+    assert_eq!(
+        txout_index.keychains().get(&TestKeychain::External),
+        Some(&no_origin_desc)
+    );
+    assert_eq!(
+        txout_index.txout(op),
+        Some((&(TestKeychain::External, 1), &txout))
+    );
+    assert_eq!(count_stored(&txout_index), 8);
+
+    let (other_desc, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&Secp256k1::signing_only(), "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/2/*)").unwrap();
+
+    // different spks are an error by default and nothing is changed
+    assert_eq!(
+        txout_index.replace_descriptor(&TestKeychain::External, other_desc.clone(), false),
+        Err(ReplaceDescriptorError {
+            mismatched_indices: vec![0, 1, 2]
+        })
+    );
+    assert_eq!(
+        txout_index.keychains().get(&TestKeychain::External),
+        Some(&no_origin_desc)
+    );
+    assert!(txout_index.txout(op).is_some());
+
+    // forcing clears the txouts and re-derives the spks, but keeps the revealed index
+    assert_eq!(
+        txout_index.replace_descriptor(&TestKeychain::External, other_desc.clone(), true),
+        Ok(keychain::ChangeSet([(TestKeychain::External, 2)].into()))
+    );
+    assert_eq!(txout_index.txout(op), None);
+    assert_eq!(
+        txout_index.last_revealed_index(&TestKeychain::External),
+        Some(2)
+    );
+    assert_eq!(count_stored(&txout_index), 8);
+    for index in 0..8 {
+        assert_eq!(
+            txout_index
+                .inner()
+                .spk_at_index(&(TestKeychain::External, index)),
+            Some(spk_at_index(&other_desc, index).as_script())
+        );
+    }
+}
+
+#[test]
+fn test_replace_descriptor_then_reload() {
+    let secp = Secp256k1::signing_only();
+    let (other_desc, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, "tr([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/2/*)").unwrap();
+    let (no_wildcard_desc, _) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, "wpkh([73c5da0a/86'/0'/0']xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk/1/0)").unwrap();
+
+    for new_desc in [other_desc, no_wildcard_desc] {
+        let (mut txout_index, _, internal_desc) = init_txout_index();
+        txout_index.set_lookahead_for_all(5);
+        let (_, mut persisted) = txout_index.reveal_to_target(&TestKeychain::External, 2);
+
+        let changeset = txout_index
+            .replace_descriptor(&TestKeychain::External, new_desc.clone(), true)
+            .expect("forced replacement must succeed");
+        let expected_index = if new_desc.has_wildcard() { 2 } else { 0 };
+        assert_eq!(
+            changeset,
+            keychain::ChangeSet([(TestKeychain::External, expected_index)].into())
+        );
+        persisted.append(changeset);
+
+        // reloading with the new descriptor restores the same state
+        let mut reloaded = KeychainTxOutIndex::<TestKeychain>::default();
+        reloaded.add_keychain(TestKeychain::External, new_desc);
+        reloaded.add_keychain(TestKeychain::Internal, internal_desc);
+        reloaded.set_lookahead_for_all(5);
+        reloaded.apply_changeset(persisted);
+        assert_eq!(
+            reloaded.last_revealed_indices(),
+            txout_index.last_revealed_indices()
+        );
+        assert_eq!(reloaded.inner().all_spks(), txout_index.inner().all_spks());
+        assert_eq!(
+            reloaded
+                .revealed_spks_of_keychain(&TestKeychain::External)
+                .collect::<Vec<_>>(),
+            txout_index
+                .revealed_spks_of_keychain(&TestKeychain::External)
+                .collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn test_index_of_spk() {
+    let (mut txout_index, external_desc, internal_desc) = init_txout_index();
+    txout_index.set_lookahead(&TestKeychain::Internal, 5);
+    let _ = txout_index.reveal_to_target(&TestKeychain::External, 1);
+
+    // revealed spks and spks within the lookahead are both found
+    assert_eq!(
+        txout_index.index_of_spk(&spk_at_index(&external_desc, 1)),
+        Some(&(TestKeychain::External, 1))
+    );
+    assert_eq!(
+        txout_index.index_of_spk(&spk_at_index(&internal_desc, 4)),
+        Some(&(TestKeychain::Internal, 4))
+    );
+    // spks which are neither revealed nor within the lookahead are not
+    assert_eq!(
+        txout_index.index_of_spk(&spk_at_index(&external_desc, 2)),
+        None
+    );
+    assert_eq!(
+        txout_index.index_of_spk(&spk_at_index(&internal_desc, 5)),
+        None
+    );
+
+    // the lookup is the reverse of `spk_at_index`
+    let spk = txout_index
+        .spk_at_index(&TestKeychain::External, 0)
+        .expect("must derive");
+    assert_eq!(
+        txout_index.index_of_spk(&spk),
+        Some(&(TestKeychain::External, 0))
+    );
+}
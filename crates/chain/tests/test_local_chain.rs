@@ -1,4 +1,7 @@
-use bdk_chain::local_chain::{CannotConnectError, ChangeSet, InsertBlockError, LocalChain, Update};
+use bdk_chain::local_chain::{
+    display_changeset, ApplyHeaderError, CannotConnectError, ChangeSet, CheckPoint,
+    InsertBlockError, LocalChain, MissingGenesisError, Update, UpdateFromBlocksError,
+};
 use bitcoin::BlockHash;
 
 #[macro_use]
@@ -346,3 +349,338 @@ fn local_chain_insert_block() {
         assert_eq!(chain, t.expected_final, "[{}] unexpected final chain", i,);
     }
 }
+
+#[test]
+fn local_chain_heights() {
+    let mut chain = local_chain![(0, h!("A")), (1, h!("B")), (3, h!("D"))];
+    assert_eq!(chain.heights().collect::<Vec<_>>(), vec![3, 1, 0]);
+    assert_eq!(chain.heights().rev().collect::<Vec<_>>(), vec![0, 1, 3]);
+
+    // the update invalidates block 3 and fills in the gap at height 2
+    let _ = chain
+        .apply_update(chain_update![(1, h!("B")), (2, h!("C")), (3, h!("D'"))])
+        .expect("update must connect");
+    assert_eq!(chain.heights().collect::<Vec<_>>(), vec![3, 2, 1, 0]);
+
+    assert_eq!(LocalChain::default().heights().next(), None);
+}
+
+#[test]
+fn local_chain_contains_block() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (3, h!("D"))];
+
+    assert_eq!(chain.contains_block((1, h!("B")).into()), Some(true));
+    // conflicting hash at a known height
+    assert_eq!(chain.contains_block((1, h!("B'")).into()), Some(false));
+    // height that we do not track
+    assert_eq!(chain.contains_block((2, h!("C")).into()), None);
+    // height above the tip
+    assert_eq!(chain.contains_block((4, h!("E")).into()), None);
+}
+
+#[test]
+fn local_chain_disconnect_from() {
+    let mut chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C")), (4, h!("E"))];
+
+    let changeset = chain.disconnect_from(2);
+    assert_eq!(changeset, [(2, None), (4, None)].into());
+    assert_eq!(chain, local_chain![(0, h!("A")), (1, h!("B"))]);
+    assert_eq!(chain.tip().map(|cp| cp.height()), Some(1));
+
+    // disconnecting above the tip is a no-op
+    assert!(chain.disconnect_from(5).is_empty());
+    assert_eq!(chain, local_chain![(0, h!("A")), (1, h!("B"))]);
+
+    // genesis is never disconnected
+    let changeset = chain.disconnect_from(0);
+    assert_eq!(changeset, [(1, None)].into());
+    assert_eq!(chain, local_chain![(0, h!("A"))]);
+    assert!(chain.disconnect_from(0).is_empty());
+    assert_eq!(chain, local_chain![(0, h!("A"))]);
+
+    // the changeset can be applied to another copy of the chain
+    let mut original = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C")), (4, h!("E"))];
+    let mut other = original.clone();
+    other.apply_changeset(&original.disconnect_from(3));
+    assert_eq!(other, original);
+    assert_eq!(
+        other,
+        local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))]
+    );
+}
+
+#[test]
+fn local_chain_merge_update() {
+    let mut chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+
+    // a pure extension does not invalidate anything
+    let (changeset, invalidated_height) = chain
+        .merge_update(chain_update![(2, h!("C")), (3, h!("D"))])
+        .expect("must connect");
+    assert_eq!(changeset, [(3, Some(h!("D")))].into());
+    assert_eq!(invalidated_height, None);
+
+    // rewriting height 2 invalidates it (and everything above)
+    let (changeset, invalidated_height) = chain
+        .merge_update(chain_update![(1, h!("B")), (2, h!("C'")), (3, h!("D'"))])
+        .expect("must connect");
+    assert_eq!(changeset, [(2, Some(h!("C'"))), (3, Some(h!("D'")))].into());
+    assert_eq!(invalidated_height, Some(2));
+
+    // blocks above the new tip that are removed are also invalidated
+    let (changeset, invalidated_height) = chain
+        .merge_update(chain_update![(0, h!("A")), (1, h!("B'"))])
+        .expect("must connect");
+    assert_eq!(
+        changeset,
+        [(1, Some(h!("B'"))), (2, None), (3, None)].into()
+    );
+    assert_eq!(invalidated_height, Some(1));
+    assert_eq!(chain, local_chain![(0, h!("A")), (1, h!("B'"))]);
+
+    // updating an empty chain does not invalidate anything
+    let (_, invalidated_height) = LocalChain::default()
+        .merge_update(chain_update![(0, h!("A"))])
+        .expect("must connect");
+    assert_eq!(invalidated_height, None);
+}
+
+#[test]
+fn local_chain_apply_header() {
+    use bitcoin::{block::Header, hash_types::TxMerkleNode, hashes::Hash, CompactTarget};
+
+    let header = |prev_blockhash: BlockHash, nonce: u32| Header {
+        version: bitcoin::block::Version::ONE,
+        prev_blockhash,
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: 0,
+        bits: CompactTarget::from_consensus(0),
+        nonce,
+    };
+    let header_0 = header(BlockHash::all_zeros(), 0);
+    let header_1 = header(header_0.block_hash(), 1);
+    let header_2 = header(header_1.block_hash(), 2);
+
+    let mut chain = LocalChain::default();
+    assert_eq!(
+        chain.apply_header(0, &header_0),
+        Ok([(0, Some(header_0.block_hash()))].into())
+    );
+    assert_eq!(
+        chain.apply_header(1, &header_1),
+        Ok([(1, Some(header_1.block_hash()))].into())
+    );
+    // applying the same header again is a no-op
+    assert_eq!(chain.apply_header(1, &header_1), Ok(ChangeSet::new()));
+
+    // a header that does not link to the checkpoint below it indicates a reorg
+    let header_2_reorg = header(h!("B'"), 2);
+    assert_eq!(
+        chain.apply_header(2, &header_2_reorg),
+        Err(ApplyHeaderError::PrevBlockHashMismatch {
+            height: 2,
+            original_prev_hash: header_1.block_hash(),
+            header_prev_hash: h!("B'"),
+        })
+    );
+    // a header that conflicts with an existing checkpoint also indicates a reorg
+    let header_1_reorg = header(header_0.block_hash(), 100);
+    assert_eq!(
+        chain.apply_header(1, &header_1_reorg),
+        Err(ApplyHeaderError::InsertBlock(InsertBlockError {
+            height: 1,
+            original_hash: header_1.block_hash(),
+            update_hash: header_1_reorg.block_hash(),
+        }))
+    );
+    assert_eq!(
+        chain,
+        local_chain![(0, header_0.block_hash()), (1, header_1.block_hash())]
+    );
+
+    // headers can be applied without a checkpoint below them
+    let mut sparse_chain = LocalChain::default();
+    assert!(sparse_chain.apply_header(2, &header_2).is_ok());
+    assert_eq!(sparse_chain, local_chain![(2, header_2.block_hash())]);
+}
+
+#[test]
+fn local_chain_try_from_tip() {
+    let tip = chain_update![(0, h!("A")), (1, h!("B")), (3, h!("D"))].tip;
+    let chain = LocalChain::try_from_tip(tip.clone()).expect("must contain genesis");
+    assert_eq!(chain, LocalChain::from_tip(tip));
+
+    let tip = chain_update![(1, h!("B")), (3, h!("D"))].tip;
+    assert_eq!(LocalChain::try_from_tip(tip), Err(MissingGenesisError));
+}
+
+#[test]
+fn local_chain_checkpoint_at() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (3, h!("D"))];
+
+    let cp = chain
+        .checkpoint_at(1)
+        .expect("must have checkpoint at height 1");
+    assert_eq!(cp.hash(), h!("B"));
+    assert_eq!(cp.prev().map(|cp| cp.height()), Some(0));
+    assert_eq!(
+        chain.checkpoint_at(3).map(|cp| cp.block_id()),
+        chain.tip().map(|cp| cp.block_id())
+    );
+
+    // untracked heights do not return the nearest checkpoint
+    assert!(chain.checkpoint_at(2).is_none());
+    assert!(chain.checkpoint_at(4).is_none());
+    assert!(LocalChain::default().checkpoint_at(0).is_none());
+}
+
+#[test]
+fn update_from_blocks() {
+    // blocks can be unsorted and contain duplicates
+    let update = Update::from_blocks([(3, h!("D")), (0, h!("A")), (1, h!("B")), (3, h!("D"))])
+        .expect("must construct update");
+    assert!(update.introduce_older_blocks);
+    assert_eq!(update.tip.block_id(), (3, h!("D")).into());
+    assert_eq!(
+        update
+            .tip
+            .iter()
+            .map(|cp| (cp.height(), cp.hash()))
+            .collect::<Vec<_>>(),
+        vec![(3, h!("D")), (1, h!("B")), (0, h!("A"))]
+    );
+
+    let mut chain = local_chain![(0, h!("A"))];
+    chain.apply_update(update).expect("update must connect");
+    assert_eq!(
+        chain,
+        local_chain![(0, h!("A")), (1, h!("B")), (3, h!("D"))]
+    );
+
+    assert_eq!(
+        Update::from_blocks([(0, h!("A")), (1, h!("B")), (1, h!("B'"))]).err(),
+        Some(UpdateFromBlocksError::ConflictingBlocks {
+            height: 1,
+            first_hash: h!("B"),
+            second_hash: h!("B'"),
+        })
+    );
+    assert_eq!(
+        Update::from_blocks([]).err(),
+        Some(UpdateFromBlocksError::Empty)
+    );
+}
+
+#[test]
+fn checkpoint_insert() {
+    let blocks = |cp: &CheckPoint| {
+        cp.iter()
+            .map(|cp| (cp.height(), cp.hash()))
+            .collect::<Vec<_>>()
+    };
+    let cp = chain_update![(0, h!("A")), (2, h!("C")), (3, h!("D"))].tip;
+
+    // a matching hash at an existing height is a no-op
+    let same = cp.clone().insert((2, h!("C")).into());
+    assert_eq!(blocks(&same), blocks(&cp));
+
+    // a conflicting hash replaces the block and drops everything above it
+    let reorged = cp.clone().insert((2, h!("C'")).into());
+    assert_eq!(reorged.block_id(), (2, h!("C'")).into());
+    assert_eq!(blocks(&reorged), vec![(2, h!("C'")), (0, h!("A"))]);
+    let reorged_base = cp.clone().insert((0, h!("A'")).into());
+    assert_eq!(blocks(&reorged_base), vec![(0, h!("A'"))]);
+
+    // a higher block extends the tip
+    let extended = cp.clone().insert((5, h!("F")).into());
+    assert_eq!(extended.block_id(), (5, h!("F")).into());
+    assert_eq!(
+        blocks(&extended),
+        vec![(5, h!("F")), (3, h!("D")), (2, h!("C")), (0, h!("A"))]
+    );
+
+    // a new block between existing blocks keeps the blocks above it
+    let filled = cp.insert((1, h!("B")).into());
+    assert_eq!(filled.block_id(), (3, h!("D")).into());
+    assert_eq!(
+        blocks(&filled),
+        vec![(3, h!("D")), (2, h!("C")), (1, h!("B")), (0, h!("A"))]
+    );
+}
+
+#[test]
+fn changeset_display() {
+    let changeset: ChangeSet =
+        [(1, Some(h!("B"))), (2, None), (3, Some(h!("D"))), (4, None)].into();
+    assert_eq!(
+        display_changeset(&changeset).to_string(),
+        "added heights [1, 3], removed heights [2, 4]"
+    );
+    assert_eq!(
+        display_changeset(&ChangeSet::default()).to_string(),
+        "added heights [], removed heights []"
+    );
+}
+
+#[test]
+fn local_chain_new_with_assume_valid() {
+    let chain_blocks = |chain: &LocalChain| {
+        chain
+            .blocks()
+            .iter()
+            .map(|(&height, &hash)| (height, hash))
+            .collect::<Vec<_>>()
+    };
+
+    let mut chain = LocalChain::new_with_assume_valid(h!("G"), (100, h!("AV")).into());
+    assert_eq!(chain_blocks(&chain), vec![(0, h!("G")), (100, h!("AV"))]);
+    assert_eq!(
+        chain.tip().expect("must have tip").block_id(),
+        (100, h!("AV")).into()
+    );
+
+    // an update connecting at the assume-valid block does not need the blocks below it
+    let changeset = chain
+        .apply_update(chain_update![
+            (100, h!("AV")),
+            (101, h!("B")),
+            (102, h!("C"))
+        ])
+        .expect("must connect");
+    assert_eq!(
+        changeset,
+        [(101, Some(h!("B"))), (102, Some(h!("C")))].into()
+    );
+
+    // reorgs above the assume-valid block still work
+    let changeset = chain
+        .apply_update(chain_update![
+            (100, h!("AV")),
+            (101, h!("B'")),
+            (102, h!("C'"))
+        ])
+        .expect("must connect");
+    assert_eq!(
+        changeset,
+        [(101, Some(h!("B'"))), (102, Some(h!("C'")))].into()
+    );
+    assert_eq!(
+        chain_blocks(&chain),
+        vec![
+            (0, h!("G")),
+            (100, h!("AV")),
+            (101, h!("B'")),
+            (102, h!("C'"))
+        ]
+    );
+
+    // the genesis block can be the assume-valid block
+    let chain = LocalChain::new_with_assume_valid(h!("G"), (0, h!("G")).into());
+    assert_eq!(chain_blocks(&chain), vec![(0, h!("G"))]);
+}
+
+#[test]
+#[should_panic(expected = "assume-valid block at height 0 must be the genesis block")]
+fn local_chain_new_with_assume_valid_conflicting_genesis() {
+    let _ = LocalChain::new_with_assume_valid(h!("G"), (0, h!("not G")).into());
+}
@@ -0,0 +1,191 @@
+#[cfg(feature = "async")]
+use bdk_chain::CommitAsyncError;
+use bdk_chain::{collections::BTreeSet, Persist, PersistBackend};
+#[cfg(feature = "async")]
+use std::sync::Barrier;
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+/// A backend that records every changeset written to it.
+#[derive(Debug, Default)]
+struct RecordingBackend {
+    written: Vec<BTreeSet<u32>>,
+}
+
+impl PersistBackend<BTreeSet<u32>> for RecordingBackend {
+    type WriteError = Infallible;
+
+    type LoadError = Infallible;
+
+    fn write_changes(&mut self, changeset: &BTreeSet<u32>) -> Result<(), Self::WriteError> {
+        self.written.push(changeset.clone());
+        Ok(())
+    }
+
+    fn load_from_persistence(&mut self) -> Result<BTreeSet<u32>, Self::LoadError> {
+        Ok(self.written.iter().flatten().copied().collect())
+    }
+}
+
+#[test]
+fn test_stage_and_commit() {
+    let backend = Arc::new(Mutex::new(RecordingBackend::default()));
+    let mut persist = Persist::new(backend.clone());
+
+    // nothing is written if nothing is staged
+    assert_eq!(persist.stage_and_commit(BTreeSet::new()), Ok(false));
+    assert!(backend.lock().unwrap().written.is_empty());
+
+    // the changeset is written alongside the changes which are already staged
+    persist.stage([1].into());
+    assert_eq!(persist.stage_and_commit([2].into()), Ok(true));
+    assert!(persist.staged().is_empty());
+    assert_eq!(backend.lock().unwrap().written, vec![[1, 2].into()]);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_commit_async() {
+    let backend = Arc::new(Mutex::new(RecordingBackend::default()));
+    let persist = Mutex::new(Persist::new(backend.clone()));
+
+    // nothing is written if nothing is staged
+    assert_eq!(Persist::commit_async(&persist).await, Ok(None));
+    assert!(backend.lock().unwrap().written.is_empty());
+
+    persist.lock().unwrap().stage([1, 2].into());
+    persist.lock().unwrap().stage([3].into());
+    assert_eq!(
+        Persist::commit_async(&persist).await,
+        Ok(Some([1, 2, 3].into()))
+    );
+    assert!(persist.lock().unwrap().staged().is_empty());
+
+    // changes staged after a commit go to the next commit
+    persist.lock().unwrap().stage([4].into());
+    assert_eq!(Persist::commit_async(&persist).await, Ok(Some([4].into())));
+
+    let mut backend = backend.lock().unwrap();
+    assert_eq!(backend.written, vec![[1, 2, 3].into(), [4].into()]);
+    assert_eq!(
+        backend.load_from_persistence(),
+        Ok([1, 2, 3, 4].into_iter().collect())
+    );
+}
+
+/// A backend that waits at `gate` twice before writing, so that the caller can act while the write
+/// is in progress.
+#[cfg(feature = "async")]
+#[derive(Debug, Clone)]
+struct GatedBackend {
+    recording: Arc<Mutex<RecordingBackend>>,
+    gate: Arc<Barrier>,
+}
+
+#[cfg(feature = "async")]
+impl PersistBackend<BTreeSet<u32>> for GatedBackend {
+    type WriteError = Infallible;
+
+    type LoadError = Infallible;
+
+    fn write_changes(&mut self, changeset: &BTreeSet<u32>) -> Result<(), Self::WriteError> {
+        // the write has started
+        self.gate.wait();
+        // the caller is done
+        self.gate.wait();
+        self.recording.lock().unwrap().write_changes(changeset)
+    }
+
+    fn load_from_persistence(&mut self) -> Result<BTreeSet<u32>, Self::LoadError> {
+        self.recording.lock().unwrap().load_from_persistence()
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_commit_async_keeps_changes_staged_during_write() {
+    let recording = Arc::new(Mutex::new(RecordingBackend::default()));
+    let gate = Arc::new(Barrier::new(2));
+    let persist = Mutex::new(Persist::new(GatedBackend {
+        recording: recording.clone(),
+        gate: gate.clone(),
+    }));
+    persist.lock().unwrap().stage([1, 2].into());
+
+    let stage_during_write = async {
+        let wait = |gate: Arc<Barrier>| tokio::task::spawn_blocking(move || gate.wait());
+        wait(gate.clone()).await.unwrap();
+        persist.lock().unwrap().stage([3].into());
+        wait(gate.clone()).await.unwrap();
+    };
+    let (committed, _) = tokio::join!(Persist::commit_async(&persist), stage_during_write);
+
+    // only the changes staged before the write are committed
+    assert_eq!(committed, Ok(Some([1, 2].into())));
+    assert_eq!(recording.lock().unwrap().written, vec![[1, 2].into()]);
+    // the changes staged during the write go to the next commit
+    assert_eq!(persist.lock().unwrap().staged(), &[3].into());
+}
+
+/// A backend that fails to write.
+#[cfg(feature = "async")]
+#[derive(Debug, Default, Clone)]
+struct FailingBackend;
+
+#[cfg(feature = "async")]
+impl PersistBackend<BTreeSet<u32>> for FailingBackend {
+    type WriteError = &'static str;
+
+    type LoadError = Infallible;
+
+    fn write_changes(&mut self, _changeset: &BTreeSet<u32>) -> Result<(), Self::WriteError> {
+        Err("disk is full")
+    }
+
+    fn load_from_persistence(&mut self) -> Result<BTreeSet<u32>, Self::LoadError> {
+        Ok(BTreeSet::new())
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_commit_async_keeps_changes_staged_on_failure() {
+    let persist = Mutex::new(Persist::new(FailingBackend));
+    persist.lock().unwrap().stage([1].into());
+    assert_eq!(
+        Persist::commit_async(&persist).await,
+        Err(CommitAsyncError::Write("disk is full"))
+    );
+    assert_eq!(persist.lock().unwrap().staged(), &[1].into());
+}
+
+/// A backend that panics when writing.
+#[cfg(feature = "async")]
+#[derive(Debug, Default, Clone)]
+struct PanickingBackend;
+
+#[cfg(feature = "async")]
+impl PersistBackend<BTreeSet<u32>> for PanickingBackend {
+    type WriteError = Infallible;
+
+    type LoadError = Infallible;
+
+    fn write_changes(&mut self, _changeset: &BTreeSet<u32>) -> Result<(), Self::WriteError> {
+        panic!("backend panicked while writing")
+    }
+
+    fn load_from_persistence(&mut self) -> Result<BTreeSet<u32>, Self::LoadError> {
+        Ok(BTreeSet::new())
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+#[should_panic(expected = "backend panicked while writing")]
+async fn test_commit_async_resumes_backend_panic() {
+    let persist = Mutex::new(Persist::new(PanickingBackend));
+    persist.lock().unwrap().stage([1].into());
+    let _ = Persist::commit_async(&persist).await;
+}
@@ -55,6 +55,69 @@ fn spk_txout_sent_and_received() {
     assert_eq!(index.net_value(&tx2), 8_000);
 }
 
+#[test]
+fn spk_txout_net_value_with() {
+    let spk1 = ScriptBuf::from_hex("001404f1e52ce2bab3423c6a8c63b7cd730d8f12542c").unwrap();
+    let spk2 = ScriptBuf::from_hex("00142b57404ae14f08c3a0c903feb2af7830605eb00f").unwrap();
+    let foreign_spk = ScriptBuf::new();
+
+    let mut index = SpkTxOutIndex::default();
+    index.insert_spk(0, spk1.clone());
+
+    let prev_tx = Transaction {
+        version: 0x02,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![
+            TxOut {
+                value: 42_000,
+                script_pubkey: spk1.clone(),
+            },
+            TxOut {
+                value: 10_000,
+                script_pubkey: foreign_spk.clone(),
+            },
+        ],
+    };
+    let tx = Transaction {
+        version: 0x02,
+        lock_time: absolute::LockTime::ZERO,
+        input: (0..2)
+            .map(|vout| TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), vout),
+                ..Default::default()
+            })
+            .collect(),
+        output: vec![
+            TxOut {
+                value: 30_000,
+                script_pubkey: spk2,
+            },
+            TxOut {
+                value: 12_000,
+                script_pubkey: spk1,
+            },
+            TxOut {
+                value: 9_000,
+                script_pubkey: foreign_spk,
+            },
+        ],
+    };
+    let get_txout = |op: OutPoint| {
+        if op.txid == prev_tx.txid() {
+            prev_tx.output.get(op.vout as usize).cloned()
+        } else {
+            None
+        }
+    };
+
+    // `prev_tx` is never scanned, the prevouts are sourced from `get_txout`
+    assert_eq!(index.net_value(&tx), 12_000);
+    assert_eq!(index.net_value_with(&tx, get_txout), 12_000 - 42_000);
+    assert_eq!(index.net_value_with(&tx, |_| None), 12_000);
+    assert_eq!(index.net_value_with(&prev_tx, get_txout), 42_000);
+}
+
 #[test]
 fn mark_used() {
     let spk1 = ScriptBuf::from_hex("001404f1e52ce2bab3423c6a8c63b7cd730d8f12542c").unwrap();
@@ -98,3 +161,104 @@ fn unmark_used_does_not_result_in_invalid_representation() {
     assert!(!spk_index.unmark_used(&2));
     assert!(spk_index.unused_spks(..).collect::<Vec<_>>().is_empty());
 }
+
+#[test]
+fn txouts_of_spk_index() {
+    let spk1 = ScriptBuf::from_hex("001404f1e52ce2bab3423c6a8c63b7cd730d8f12542c").unwrap();
+    let spk2 = ScriptBuf::from_hex("00142b57404ae14f08c3a0c903feb2af7830605eb00f").unwrap();
+
+    let mut index = SpkTxOutIndex::default();
+    index.insert_spk(0, spk1.clone());
+    index.insert_spk(1, spk2.clone());
+
+    let tx1 = Transaction {
+        version: 0x02,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: spk1.clone(),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: spk2.clone(),
+            },
+        ],
+    };
+    // spends the first output of `tx1` and pays to `spk1` again
+    let tx2 = Transaction {
+        version: 0x02,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx1.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 9_000,
+            script_pubkey: spk1,
+        }],
+    };
+    index.scan(&tx1);
+    index.scan(&tx2);
+
+    let mut expected = vec![
+        (OutPoint::new(tx1.txid(), 0), &tx1.output[0]),
+        (OutPoint::new(tx2.txid(), 0), &tx2.output[0]),
+    ];
+    expected.sort_by_key(|(op, _)| *op);
+    // the spent output of `tx1` is included
+    assert_eq!(index.txouts_of_spk_index(&0).collect::<Vec<_>>(), expected);
+    assert_eq!(
+        index.txouts_of_spk_index(&1).collect::<Vec<_>>(),
+        vec![(OutPoint::new(tx1.txid(), 1), &tx1.output[1])]
+    );
+    assert_eq!(index.txouts_of_spk_index(&2).count(), 0);
+}
+
+#[test]
+fn total_received() {
+    let spk1 = ScriptBuf::from_hex("001404f1e52ce2bab3423c6a8c63b7cd730d8f12542c").unwrap();
+    let spk2 = ScriptBuf::from_hex("00142b57404ae14f08c3a0c903feb2af7830605eb00f").unwrap();
+
+    let mut index = SpkTxOutIndex::default();
+    index.insert_spk(0, spk1.clone());
+    index.insert_spk(1, spk2.clone());
+    assert_eq!(index.total_received(&0), 0);
+
+    let tx1 = Transaction {
+        version: 0x02,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: spk1.clone(),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: spk2,
+            },
+        ],
+    };
+    // spends the first output of `tx1` and pays to `spk1` again
+    let tx2 = Transaction {
+        version: 0x02,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx1.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 9_000,
+            script_pubkey: spk1,
+        }],
+    };
+    index.scan(&tx1);
+    index.scan(&tx2);
+
+    // the spent output of `tx1` is still counted as received
+    assert_eq!(index.total_received(&0), 19_000);
+    assert_eq!(index.total_received(&1), 20_000);
+    assert_eq!(index.total_received(&2), 0);
+}
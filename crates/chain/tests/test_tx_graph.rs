@@ -1,16 +1,21 @@
 #[macro_use]
 mod common;
-use bdk_chain::tx_graph::CalculateFeeError;
+use bdk_chain::tx_graph::{
+    AncestorPackageError, CalculateFeeError, InsertTxOutError, OverlapError,
+};
 use bdk_chain::{
     collections::*,
+    keychain::Balance,
     local_chain::LocalChain,
-    tx_graph::{ChangeSet, TxGraph},
-    Anchor, Append, BlockId, ChainPosition, ConfirmationHeightAnchor,
+    tx_graph::{self, ChangeSet, ChangeSetWithRemovals, TxGraph},
+    Anchor, Append, BlockId, ChainPosition, ConfirmationHashAnchor, ConfirmationHeightAnchor,
+    COINBASE_MATURITY,
 };
 use bitcoin::{
-    absolute, hashes::Hash, BlockHash, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid,
+    absolute, hashes::Hash, Amount, BlockHash, FeeRate, OutPoint, ScriptBuf, Transaction, TxIn,
+    TxOut, Txid, Witness,
 };
-use core::iter;
+use core::{iter, ops::Bound};
 use std::vec;
 
 #[test]
@@ -276,6 +281,56 @@ fn insert_tx_can_retrieve_full_tx_from_graph() {
     assert_eq!(graph.get_tx(tx.txid()), Some(&tx));
 }
 
+#[test]
+fn test_merge_disjoint() {
+    let tx_a = common::new_tx(0);
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..Default::default()
+        }],
+        ..common::new_tx(1)
+    };
+    let tx_c = common::new_tx(2);
+    let anchor = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+
+    let mut graph = TxGraph::<BlockId>::new([tx_a.clone()]);
+    let mut other = TxGraph::<BlockId>::new([tx_b.clone()]);
+    let _ = other.insert_anchor(tx_b.txid(), anchor);
+    let _ = other.insert_seen_at(tx_b.txid(), 100);
+    let _ = other.insert_txout(OutPoint::new(h!("floating"), 1), TxOut::default());
+
+    // the result is the same as `apply_update`
+    let mut expected_graph = graph.clone();
+    let expected_changeset = expected_graph.apply_update(other.clone());
+    assert_eq!(graph.merge_disjoint(other), Ok(expected_changeset));
+    assert_eq!(graph, expected_graph);
+    assert_eq!(
+        graph.outspends(OutPoint::new(tx_a.txid(), 0)),
+        &[tx_b.txid()].into_iter().collect::<HashSet<_>>()
+    );
+
+    // overlapping graphs are rejected without changing `self`
+    let original = graph.clone();
+    let overlapping = TxGraph::<BlockId>::new([tx_c, tx_b.clone()]);
+    assert_eq!(
+        graph.merge_disjoint(overlapping),
+        Err(OverlapError { txid: tx_b.txid() })
+    );
+    assert_eq!(graph, original);
+    let mut overlapping = TxGraph::<BlockId>::default();
+    let _ = overlapping.insert_txout(OutPoint::new(h!("floating"), 0), TxOut::default());
+    assert_eq!(
+        graph.merge_disjoint(overlapping),
+        Err(OverlapError {
+            txid: h!("floating")
+        })
+    );
+}
+
 #[test]
 fn insert_tx_displaces_txouts() {
     let mut tx_graph = TxGraph::<()>::default();
@@ -388,6 +443,108 @@ fn insert_txout_does_not_displace_tx() {
     );
 }
 
+#[test]
+fn test_full_txs_excludes_floating_txouts() {
+    let mut graph = TxGraph::<()>::default();
+    let txs = (0..3)
+        .map(|i| Transaction {
+            version: 0x01,
+            lock_time: absolute::LockTime::from_height(i).unwrap(),
+            input: vec![],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::default(),
+            }],
+        })
+        .collect::<Vec<_>>();
+    for tx in &txs {
+        let _ = graph.insert_tx(tx.clone());
+    }
+
+    // a txout of a transaction we do not have in full
+    let _ = graph.insert_txout(
+        OutPoint::new(h!("floating"), 0),
+        TxOut {
+            value: 20_000,
+            script_pubkey: ScriptBuf::default(),
+        },
+    );
+    assert_eq!(graph.floating_txouts().count(), 1);
+
+    assert_eq!(
+        graph.full_txs().map(|tx| tx.txid).collect::<BTreeSet<_>>(),
+        txs.iter().map(|tx| tx.txid()).collect::<BTreeSet<_>>()
+    );
+    assert!(graph.full_txs().all(|tx| tx.tx.txid() == tx.txid));
+
+    // inserting the full transaction of a floating txout makes it appear
+    let tx = Transaction {
+        version: 0x02,
+        ..txs[0].clone()
+    };
+    let _ = graph.insert_txout(OutPoint::new(tx.txid(), 0), tx.output[0].clone());
+    assert_eq!(graph.full_txs().count(), 3);
+    let _ = graph.insert_tx(tx.clone());
+    assert_eq!(graph.full_txs().count(), 4);
+    assert!(graph.full_txs().any(|node| node.txid == tx.txid()));
+}
+
+#[test]
+fn test_missing_prevouts() {
+    let op_1 = OutPoint::new(h!("op_1"), 0);
+    let op_2 = OutPoint::new(h!("op_2"), 1);
+
+    let tx_a = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: op_1,
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: op_2,
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut::default()],
+        ..common::new_tx(0)
+    };
+    // tx_b spends tx_a (which is in the graph) and op_2 (which is also spent by tx_a)
+    let tx_b = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(tx_a.txid(), 0),
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: op_2,
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut::default()],
+        ..common::new_tx(1)
+    };
+
+    let mut graph = TxGraph::<()>::default();
+    let _ = graph.insert_tx(tx_a);
+    let _ = graph.insert_tx(tx_b);
+    assert_eq!(
+        graph.missing_prevouts().collect::<Vec<_>>(),
+        vec![op_1, op_2]
+    );
+
+    // prevouts become known with `insert_txout`
+    let _ = graph.insert_txout(op_1, TxOut::default());
+    assert_eq!(graph.missing_prevouts().collect::<Vec<_>>(), vec![op_2]);
+    // the floating txout is not missing
+    assert_eq!(
+        graph
+            .floating_txouts()
+            .map(|(op, _)| op)
+            .collect::<Vec<_>>(),
+        vec![op_1]
+    );
+}
+
 #[test]
 fn test_calculate_fee() {
     let mut graph = TxGraph::<()>::default();
@@ -479,6 +636,58 @@ fn test_calculate_fee() {
     );
 }
 
+#[test]
+fn test_calculate_fee_rate() {
+    let mut graph = TxGraph::<()>::default();
+    let intx = Transaction {
+        output: vec![TxOut {
+            value: 100_000,
+            ..Default::default()
+        }],
+        ..common::new_tx(0)
+    };
+    let _ = graph.insert_tx(intx.clone());
+
+    let mut tx = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(intx.txid(), 0),
+            witness: Witness::from_slice(&[[0_u8; 1]]),
+            ..Default::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(1)
+    };
+    // the weight is not a multiple of 4, so the vsize must be rounded up
+    let weight = tx.weight().to_wu();
+    assert_ne!(weight % 4, 0);
+    let vsize = (weight + 3) / 4;
+    assert_eq!(tx.vsize() as u64, vsize);
+
+    tx.output[0].value = 100_000 - vsize * 10;
+    assert_eq!(
+        graph.calculate_fee_rate(&tx),
+        Ok(FeeRate::from_sat_per_vb_unchecked(10))
+    );
+
+    tx.output[0].value = 100_000 - vsize * 10 - 1;
+    let fee_rate = graph
+        .calculate_fee_rate(&tx)
+        .expect("must calculate fee rate");
+    assert_eq!(fee_rate.to_sat_per_kwu(), (vsize * 10 + 1) * 250 / vsize);
+    assert!(fee_rate > FeeRate::from_sat_per_vb_unchecked(10));
+
+    // missing prevouts result in the same error as `calculate_fee`
+    let outpoint = OutPoint::new(h!("unknown_txid"), 0);
+    tx.input.push(TxIn {
+        previous_output: outpoint,
+        ..Default::default()
+    });
+    assert_eq!(
+        graph.calculate_fee_rate(&tx),
+        Err(CalculateFeeError::MissingTxOut(vec![outpoint]))
+    );
+}
+
 #[test]
 fn test_calculate_fee_on_coinbase() {
     let tx = Transaction {
@@ -545,6 +754,68 @@ fn test_conflicting_descendants() {
     );
 }
 
+#[test]
+fn test_direct_conflicts() {
+    let op_1 = OutPoint::new(h!("op_1"), 0);
+    let op_2 = OutPoint::new(h!("op_2"), 1);
+
+    // tx_a spends op_1
+    let tx_a = Transaction {
+        input: vec![TxIn {
+            previous_output: op_1,
+            ..TxIn::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(0)
+    };
+
+    // tx_b spends op_2
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: op_2,
+            ..TxIn::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(1)
+    };
+
+    // tx_c spends both op_1 and op_2, conflicting with tx_a and tx_b
+    let tx_c = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: op_2,
+                ..TxIn::default()
+            },
+            TxIn {
+                previous_output: op_1,
+                ..TxIn::default()
+            },
+        ],
+        output: vec![TxOut::default()],
+        ..common::new_tx(2)
+    };
+
+    let mut graph = TxGraph::<()>::default();
+    let _ = graph.insert_tx(tx_a.clone());
+    let _ = graph.insert_tx(tx_b.clone());
+
+    assert_eq!(
+        graph.direct_conflicts(&tx_c).collect::<BTreeSet<_>>(),
+        [(0_usize, tx_b.txid()), (1_usize, tx_a.txid())].into(),
+    );
+
+    // tx_c should not conflict with itself once it is in the graph
+    let _ = graph.insert_tx(tx_c.clone());
+    assert_eq!(
+        graph.direct_conflicts(&tx_c).collect::<BTreeSet<_>>(),
+        [(0_usize, tx_b.txid()), (1_usize, tx_a.txid())].into(),
+    );
+    assert_eq!(
+        graph.direct_conflicts(&tx_a).collect::<Vec<_>>(),
+        vec![(0_usize, tx_c.txid())],
+    );
+}
+
 #[test]
 fn test_descendants_no_repeat() {
     let tx_a = Transaction {
@@ -640,6 +911,143 @@ fn test_descendants_no_repeat() {
     assert!(expected_txids.is_empty());
 }
 
+#[test]
+fn test_descendants() {
+    let tx_a = Transaction {
+        output: vec![TxOut::default(), TxOut::default()],
+        ..common::new_tx(0)
+    };
+    // tx_b spends tx_a
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..TxIn::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(1)
+    };
+    // tx_c spends tx_b
+    let tx_c = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_b.txid(), 0),
+            ..TxIn::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(2)
+    };
+    // tx_d spends tx_a
+    let tx_d = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 1),
+            ..TxIn::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(3)
+    };
+    // tx_e spends both tx_c and tx_d (diamond)
+    let tx_e = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(tx_c.txid(), 0),
+                ..TxIn::default()
+            },
+            TxIn {
+                previous_output: OutPoint::new(tx_d.txid(), 0),
+                ..TxIn::default()
+            },
+        ],
+        output: vec![TxOut::default()],
+        ..common::new_tx(4)
+    };
+
+    let mut graph = TxGraph::<()>::default();
+    for tx in [&tx_a, &tx_b, &tx_c, &tx_d] {
+        let _ = graph.insert_tx(tx.clone());
+    }
+
+    let descendants = graph.descendants(tx_a.txid()).collect::<Vec<_>>();
+    assert_eq!(descendants.len(), 3);
+    // breadth-first: direct children come before grandchildren
+    assert_eq!(
+        descendants[..2].iter().cloned().collect::<BTreeSet<_>>(),
+        [tx_b.txid(), tx_d.txid()].into()
+    );
+    assert_eq!(descendants[2], tx_c.txid());
+
+    assert_eq!(
+        graph.descendants(tx_b.txid()).collect::<Vec<_>>(),
+        vec![tx_c.txid()]
+    );
+    assert_eq!(graph.descendants(tx_c.txid()).count(), 0);
+
+    // diamond-shaped descendants are only returned once
+    let _ = graph.insert_tx(tx_e.clone());
+    let descendants = graph.descendants(tx_a.txid()).collect::<Vec<_>>();
+    assert_eq!(descendants.len(), 4);
+    assert_eq!(
+        descendants[2..].iter().cloned().collect::<BTreeSet<_>>(),
+        [tx_c.txid(), tx_e.txid()].into()
+    );
+}
+
+#[test]
+fn test_list_conflicted_txs() {
+    let local_chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+    let tip = local_chain.tip().expect("must have tip").block_id();
+    let spend = |previous_output: OutPoint, lt: u32| Transaction {
+        input: vec![TxIn {
+            previous_output,
+            ..TxIn::default()
+        }],
+        output: vec![TxOut::default()],
+        ..common::new_tx(lt)
+    };
+
+    // `tx_rbf` replaces `tx_original` as it is seen later
+    let tx_original = spend(OutPoint::new(h!("op_a"), 0), 0);
+    let tx_rbf = spend(OutPoint::new(h!("op_a"), 0), 1);
+    // `tx_confirmed` evicts `tx_double_spend` even though it is seen earlier
+    let tx_confirmed = spend(OutPoint::new(h!("op_b"), 0), 2);
+    let tx_double_spend = spend(OutPoint::new(h!("op_b"), 0), 3);
+    // `tx_unrelated` has no conflicts
+    let tx_unrelated = spend(OutPoint::new(h!("op_c"), 0), 4);
+
+    let mut graph = TxGraph::<BlockId>::default();
+    for tx in [
+        &tx_original,
+        &tx_rbf,
+        &tx_confirmed,
+        &tx_double_spend,
+        &tx_unrelated,
+    ] {
+        let _ = graph.insert_tx(tx.clone());
+    }
+    let _ = graph.insert_seen_at(tx_original.txid(), 10);
+    let _ = graph.insert_seen_at(tx_rbf.txid(), 20);
+    let _ = graph.insert_seen_at(tx_double_spend.txid(), 30);
+    let _ = graph.insert_anchor(tx_confirmed.txid(), tip);
+
+    assert_eq!(
+        graph
+            .list_conflicted_txs(&local_chain, tip)
+            .collect::<BTreeSet<_>>(),
+        [
+            (tx_original.txid(), tx_rbf.txid()),
+            (tx_double_spend.txid(), tx_confirmed.txid()),
+        ]
+        .into()
+    );
+    // evicted and canonical txs are disjoint
+    let canonical = graph
+        .list_chain_txs(&local_chain, tip)
+        .map(|tx| tx.tx_node.txid)
+        .collect::<BTreeSet<_>>();
+    assert_eq!(
+        canonical,
+        [tx_rbf.txid(), tx_confirmed.txid(), tx_unrelated.txid()].into()
+    );
+}
+
 #[test]
 fn test_chain_spends() {
     let local_chain: LocalChain = (0..=100)
@@ -843,134 +1251,1533 @@ fn test_changeset_last_seen_append() {
 }
 
 #[test]
-fn test_missing_blocks() {
-    /// An anchor implementation for testing, made up of `(the_anchor_block, random_data)`.
-    #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, core::hash::Hash)]
-    struct TestAnchor(BlockId);
+fn test_batch_insert_seen_at() {
+    let tx_a = common::new_tx(0);
+    let tx_b = common::new_tx(1);
+    let (txid_a, txid_b) = (tx_a.txid(), tx_b.txid());
 
-    impl Anchor for TestAnchor {
-        fn anchor_block(&self) -> BlockId {
-            self.0
-        }
-    }
+    let mut graph = TxGraph::<()>::new([tx_a, tx_b]);
+    let _ = graph.insert_seen_at(txid_a, 10);
 
-    struct Scenario<'a> {
-        name: &'a str,
-        graph: TxGraph<TestAnchor>,
-        chain: LocalChain,
-        exp_heights: &'a [u32],
-    }
+    // a lower `seen_at` than what is recorded is ignored, and the highest `seen_at` in the batch is
+    // kept
+    let changeset = graph.batch_insert_seen_at([(txid_a, 5), (txid_b, 20), (txid_b, 15)]);
+    assert_eq!(changeset.last_seen, [(txid_b, 20)].into());
+    assert!(changeset.txs.is_empty());
 
-    const fn new_anchor(height: u32, hash: BlockHash) -> TestAnchor {
-        TestAnchor(BlockId { height, hash })
-    }
+    let changeset = graph.batch_insert_seen_at([(txid_a, 11), (txid_b, 20)]);
+    assert_eq!(changeset.last_seen, [(txid_a, 11)].into());
 
-    fn new_scenario<'a>(
-        name: &'a str,
-        graph_anchors: &'a [(Txid, TestAnchor)],
-        chain: &'a [(u32, BlockHash)],
-        exp_heights: &'a [u32],
-    ) -> Scenario<'a> {
-        Scenario {
-            name,
-            graph: {
-                let mut g = TxGraph::default();
+    assert_eq!(
+        graph
+            .get_tx_node(txid_a)
+            .map(|node| node.last_seen_unconfirmed),
+        Some(11)
+    );
+    assert_eq!(
+        graph
+            .get_tx_node(txid_b)
+            .map(|node| node.last_seen_unconfirmed),
+        Some(20)
+    );
+}
+
+#[test]
+fn test_last_seen() {
+    let tx = common::new_tx(0);
+    let txid = tx.txid();
+    let mut graph = TxGraph::<()>::new([tx]);
+    assert_eq!(graph.last_seen(txid), None);
+    assert_eq!(graph.last_seen(h!("unknown")), None);
+
+    let _ = graph.insert_seen_at(txid, 10);
+    assert_eq!(graph.last_seen(txid), Some(10));
+    // an older timestamp does not replace the latest one
+    let _ = graph.insert_seen_at(txid, 5);
+    assert_eq!(graph.last_seen(txid), Some(10));
+    let _ = graph.insert_seen_at(txid, 20);
+    assert_eq!(graph.last_seen(txid), Some(20));
+
+    // txs only known by their txouts can also be seen
+    let _ = graph.insert_txout(OutPoint::new(h!("floating"), 0), TxOut::default());
+    let _ = graph.insert_seen_at(h!("floating"), 30);
+    assert_eq!(graph.last_seen(h!("floating")), Some(30));
+}
+
+#[test]
+fn test_get_tx_node() {
+    let tx = common::new_tx(0);
+    let txid = tx.txid();
+    let anchor = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+
+    let mut graph = TxGraph::<BlockId>::default();
+    let _ = graph.insert_tx(tx.clone());
+    let _ = graph.insert_anchor(txid, anchor);
+    let _ = graph.insert_seen_at(txid, 42);
+
+    let node = graph.get_tx_node(txid).expect("must have full tx");
+    assert_eq!(node.txid, txid);
+    assert_eq!(node.tx, &tx);
+    assert_eq!(node.anchors, &[anchor].into());
+    assert_eq!(node.last_seen_unconfirmed, 42);
+
+    // txs only known by their txouts have no node
+    let _ = graph.insert_txout(OutPoint::new(h!("floating"), 0), TxOut::default());
+    assert!(graph.get_tx_node(h!("floating")).is_none());
+    assert!(graph.get_tx_node(h!("unknown")).is_none());
+}
+
+#[test]
+fn test_try_insert_txout() {
+    let tx = Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+    let txid = tx.txid();
+    let txout = tx.output[0].clone();
+    let other_txout = TxOut {
+        value: 20_000,
+        script_pubkey: ScriptBuf::new(),
+    };
+
+    let mut graph = TxGraph::<()>::default();
+
+    // without the full tx, the txout is inserted as floating
+    let floating_op = OutPoint::new(h!("floating"), 3);
+    let changeset = graph
+        .try_insert_txout(floating_op, other_txout.clone())
+        .expect("must insert floating txout");
+    assert_eq!(
+        changeset.txouts,
+        [(floating_op, other_txout.clone())].into()
+    );
+
+    let _ = graph.insert_tx(tx);
+
+    // a txout matching the full tx is accepted, but nothing changes
+    let changeset = graph
+        .try_insert_txout(OutPoint::new(txid, 0), txout)
+        .expect("txout matches tx");
+    assert!(changeset.is_empty());
+
+    assert_eq!(
+        graph.try_insert_txout(OutPoint::new(txid, 0), other_txout.clone()),
+        Err(InsertTxOutError::TxOutMismatch {
+            outpoint: OutPoint::new(txid, 0),
+            expected: TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+            got: other_txout.clone(),
+        })
+    );
+    assert_eq!(
+        graph.try_insert_txout(OutPoint::new(txid, 1), other_txout),
+        Err(InsertTxOutError::MissingOutput {
+            outpoint: OutPoint::new(txid, 1),
+            output_count: 1,
+        })
+    );
+    assert_eq!(graph.get_txout(OutPoint::new(txid, 1)), None);
+}
+
+#[test]
+fn test_txs_spending_or_funding() {
+    let spk = |byte: u8| ScriptBuf::from(vec![byte]);
+    let tx_fund = Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: spk(1),
+        }],
+    };
+    let tx_spend = Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_fund.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 9_000,
+            script_pubkey: spk(2),
+        }],
+    };
+    // spends an outpoint that is unknown to the graph
+    let tx_unknown_prevout = Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(h!("unknown"), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 5_000,
+            script_pubkey: spk(3),
+        }],
+    };
+    let floating_op = OutPoint::new(h!("floating"), 1);
+
+    let mut graph = TxGraph::<()>::new([
+        tx_fund.clone(),
+        tx_spend.clone(),
+        tx_unknown_prevout.clone(),
+    ]);
+    let _ = graph.insert_txout(
+        floating_op,
+        TxOut {
+            value: 1_000,
+            script_pubkey: spk(1),
+        },
+    );
+
+    let query = |spks: Vec<ScriptBuf>| graph.txs_spending_or_funding(spks).collect::<BTreeSet<_>>();
+
+    assert_eq!(
+        query(vec![spk(1)]),
+        [tx_fund.txid(), tx_spend.txid(), floating_op.txid].into()
+    );
+    assert_eq!(query(vec![spk(2)]), [tx_spend.txid()].into());
+    assert_eq!(
+        query(vec![spk(2), spk(3)]),
+        [tx_spend.txid(), tx_unknown_prevout.txid()].into()
+    );
+    assert!(query(vec![spk(4)]).is_empty());
+}
+
+#[test]
+fn test_prune_spent_before() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C")), (3, h!("D"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let block = |height: u32| BlockId {
+        height,
+        hash: chain.blocks()[&height],
+    };
+    let spending_tx = |lock_time: u32, prevout: Option<OutPoint>| Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::from_consensus(lock_time),
+        input: prevout
+            .map(|previous_output| TxIn {
+                previous_output,
+                ..Default::default()
+            })
+            .into_iter()
+            .collect(),
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+
+    // tx_a <- tx_b <- tx_c (unspent)
+    let tx_a = spending_tx(0, None);
+    let tx_b = spending_tx(1, Some(OutPoint::new(tx_a.txid(), 0)));
+    let tx_c = spending_tx(2, Some(OutPoint::new(tx_b.txid(), 0)));
+    // tx_d <- tx_e (unconfirmed)
+    let tx_d = spending_tx(3, None);
+    let tx_e = spending_tx(4, Some(OutPoint::new(tx_d.txid(), 0)));
+
+    let mut graph = TxGraph::<BlockId>::new([
+        tx_a.clone(),
+        tx_b.clone(),
+        tx_c.clone(),
+        tx_d.clone(),
+        tx_e.clone(),
+    ]);
+    for (txid, height) in [
+        (tx_a.txid(), 1),
+        (tx_b.txid(), 1),
+        (tx_c.txid(), 2),
+        (tx_d.txid(), 1),
+    ] {
+        let _ = graph.insert_anchor(txid, block(height));
+    }
+    let _ = graph.insert_seen_at(tx_e.txid(), 100);
+    let original = graph.clone();
+
+    // nothing is confirmed below height 1
+    assert!(graph.prune_spent_before(1, &chain, tip).is_empty());
+
+    // `tx_b` is kept as it is the prevout of the unspent `tx_c`, and `tx_d` is kept as it is the
+    // prevout of the unconfirmed `tx_e`
+    let changeset = graph.prune_spent_before(3, &chain, tip);
+    assert_eq!(
+        changeset,
+        ChangeSetWithRemovals {
+            removed: [tx_a.txid()].into(),
+            ..Default::default()
+        }
+    );
+    assert!(graph.get_tx(tx_a.txid()).is_none());
+    assert_eq!(graph.get_chain_position(&chain, tip, tx_a.txid()), None);
+    assert_eq!(
+        graph.full_txs().map(|tx| tx.txid).collect::<BTreeSet<_>>(),
+        [tx_b.txid(), tx_c.txid(), tx_d.txid(), tx_e.txid()].into()
+    );
+    // the removed prevouts of `tx_b` are now missing
+    assert_eq!(
+        graph.missing_prevouts().collect::<Vec<_>>(),
+        vec![OutPoint::new(tx_a.txid(), 0)]
+    );
+
+    // the changeset can be applied to mirror the pruning
+    let mut mirror = original.clone();
+    mirror.apply_changeset_with_removals(changeset);
+    assert_eq!(mirror.initial_changeset(), graph.initial_changeset());
+
+    // pruning again does nothing
+    assert!(graph.prune_spent_before(3, &chain, tip).is_empty());
+
+    // once `tx_c` is spent by a confirmed tx, `tx_b` can be pruned
+    let tx_f = spending_tx(5, Some(OutPoint::new(tx_c.txid(), 0)));
+    let _ = graph.insert_tx(tx_f.clone());
+    let _ = graph.insert_anchor(tx_f.txid(), block(3));
+    assert_eq!(
+        graph.prune_spent_before(3, &chain, tip).removed,
+        [tx_b.txid()].into()
+    );
+}
+
+#[test]
+fn test_evict_unconfirmed_before() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let spending_tx = |lock_time: u32, prevout: Option<OutPoint>| Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::from_consensus(lock_time),
+        input: prevout
+            .map(|previous_output| TxIn {
+                previous_output,
+                ..Default::default()
+            })
+            .into_iter()
+            .collect(),
+        output: vec![TxOut {
+            value: 10_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+
+    // tx_a (stale) <- tx_b (recent): `tx_a` is kept for its recent descendant
+    let tx_a = spending_tx(0, None);
+    let tx_b = spending_tx(1, Some(OutPoint::new(tx_a.txid(), 0)));
+    // tx_c (stale) <- tx_d (stale): both are evicted
+    let tx_c = spending_tx(2, None);
+    let tx_d = spending_tx(3, Some(OutPoint::new(tx_c.txid(), 0)));
+    // tx_e is confirmed and never seen unconfirmed
+    let tx_e = spending_tx(4, None);
+    // tx_f was never seen (last seen at 0), so neither it nor its stale parent tx_g is evicted
+    let tx_g = spending_tx(5, None);
+    let tx_f = spending_tx(6, Some(OutPoint::new(tx_g.txid(), 0)));
+
+    let mut graph = TxGraph::<BlockId>::new([
+        tx_a.clone(),
+        tx_b.clone(),
+        tx_c.clone(),
+        tx_d.clone(),
+        tx_e.clone(),
+        tx_f.clone(),
+        tx_g.clone(),
+    ]);
+    let _ = graph.batch_insert_seen_at([
+        (tx_a.txid(), 10),
+        (tx_b.txid(), 100),
+        (tx_c.txid(), 10),
+        (tx_d.txid(), 20),
+        (tx_g.txid(), 10),
+    ]);
+    let _ = graph.insert_anchor(tx_e.txid(), tip);
+    let original = graph.clone();
+
+    let changeset = graph.evict_unconfirmed_before(50, &chain, tip);
+    assert_eq!(
+        changeset,
+        ChangeSetWithRemovals {
+            removed: [tx_c.txid(), tx_d.txid()].into(),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        graph.full_txs().map(|tx| tx.txid).collect::<BTreeSet<_>>(),
+        [
+            tx_a.txid(),
+            tx_b.txid(),
+            tx_e.txid(),
+            tx_f.txid(),
+            tx_g.txid()
+        ]
+        .into()
+    );
+
+    // the changeset can be applied to mirror the eviction
+    let mut mirror = original;
+    mirror.apply_changeset_with_removals(changeset);
+    assert_eq!(mirror.initial_changeset(), graph.initial_changeset());
+
+    // with a later cutoff, `tx_a` is evicted alongside its descendant
+    let changeset = graph.evict_unconfirmed_before(101, &chain, tip);
+    assert_eq!(changeset.removed, [tx_a.txid(), tx_b.txid()].into());
+    assert!(graph.get_tx(tx_e.txid()).is_some());
+    assert!(graph.get_tx(tx_f.txid()).is_some());
+}
+
+#[test]
+fn test_append_changeset_with_removals() {
+    let tx_a = common::new_tx(0);
+    let tx_b = common::new_tx(1);
+
+    let mut lhs = ChangeSetWithRemovals::<()>::from(ChangeSet {
+        txs: [tx_a.clone()].into(),
+        last_seen: [(tx_a.txid(), 10)].into(),
+        ..Default::default()
+    });
+    lhs.removed.insert(tx_b.txid());
+
+    // re-adding `tx_b` un-removes it
+    lhs.append(ChangeSetWithRemovals::from(ChangeSet {
+        txs: [tx_b.clone()].into(),
+        ..Default::default()
+    }));
+    assert!(lhs.removed.is_empty());
+
+    // adding a floating txout of a removed tx does not un-remove it, and the txout is kept when
+    // the changeset is applied
+    let outpoint = OutPoint::new(tx_a.txid(), 0);
+    let txout = TxOut {
+        value: 1_000,
+        script_pubkey: ScriptBuf::new(),
+    };
+    let mut removal = ChangeSetWithRemovals::<()> {
+        removed: [tx_a.txid()].into(),
+        ..Default::default()
+    };
+    removal.append(ChangeSetWithRemovals::from(ChangeSet {
+        txouts: [(outpoint, txout.clone())].into(),
+        ..Default::default()
+    }));
+    assert_eq!(removal.removed, [tx_a.txid()].into());
+    let mut graph = TxGraph::<()>::new([tx_a.clone()]);
+    graph.apply_changeset_with_removals(removal);
+    assert!(graph.get_tx(tx_a.txid()).is_none());
+    assert_eq!(graph.get_txout(outpoint), Some(&txout));
+
+    // removing `tx_a` drops its additions
+    lhs.append(ChangeSetWithRemovals {
+        removed: [tx_a.txid()].into(),
+        ..Default::default()
+    });
+    assert_eq!(lhs.graph.txs, [tx_b].into());
+    assert!(lhs.graph.last_seen.is_empty());
+    assert_eq!(lhs.removed, [tx_a.txid()].into());
+
+    // removing an anchor drops its addition, and re-adding it un-removes it
+    let anchor = (BlockId::default(), tx_a.txid());
+    let mut lhs = ChangeSetWithRemovals::from(ChangeSet {
+        anchors: [anchor].into(),
+        ..Default::default()
+    });
+    lhs.append(ChangeSetWithRemovals {
+        removed_anchors: [anchor].into(),
+        ..Default::default()
+    });
+    assert!(lhs.graph.anchors.is_empty());
+    assert_eq!(lhs.removed_anchors, [anchor].into());
+    lhs.append(ChangeSetWithRemovals::from(ChangeSet {
+        anchors: [anchor].into(),
+        ..Default::default()
+    }));
+    assert!(lhs.removed_anchors.is_empty());
+    assert_eq!(lhs.graph.anchors, [anchor].into());
+}
+
+#[test]
+fn test_invalidated_anchors() {
+    let mut chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C")), (3, h!("D"))];
+    let block = |height: u32, hash: BlockHash| BlockId { height, hash };
+
+    let txs = (0..5).map(common::new_tx).collect::<Vec<_>>();
+    let txids = txs.iter().map(Transaction::txid).collect::<Vec<_>>();
+    let mut graph = TxGraph::<BlockId>::new(txs);
+    // anchored below the invalidation height
+    let _ = graph.insert_anchor(txids[0], block(1, h!("B")));
+    // anchored at and above the invalidation height
+    let _ = graph.insert_anchor(txids[1], block(2, h!("C")));
+    let _ = graph.insert_anchor(txids[2], block(3, h!("D")));
+    // has both an invalidated anchor and a still-valid anchor
+    let _ = graph.insert_anchor(txids[3], block(1, h!("B")));
+    let _ = graph.insert_anchor(txids[3], block(3, h!("D")));
+    // has an invalidated anchor and an anchor which is not in the chain
+    let _ = graph.insert_anchor(txids[4], block(1, h!("B'")));
+    let _ = graph.insert_anchor(txids[4], block(2, h!("C")));
+
+    let mut expected = vec![txids[1], txids[2], txids[4]];
+    expected.sort_unstable();
+    assert_eq!(tx_graph::invalidated_anchors(&graph, &chain, 2), expected);
+
+    // the result is the same after disconnecting the blocks
+    let _ = chain.disconnect_from(2);
+    assert_eq!(tx_graph::invalidated_anchors(&graph, &chain, 2), expected);
+
+    assert!(tx_graph::invalidated_anchors(&graph, &chain, 4).is_empty());
+}
+
+#[test]
+fn test_anchors_inconsistent_with() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (3, h!("D"))];
+    let block = |height: u32, hash: BlockHash| BlockId { height, hash };
+
+    let txs = (0..3).map(common::new_tx).collect::<Vec<_>>();
+    let txids = txs.iter().map(Transaction::txid).collect::<Vec<_>>();
+    let mut graph = TxGraph::<ConfirmationHashAnchor>::new(txs);
+    let anchor = |anchor_block: BlockId, confirmation_block: BlockId| ConfirmationHashAnchor {
+        anchor_block,
+        confirmation_height: confirmation_block.height,
+        confirmation_hash: confirmation_block.hash,
+    };
+    // consistent with the chain
+    let _ = graph.insert_anchor(txids[0], anchor(block(3, h!("D")), block(1, h!("B"))));
+    // the chain has no block at height 2, so this cannot be checked
+    let _ = graph.insert_anchor(txids[0], anchor(block(2, h!("C")), block(2, h!("C"))));
+    // the anchor block is stale
+    let stale_anchor_block = anchor(block(3, h!("D'")), block(1, h!("B")));
+    let _ = graph.insert_anchor(txids[1], stale_anchor_block);
+    // the confirmation block is stale
+    let stale_confirmation_block = anchor(block(3, h!("D")), block(1, h!("B'")));
+    let _ = graph.insert_anchor(txids[2], stale_confirmation_block);
+
+    let mut expected = vec![
+        (txids[1], stale_anchor_block),
+        (txids[2], stale_confirmation_block),
+    ];
+    expected.sort_unstable();
+    assert_eq!(
+        tx_graph::anchors_inconsistent_with(&graph, &chain),
+        expected
+    );
+    assert!(tx_graph::anchors_inconsistent_with(&graph, &LocalChain::default()).is_empty());
+}
+
+#[test]
+fn test_remove_anchor() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let block = |height: u32, hash: BlockHash| BlockId { height, hash };
+
+    let tx_a = common::new_tx(0);
+    let tx_b = common::new_tx(1);
+    let mut graph = TxGraph::<BlockId>::new([tx_a.clone(), tx_b.clone()]);
+    let _ = graph.insert_anchor(tx_a.txid(), block(1, h!("B")));
+    let _ = graph.insert_anchor(tx_b.txid(), block(1, h!("B")));
+    let _ = graph.insert_anchor(tx_b.txid(), block(2, h!("C")));
+    let _ = graph.insert_seen_at(tx_a.txid(), 10);
+    let initial = graph.clone();
+    assert!(matches!(
+        graph.get_chain_position(&chain, tip, tx_a.txid()),
+        Some(ChainPosition::Confirmed(_))
+    ));
+
+    let changeset = graph.remove_anchor(tx_a.txid(), &block(1, h!("B")));
+    assert_eq!(
+        changeset.removed_anchors,
+        [(block(1, h!("B")), tx_a.txid())].into()
+    );
+    assert!(changeset.removed.is_empty());
+    assert_eq!(
+        graph.get_chain_position(&chain, tip, tx_a.txid()),
+        Some(ChainPosition::Unconfirmed(10))
+    );
+    assert!(graph.get_tx(tx_a.txid()).is_some());
+
+    // other anchors are kept
+    let changeset_b = graph.remove_anchor(tx_b.txid(), &block(1, h!("B")));
+    assert_eq!(
+        graph.get_chain_position(&chain, tip, tx_b.txid()),
+        Some(ChainPosition::Confirmed(&block(2, h!("C"))))
+    );
+
+    // removing a missing anchor does nothing
+    assert!(graph
+        .remove_anchor(tx_a.txid(), &block(1, h!("B")))
+        .is_empty());
+
+    // applying the changesets reproduces the graph
+    let mut mirror = initial;
+    mirror.apply_changeset_with_removals(changeset);
+    mirror.apply_changeset_with_removals(changeset_b);
+    assert_eq!(mirror.initial_changeset(), graph.initial_changeset());
+}
+
+#[test]
+fn test_canonical_tx() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+
+    let tx_confirmed = common::new_tx(0);
+    let tx_unconfirmed = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(h!("prev"), 0),
+            ..Default::default()
+        }],
+        ..common::new_tx(1)
+    };
+    // conflicts with `tx_unconfirmed` and is seen later
+    let tx_replacement = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(h!("prev"), 0),
+            ..Default::default()
+        }],
+        ..common::new_tx(2)
+    };
+    let anchor = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+
+    let mut graph = TxGraph::<BlockId>::new([
+        tx_confirmed.clone(),
+        tx_unconfirmed.clone(),
+        tx_replacement.clone(),
+    ]);
+    let _ = graph.insert_anchor(tx_confirmed.txid(), anchor);
+    let _ = graph.insert_seen_at(tx_unconfirmed.txid(), 10);
+    let _ = graph.insert_seen_at(tx_replacement.txid(), 20);
+
+    let canonical_tx = graph
+        .canonical_tx(&chain, tip, tx_confirmed.txid())
+        .expect("tx is confirmed");
+    assert_eq!(
+        canonical_tx.chain_position,
+        ChainPosition::Confirmed(&anchor)
+    );
+    assert_eq!(canonical_tx.tx_node.tx, &tx_confirmed);
+
+    assert_eq!(
+        graph
+            .canonical_tx(&chain, tip, tx_replacement.txid())
+            .map(|canonical_tx| canonical_tx.chain_position),
+        Some(ChainPosition::Unconfirmed(20))
+    );
+    // conflicted out
+    assert!(graph
+        .canonical_tx(&chain, tip, tx_unconfirmed.txid())
+        .is_none());
+    // unknown
+    assert!(graph.canonical_tx(&chain, tip, h!("unknown")).is_none());
+
+    // matches `list_chain_txs`
+    for canonical_tx in graph.list_chain_txs(&chain, tip) {
+        assert_eq!(
+            graph.canonical_tx(&chain, tip, canonical_tx.tx_node.txid),
+            Some(canonical_tx)
+        );
+    }
+}
+
+#[test]
+fn test_filter_chain_unspents_economical() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+    let tx = Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn::default()],
+        output: [330, 1_000, 10_000]
+            .iter()
+            .map(|&value| TxOut {
+                value,
+                script_pubkey: ScriptBuf::new(),
+            })
+            .collect(),
+    };
+    let txid = tx.txid();
+
+    let mut graph = TxGraph::<BlockId>::new([tx]);
+    let _ = graph.insert_anchor(
+        txid,
+        BlockId {
+            height: 1,
+            hash: h!("B"),
+        },
+    );
+
+    let tip = chain.tip().expect("must have tip").block_id();
+    let outpoints = (0..3_u32).map(|vout| (vout, OutPoint::new(txid, vout)));
+    let query = |feerate: FeeRate, weight: &dyn Fn(&u32) -> usize| {
+        graph
+            .filter_chain_unspents_economical(&chain, tip, outpoints.clone(), feerate, weight)
+            .map(|(vout, _)| vout)
+            .collect::<Vec<_>>()
+    };
+
+    // spending each output costs 680 sats at 10 sat/vB
+    let feerate = FeeRate::from_sat_per_vb_unchecked(10);
+    assert_eq!(query(feerate, &|_| 272), vec![1, 2]);
+    // the input weight can differ per output
+    assert_eq!(
+        query(feerate, &|&vout| if vout == 1 { 1_000 } else { 272 }),
+        vec![2]
+    );
+    assert_eq!(query(FeeRate::ZERO, &|_| 272), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_iter_anchors() {
+    let txs = (0..3).map(common::new_tx).collect::<Vec<_>>();
+    let txids = txs.iter().map(Transaction::txid).collect::<Vec<_>>();
+    let block = |height: u32| BlockId {
+        height,
+        hash: BlockHash::hash(&height.to_le_bytes()),
+    };
+
+    let mut graph = TxGraph::<BlockId>::new(txs);
+    assert_eq!(graph.iter_anchors().count(), 0);
+
+    let _ = graph.insert_anchor(txids[0], block(3));
+    // a tx with multiple anchors yields a pair per anchor
+    let _ = graph.insert_anchor(txids[1], block(1));
+    let _ = graph.insert_anchor(txids[1], block(2));
+    // txids[2] is unanchored
+
+    assert_eq!(
+        graph
+            .iter_anchors()
+            .map(|(txid, anchor)| (txid, anchor.height))
+            .collect::<Vec<_>>(),
+        vec![(txids[1], 1), (txids[1], 2), (txids[0], 3)]
+    );
+}
+
+#[test]
+fn test_txouts_with_spk_in_range() {
+    let spk = |bytes: &[u8]| ScriptBuf::from(bytes.to_vec());
+    let tx = Transaction {
+        version: 0x01,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn::default()],
+        output: [spk(&[0x02]), spk(&[0x00, 0xff]), spk(&[0x01]), spk(&[0x02])]
+            .into_iter()
+            .map(|script_pubkey| TxOut {
+                value: 10_000,
+                script_pubkey,
+            })
+            .collect(),
+    };
+    let txid = tx.txid();
+    let floating_op = OutPoint::new(h!("floating"), 0);
+
+    let mut graph = TxGraph::<BlockId>::new([tx]);
+    let _ = graph.insert_txout(
+        floating_op,
+        TxOut {
+            value: 1_000,
+            script_pubkey: spk(&[0x01, 0x00]),
+        },
+    );
+
+    let query = |range: (Bound<ScriptBuf>, Bound<ScriptBuf>)| {
+        graph
+            .txouts_with_spk_in_range(range)
+            .map(|(op, _)| op)
+            .collect::<Vec<_>>()
+    };
+
+    // ordered by raw spk bytes, then by outpoint
+    assert_eq!(
+        query((Bound::Unbounded, Bound::Unbounded)),
+        vec![
+            OutPoint::new(txid, 1),
+            OutPoint::new(txid, 2),
+            floating_op,
+            OutPoint::new(txid, 0),
+            OutPoint::new(txid, 3),
+        ]
+    );
+    // disjoint shards partition the outputs
+    assert_eq!(
+        query((Bound::Unbounded, Bound::Excluded(spk(&[0x01])))),
+        vec![OutPoint::new(txid, 1)]
+    );
+    assert_eq!(
+        query((Bound::Included(spk(&[0x01])), Bound::Excluded(spk(&[0x02])))),
+        vec![OutPoint::new(txid, 2), floating_op]
+    );
+    assert_eq!(
+        query((Bound::Included(spk(&[0x02])), Bound::Unbounded)),
+        vec![OutPoint::new(txid, 0), OutPoint::new(txid, 3)]
+    );
+}
+
+#[test]
+fn test_apply_update_with_transitions() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (2, h!("C"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let txs = (0..4).map(common::new_tx).collect::<Vec<_>>();
+    let txids = txs.iter().map(Transaction::txid).collect::<Vec<_>>();
+    let block = |height: u32, hash: BlockHash| BlockId { height, hash };
+
+    let mut graph = TxGraph::<BlockId>::new(txs[..3].to_vec());
+    // txids[0] is already confirmed
+    let _ = graph.insert_anchor(txids[0], block(1, h!("B")));
+    let _ = graph.insert_seen_at(txids[1], 100);
+
+    let mut update = TxGraph::<BlockId>::default();
+    // an additional anchor of an already confirmed tx is not a transition
+    let _ = update.insert_anchor(txids[0], block(2, h!("C")));
+    // an unconfirmed tx becomes confirmed
+    let _ = update.insert_anchor(txids[1], block(2, h!("C")));
+    // an anchor which is not in the best chain is not a transition
+    let _ = update.insert_anchor(txids[2], block(2, h!("not_C")));
+    // a tx that is new to the graph is confirmed straight away
+    let _ = update.insert_tx(txs[3].clone());
+    let _ = update.insert_anchor(txids[3], block(1, h!("B")));
+
+    let mut expected_txids = vec![txids[1], txids[3]];
+    expected_txids.sort();
+
+    let (changeset, newly_confirmed) =
+        graph.apply_update_with_transitions(&chain, tip, update.clone());
+    assert_eq!(newly_confirmed, expected_txids);
+    assert_eq!(changeset.txs, [txs[3].clone()].into());
+    assert_eq!(changeset.anchors.len(), 4);
+
+    // applying the same update again does not result in any transitions
+    let (changeset, newly_confirmed) = graph.apply_update_with_transitions(&chain, tip, update);
+    assert!(changeset.is_empty());
+    assert!(newly_confirmed.is_empty());
+}
+
+#[test]
+fn test_apply_update_with_transitions_anchor_only() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    let tx = common::new_tx(0);
+    let anchor = BlockId {
+        height: 1,
+        hash: h!("B"),
+    };
+    let mut graph = TxGraph::<BlockId>::default();
+
+    // the update only has an anchor, the full tx is unknown so it is not a transition
+    let mut update = TxGraph::<BlockId>::default();
+    let _ = update.insert_anchor(tx.txid(), anchor);
+    let (changeset, newly_confirmed) = graph.apply_update_with_transitions(&chain, tip, update);
+    assert_eq!(changeset.anchors, [(anchor, tx.txid())].into());
+    assert!(newly_confirmed.is_empty());
+
+    // the tx becomes confirmed once the full tx is known
+    let (changeset, newly_confirmed) =
+        graph.apply_update_with_transitions(&chain, tip, TxGraph::new([tx.clone()]));
+    assert_eq!(changeset.txs, [tx.clone()].into());
+    assert_eq!(newly_confirmed, vec![tx.txid()]);
+}
+
+#[test]
+fn test_txs_with_anchor_in_range() {
+    let txs = (0..4).map(common::new_tx).collect::<Vec<_>>();
+    let txids = txs.iter().map(Transaction::txid).collect::<Vec<_>>();
+    let block = |height: u32| BlockId {
+        height,
+        hash: BlockHash::hash(&height.to_le_bytes()),
+    };
+
+    let mut graph = TxGraph::<BlockId>::new(txs);
+    let _ = graph.insert_anchor(txids[0], block(2));
+    let _ = graph.insert_anchor(txids[1], block(5));
+    // a tx with multiple anchors is only emitted once with its lowest anchor
+    let _ = graph.insert_anchor(txids[2], block(8));
+    let _ = graph.insert_anchor(txids[2], block(4));
+    // txids[3] is unconfirmed and is never emitted
+
+    let query = |range: (Bound<u32>, Bound<u32>)| {
+        graph
+            .txs_with_anchor_in_range(range)
+            .map(|(txid, anchor)| (txid, anchor.height))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        query((Bound::Unbounded, Bound::Unbounded)),
+        vec![(txids[0], 2), (txids[2], 4), (txids[1], 5)]
+    );
+    assert_eq!(
+        query((Bound::Included(2), Bound::Included(4))),
+        vec![(txids[0], 2), (txids[2], 4)]
+    );
+    assert_eq!(
+        query((Bound::Excluded(2), Bound::Excluded(5))),
+        vec![(txids[2], 4)]
+    );
+    // only the lowest anchor of txids[2] is considered
+    assert_eq!(query((Bound::Included(6), Bound::Unbounded)), vec![]);
+}
+
+#[test]
+fn test_missing_blocks() {
+    /// An anchor implementation for testing, made up of `(the_anchor_block, random_data)`.
+    #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, core::hash::Hash)]
+    struct TestAnchor(BlockId);
+
+    impl Anchor for TestAnchor {
+        fn anchor_block(&self) -> BlockId {
+            self.0
+        }
+    }
+
+    struct Scenario<'a> {
+        name: &'a str,
+        graph: TxGraph<TestAnchor>,
+        chain: LocalChain,
+        exp_heights: &'a [u32],
+    }
+
+    const fn new_anchor(height: u32, hash: BlockHash) -> TestAnchor {
+        TestAnchor(BlockId { height, hash })
+    }
+
+    fn new_scenario<'a>(
+        name: &'a str,
+        graph_anchors: &'a [(Txid, TestAnchor)],
+        chain: &'a [(u32, BlockHash)],
+        exp_heights: &'a [u32],
+    ) -> Scenario<'a> {
+        Scenario {
+            name,
+            graph: {
+                let mut g = TxGraph::default();
                 for (txid, anchor) in graph_anchors {
                     let _ = g.insert_anchor(*txid, anchor.clone());
                 }
                 g
             },
-            chain: {
-                let mut c = LocalChain::default();
-                for (height, hash) in chain {
-                    let _ = c.insert_block(BlockId {
-                        height: *height,
-                        hash: *hash,
-                    });
-                }
-                c
+            chain: {
+                let mut c = LocalChain::default();
+                for (height, hash) in chain {
+                    let _ = c.insert_block(BlockId {
+                        height: *height,
+                        hash: *hash,
+                    });
+                }
+                c
+            },
+            exp_heights,
+        }
+    }
+
+    fn run(scenarios: &[Scenario]) {
+        for scenario in scenarios {
+            let Scenario {
+                name,
+                graph,
+                chain,
+                exp_heights,
+            } = scenario;
+
+            let heights = graph.missing_heights(chain).collect::<Vec<_>>();
+            assert_eq!(&heights, exp_heights, "scenario: {}", name);
+        }
+    }
+
+    run(&[
+        new_scenario(
+            "2 txs with the same anchor (2:B) which is missing from chain",
+            &[
+                (h!("tx_1"), new_anchor(2, h!("B"))),
+                (h!("tx_2"), new_anchor(2, h!("B"))),
+            ],
+            &[(1, h!("A")), (3, h!("C"))],
+            &[2],
+        ),
+        new_scenario(
+            "2 txs with different anchors at the same height, one of the anchors is missing",
+            &[
+                (h!("tx_1"), new_anchor(2, h!("B1"))),
+                (h!("tx_2"), new_anchor(2, h!("B2"))),
+            ],
+            &[(1, h!("A")), (2, h!("B1"))],
+            &[],
+        ),
+        new_scenario(
+            "tx with 2 anchors of same height which are missing from the chain",
+            &[
+                (h!("tx"), new_anchor(3, h!("C1"))),
+                (h!("tx"), new_anchor(3, h!("C2"))),
+            ],
+            &[(1, h!("A")), (4, h!("D"))],
+            &[3],
+        ),
+        new_scenario(
+            "tx with 2 anchors at the same height, chain has this height but does not match either anchor",
+            &[
+                (h!("tx"), new_anchor(4, h!("D1"))),
+                (h!("tx"), new_anchor(4, h!("D2"))),
+            ],
+            &[(4, h!("D3")), (5, h!("E"))],
+            &[],
+        ),
+        new_scenario(
+            "tx with 2 anchors at different heights, one anchor exists in chain, should return nothing",
+            &[
+                (h!("tx"), new_anchor(3, h!("C"))),
+                (h!("tx"), new_anchor(4, h!("D"))),
+            ],
+            &[(4, h!("D")), (5, h!("E"))],
+            &[],
+        ),
+        new_scenario(
+            "tx with 2 anchors at different heights, first height is already in chain with different hash, iterator should only return 2nd height",
+            &[
+                (h!("tx"), new_anchor(5, h!("E1"))),
+                (h!("tx"), new_anchor(6, h!("F1"))),
+            ],
+            &[(4, h!("D")), (5, h!("E")), (7, h!("G"))],
+            &[6],
+        ),
+        new_scenario(
+            "tx with 2 anchors at different heights, neither height is in chain, both heights should be returned",
+            &[
+                (h!("tx"), new_anchor(3, h!("C"))),
+                (h!("tx"), new_anchor(4, h!("D"))),
+            ],
+            &[(1, h!("A")), (2, h!("B"))],
+            &[3, 4],
+        ),
+    ]);
+}
+
+#[test]
+fn test_balance_spendable_at_height() {
+    let coinbase_height = 10_u32;
+    let chain = LocalChain::from(
+        (0..=coinbase_height + 100)
+            .map(|height| (height, h!("block")))
+            .collect::<BTreeMap<u32, BlockHash>>(),
+    );
+    let block_at = |height: u32| BlockId {
+        height,
+        hash: h!("block"),
+    };
+
+    let coinbase = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 50_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..common::new_tx(0)
+    };
+    let outpoint = OutPoint::new(coinbase.txid(), 0);
+
+    let mut graph = TxGraph::<BlockId>::default();
+    let _ = graph.insert_tx(coinbase.clone());
+    let _ = graph.insert_anchor(coinbase.txid(), block_at(coinbase_height));
+
+    let balance_at =
+        |height: u32| graph.balance(&chain, block_at(height), [((), outpoint)], |_, _| true);
+
+    let balance = balance_at(coinbase_height);
+    assert_eq!(balance.immature, 50_000);
+    assert_eq!(balance.confirmed, 0);
+    assert_eq!(
+        balance.immature_coinbase,
+        [(coinbase_height, 50_000)].into()
+    );
+
+    // one block short of maturity
+    let not_yet_mature = balance.spendable_at_height(coinbase_height + 98);
+    assert_eq!(not_yet_mature, balance);
+    assert_eq!(not_yet_mature, balance_at(coinbase_height + 98));
+
+    // the coinbase output has 100 confirmations and can be spent in the next block
+    let mature = balance.spendable_at_height(coinbase_height + 99);
+    assert_eq!(mature.immature, 0);
+    assert_eq!(mature.confirmed, 50_000);
+    assert!(mature.immature_coinbase.is_empty());
+    assert_eq!(mature, balance_at(coinbase_height + 99));
+    assert_eq!(balance.spendable_at_height(coinbase_height + 100), mature);
+
+    // a lower tip never moves matured amounts back into `immature`
+    assert_eq!(mature.spendable_at_height(coinbase_height), mature);
+}
+
+#[test]
+fn test_balance_spendable_at_height_keeps_locked_coinbase_locked() {
+    let coinbase_height = 10_u32;
+    let chain = LocalChain::from(
+        (0..=coinbase_height + 100)
+            .map(|height| (height, h!("block")))
+            .collect::<BTreeMap<u32, BlockHash>>(),
+    );
+    let block_at = |height: u32| BlockId {
+        height,
+        hash: h!("block"),
+    };
+
+    let coinbase = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![
+            TxOut {
+                value: 50_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+        ],
+        ..common::new_tx(0)
+    };
+    let outpoints = [
+        OutPoint::new(coinbase.txid(), 0),
+        OutPoint::new(coinbase.txid(), 1),
+    ];
+
+    let mut graph = TxGraph::<BlockId>::default();
+    let _ = graph.insert_tx(coinbase.clone());
+    let _ = graph.insert_anchor(coinbase.txid(), block_at(coinbase_height));
+
+    // only the second output is locked
+    let balance_at = |height: u32| {
+        graph.balance_with_locked(
+            &chain,
+            block_at(height),
+            outpoints.iter().map(|&op| ((), op)),
+            |_, _| true,
+            COINBASE_MATURITY,
+            |op| *op == outpoints[1],
+        )
+    };
+
+    let balance = balance_at(coinbase_height);
+    assert_eq!(balance.immature, 70_000);
+    assert_eq!(balance.locked, 0);
+    assert_eq!(
+        balance.immature_coinbase,
+        [(coinbase_height, 50_000)].into()
+    );
+    assert_eq!(
+        balance.locked_immature_coinbase,
+        [(coinbase_height, 20_000)].into()
+    );
+
+    // once matured, the locked output is `locked` rather than `confirmed`
+    let mature = balance.spendable_at_height(coinbase_height + 99);
+    assert_eq!(mature.immature, 0);
+    assert_eq!(mature.confirmed, 50_000);
+    assert_eq!(mature.locked, 20_000);
+    assert!(mature.locked_immature_coinbase.is_empty());
+    assert_eq!(mature, balance_at(coinbase_height + 99));
+}
+
+#[test]
+fn test_balance_with_coinbase_maturity() {
+    let coinbase_height = 5_u32;
+    let chain = LocalChain::from(
+        (0..=coinbase_height + 2)
+            .map(|height| (height, h!("block")))
+            .collect::<BTreeMap<u32, BlockHash>>(),
+    );
+    let block_at = |height: u32| BlockId {
+        height,
+        hash: h!("block"),
+    };
+
+    let coinbase = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 50_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+        ..common::new_tx(0)
+    };
+    let outpoint = OutPoint::new(coinbase.txid(), 0);
+
+    let mut graph = TxGraph::<BlockId>::default();
+    let _ = graph.insert_tx(coinbase.clone());
+    let _ = graph.insert_anchor(coinbase.txid(), block_at(coinbase_height));
+
+    let balance_at = |height: u32, coinbase_maturity: u32| {
+        graph.balance_with_coinbase_maturity(
+            &chain,
+            block_at(height),
+            [((), outpoint)],
+            |_, _| true,
+            coinbase_maturity,
+        )
+    };
+
+    // with a maturity of 1, the coinbase output is spendable once it is confirmed
+    assert_eq!(balance_at(coinbase_height, 1).confirmed, 50_000);
+    assert_eq!(balance_at(coinbase_height, 1).immature, 0);
+
+    // with a maturity of 2, the coinbase output is spendable one block after it is mined
+    let balance = balance_at(coinbase_height, 2);
+    assert_eq!(balance.immature, 50_000);
+    assert_eq!(balance.confirmed, 0);
+    assert_eq!(balance_at(coinbase_height + 1, 2).confirmed, 50_000);
+    assert_eq!(
+        balance.spendable_at_height_with_coinbase_maturity(coinbase_height + 1, 2),
+        balance_at(coinbase_height + 1, 2)
+    );
+
+    // the default maturity still applies to `balance`
+    assert_eq!(
+        graph
+            .balance(
+                &chain,
+                block_at(coinbase_height + 2),
+                [((), outpoint)],
+                |_, _| true
+            )
+            .immature,
+        50_000
+    );
+}
+
+#[test]
+fn test_balance_with_locked() {
+    let chain = LocalChain::from(
+        (0..=2)
+            .map(|height| (height, h!("block")))
+            .collect::<BTreeMap<u32, BlockHash>>(),
+    );
+    let tip = BlockId {
+        height: 2,
+        hash: h!("block"),
+    };
+
+    let tx = Transaction {
+        output: vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+        ],
+        ..common::new_tx(0)
+    };
+    let unconfirmed_tx = Transaction {
+        output: vec![
+            TxOut {
+                value: 3_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+            TxOut {
+                value: 4_000,
+                script_pubkey: ScriptBuf::new(),
             },
-            exp_heights,
+        ],
+        ..common::new_tx(1)
+    };
+
+    let mut graph = TxGraph::<BlockId>::default();
+    let _ = graph.insert_tx(tx.clone());
+    let _ = graph.insert_anchor(tx.txid(), tip);
+    let _ = graph.insert_tx(unconfirmed_tx.clone());
+    let _ = graph.insert_seen_at(unconfirmed_tx.txid(), 100);
+
+    let outpoints = [
+        OutPoint::new(tx.txid(), 0),
+        OutPoint::new(tx.txid(), 1),
+        OutPoint::new(unconfirmed_tx.txid(), 0),
+        OutPoint::new(unconfirmed_tx.txid(), 1),
+    ];
+    // only the first output of `unconfirmed_tx` is trusted
+    let balance_with_locked = |locked: &[OutPoint]| {
+        graph.balance_with_locked(
+            &chain,
+            tip,
+            outpoints.iter().map(|&op| (op, op)),
+            |&op, _| op != outpoints[3],
+            COINBASE_MATURITY,
+            |op| locked.contains(op),
+        )
+    };
+
+    // nothing is locked, so the balance is the same as `balance`
+    let unlocked = balance_with_locked(&[]);
+    assert_eq!(
+        unlocked,
+        graph.balance(
+            &chain,
+            tip,
+            outpoints.iter().map(|&op| (op, op)),
+            |&op, _| op != outpoints[3],
+        )
+    );
+    assert_eq!(unlocked.locked, 0);
+
+    // confirmed and trusted pending outputs are moved into `locked`, untrusted ones are not
+    let balance = balance_with_locked(&[outpoints[1], outpoints[2], outpoints[3]]);
+    assert_eq!(
+        balance,
+        Balance {
+            confirmed: 10_000,
+            untrusted_pending: 4_000,
+            locked: 23_000,
+            ..Default::default()
         }
+    );
+    assert_eq!(balance.trusted_spendable(), 10_000);
+    assert_eq!(balance.total(), unlocked.total());
+}
+
+#[test]
+fn test_confirmation_hash_anchor_invalidated_by_reorg() {
+    let tx = common::new_tx(0);
+    let txid = tx.txid();
+    let anchor = ConfirmationHashAnchor {
+        anchor_block: BlockId {
+            height: 3,
+            hash: h!("D"),
+        },
+        confirmation_height: 1,
+        confirmation_hash: h!("B"),
+    };
+
+    let mut graph = TxGraph::<ConfirmationHashAnchor>::default();
+    let _ = graph.insert_tx(tx);
+    let _ = graph.insert_anchor(txid, anchor);
+
+    let chain = local_chain![(0, h!("A")), (1, h!("B")), (3, h!("D"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+    assert_eq!(
+        graph.get_chain_position(&chain, tip, txid),
+        Some(ChainPosition::Confirmed(&anchor))
+    );
+
+    // The anchor block is still in the chain, but the confirmation block has been replaced.
+    let reorged_chain = local_chain![(0, h!("A")), (1, h!("B'")), (3, h!("D"))];
+    assert_eq!(
+        graph.get_chain_position(&reorged_chain, tip, txid),
+        Some(ChainPosition::Unconfirmed(0))
+    );
+
+    // We cannot disprove the confirmation block if the chain does not have its height.
+    let sparse_chain = local_chain![(0, h!("A")), (3, h!("D"))];
+    assert_eq!(
+        graph.get_chain_position(&sparse_chain, tip, txid),
+        Some(ChainPosition::Confirmed(&anchor))
+    );
+}
+
+#[test]
+fn test_unconfirmed_ancestor_package() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+
+    // tx_a is confirmed
+    let tx_a = Transaction {
+        output: vec![
+            TxOut {
+                value: 10_000,
+                ..Default::default()
+            },
+            TxOut {
+                value: 10_000,
+                ..Default::default()
+            },
+        ],
+        ..common::new_tx(0)
+    };
+    // tx_b spends tx_a:0 (fee 1_000)
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![
+            TxOut {
+                value: 4_500,
+                ..Default::default()
+            },
+            TxOut {
+                value: 4_500,
+                ..Default::default()
+            },
+        ],
+        ..common::new_tx(1)
+    };
+    // tx_c spends tx_b:0 and tx_a:1 (fee 500)
+    let tx_c = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(tx_b.txid(), 0),
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: OutPoint::new(tx_a.txid(), 1),
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut {
+            value: 14_000,
+            ..Default::default()
+        }],
+        ..common::new_tx(2)
+    };
+    // tx_d spends tx_b:1 and tx_c:0, so tx_b is a shared ancestor (fee 2_500)
+    let tx_d = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(tx_b.txid(), 1),
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: OutPoint::new(tx_c.txid(), 0),
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut {
+            value: 16_000,
+            ..Default::default()
+        }],
+        ..common::new_tx(3)
+    };
+
+    let mut graph = TxGraph::<BlockId>::default();
+    for tx in [&tx_a, &tx_b, &tx_c, &tx_d] {
+        let _ = graph.insert_tx(tx.clone());
     }
+    let _ = graph.insert_anchor(tx_a.txid(), tip);
 
-    fn run(scenarios: &[Scenario]) {
-        for scenario in scenarios {
-            let Scenario {
-                name,
-                graph,
-                chain,
-                exp_heights,
-            } = scenario;
+    assert_eq!(
+        graph.unconfirmed_ancestor_package(&chain, tip, tx_b.txid()),
+        Ok((Amount::from_sat(1_000), tx_b.vsize()))
+    );
+    assert_eq!(
+        graph.unconfirmed_ancestor_package(&chain, tip, tx_d.txid()),
+        Ok((
+            Amount::from_sat(1_000 + 500 + 2_500),
+            tx_b.vsize() + tx_c.vsize() + tx_d.vsize()
+        ))
+    );
 
-            let heights = graph.missing_heights(chain).collect::<Vec<_>>();
-            assert_eq!(&heights, exp_heights, "scenario: {}", name);
-        }
+    // a missing prevout results in an error instead of undercounting the fee
+    let missing_outpoint = OutPoint::new(h!("missing"), 0);
+    let tx_e = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(tx_d.txid(), 0),
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: missing_outpoint,
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut::default()],
+        ..common::new_tx(4)
+    };
+    let _ = graph.insert_tx(tx_e.clone());
+    assert_eq!(
+        graph.unconfirmed_ancestor_package(&chain, tip, tx_e.txid()),
+        Err(AncestorPackageError::CalculateFee(
+            CalculateFeeError::MissingTxOut(vec![missing_outpoint])
+        ))
+    );
+
+    // an unknown tx is also an error
+    assert_eq!(
+        graph.unconfirmed_ancestor_package(&chain, tip, h!("unknown")),
+        Err(AncestorPackageError::MissingTx(h!("unknown")))
+    );
+}
+
+#[test]
+fn test_unconfirmed_ancestor_package_skips_conflicted_parent() {
+    let chain = local_chain![(0, h!("A")), (1, h!("B"))];
+    let tip = chain.tip().expect("must have tip").block_id();
+
+    // tx_a is confirmed
+    let tx_a = Transaction {
+        output: vec![
+            TxOut {
+                value: 10_000,
+                ..Default::default()
+            },
+            TxOut {
+                value: 10_000,
+                ..Default::default()
+            },
+        ],
+        ..common::new_tx(0)
+    };
+    // tx_b spends tx_a:0 (fee 1_000), but is conflicted by tx_b2
+    let tx_b = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 9_000,
+            ..Default::default()
+        }],
+        ..common::new_tx(1)
+    };
+    // tx_b2 double spends tx_a:0 and is seen later (fee 2_000)
+    let tx_b2 = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint::new(tx_a.txid(), 0),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 8_000,
+            ..Default::default()
+        }],
+        ..common::new_tx(2)
+    };
+    // tx_c spends tx_b:0 and tx_a:1 (fee 500)
+    let tx_c = Transaction {
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(tx_b.txid(), 0),
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: OutPoint::new(tx_a.txid(), 1),
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut {
+            value: 18_500,
+            ..Default::default()
+        }],
+        ..common::new_tx(3)
+    };
+
+    let mut graph = TxGraph::<BlockId>::default();
+    for tx in [&tx_a, &tx_b, &tx_b2, &tx_c] {
+        let _ = graph.insert_tx(tx.clone());
     }
+    let _ = graph.insert_anchor(tx_a.txid(), tip);
+    let _ = graph.insert_seen_at(tx_b.txid(), 1);
+    let _ = graph.insert_seen_at(tx_b2.txid(), 2);
+    assert_eq!(graph.get_chain_position(&chain, tip, tx_b.txid()), None);
 
-    run(&[
-        new_scenario(
-            "2 txs with the same anchor (2:B) which is missing from chain",
-            &[
-                (h!("tx_1"), new_anchor(2, h!("B"))),
-                (h!("tx_2"), new_anchor(2, h!("B"))),
-            ],
-            &[(1, h!("A")), (3, h!("C"))],
-            &[2],
-        ),
-        new_scenario(
-            "2 txs with different anchors at the same height, one of the anchors is missing",
-            &[
-                (h!("tx_1"), new_anchor(2, h!("B1"))),
-                (h!("tx_2"), new_anchor(2, h!("B2"))),
-            ],
-            &[(1, h!("A")), (2, h!("B1"))],
-            &[],
-        ),
-        new_scenario(
-            "tx with 2 anchors of same height which are missing from the chain",
-            &[
-                (h!("tx"), new_anchor(3, h!("C1"))),
-                (h!("tx"), new_anchor(3, h!("C2"))),
-            ],
-            &[(1, h!("A")), (4, h!("D"))],
-            &[3],
-        ),
-        new_scenario(
-            "tx with 2 anchors at the same height, chain has this height but does not match either anchor",
-            &[
-                (h!("tx"), new_anchor(4, h!("D1"))),
-                (h!("tx"), new_anchor(4, h!("D2"))),
-            ],
-            &[(4, h!("D3")), (5, h!("E"))],
-            &[],
-        ),
-        new_scenario(
-            "tx with 2 anchors at different heights, one anchor exists in chain, should return nothing",
-            &[
-                (h!("tx"), new_anchor(3, h!("C"))),
-                (h!("tx"), new_anchor(4, h!("D"))),
-            ],
-            &[(4, h!("D")), (5, h!("E"))],
-            &[],
-        ),
-        new_scenario(
-            "tx with 2 anchors at different heights, first height is already in chain with different hash, iterator should only return 2nd height",
-            &[
-                (h!("tx"), new_anchor(5, h!("E1"))),
-                (h!("tx"), new_anchor(6, h!("F1"))),
-            ],
-            &[(4, h!("D")), (5, h!("E")), (7, h!("G"))],
-            &[6],
-        ),
-        new_scenario(
-            "tx with 2 anchors at different heights, neither height is in chain, both heights should be returned",
-            &[
-                (h!("tx"), new_anchor(3, h!("C"))),
-                (h!("tx"), new_anchor(4, h!("D"))),
-            ],
-            &[(1, h!("A")), (2, h!("B"))],
-            &[3, 4],
-        ),
-    ]);
+    // the conflicted tx_b is not counted
+    assert_eq!(
+        graph.unconfirmed_ancestor_package(&chain, tip, tx_c.txid()),
+        Ok((Amount::from_sat(500), tx_c.vsize()))
+    );
 }
@@ -0,0 +1,198 @@
+use std::{convert::TryFrom, time::Duration};
+
+use bdk_chain::bitcoin::Network;
+use electrum_client::{Client, Config, Error, Socks5Config};
+
+use crate::should_validate_domain;
+
+/// Options for connecting to an electrum server.
+///
+/// This is a convenience for building an [`electrum_client::Config`] with [`config`], or for
+/// connecting directly with [`connect`].
+///
+/// [`config`]: Self::config
+/// [`connect`]: Self::connect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOptions {
+    /// Address of a SOCKS5 proxy to route the connection through (e.g. `127.0.0.1:9050` for Tor).
+    ///
+    /// This is required to connect to `.onion` electrum servers, as the domain is resolved by the
+    /// proxy.
+    pub proxy: Option<String>,
+    /// Timeout for connecting and for each request.
+    ///
+    /// [`electrum_client`] only supports whole seconds of up to 255 seconds, so the timeout is
+    /// rounded up to whole seconds and capped at 255 seconds.
+    pub timeout: Option<Duration>,
+    /// Number of times a failed request is retried.
+    pub retry: u8,
+    /// Whether the domain of the server's certificate is validated (only applies to `ssl://`).
+    pub validate_domain: bool,
+}
+
+impl ClientOptions {
+    /// Construct [`ClientOptions`] with the default options for `network`.
+    ///
+    /// Domain validation is determined by [`should_validate_domain`].
+    pub fn new(network: Network) -> Self {
+        Self {
+            proxy: None,
+            timeout: None,
+            retry: 1,
+            validate_domain: should_validate_domain(network),
+        }
+    }
+
+    /// Build the [`electrum_client::Config`] of these options.
+    pub fn config(&self) -> Config {
+        Config::builder()
+            .socks5(self.proxy.as_ref().map(Socks5Config::new))
+            .timeout(self.timeout.map(|timeout| {
+                let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+                u8::try_from(secs).unwrap_or(u8::MAX)
+            }))
+            .retry(self.retry)
+            .validate_domain(self.validate_domain)
+            .build()
+    }
+
+    /// Connect to the electrum server at `url` with these options.
+    ///
+    /// # Errors
+    ///
+    /// If a [`proxy`] is set, I/O errors of establishing the connection are returned as
+    /// [`ConnectError::Proxy`], as these come from the SOCKS5 layer (e.g. the proxy is unreachable
+    /// or refuses the connection). Other errors (e.g. of the SSL handshake with the server) are
+    /// returned as [`ConnectError::Electrum`].
+    ///
+    /// [`proxy`]: Self::proxy
+    pub fn connect(&self, url: &str) -> Result<Client, ConnectError> {
+        Client::from_config(url, self.config()).map_err(|error| self.connect_error(error))
+    }
+
+    fn connect_error(&self, error: Error) -> ConnectError {
+        match (&self.proxy, error) {
+            (Some(proxy), Error::IOError(error)) => ConnectError::Proxy {
+                proxy: proxy.clone(),
+                error: Error::IOError(error),
+            },
+            (_, error) => ConnectError::Electrum(error),
+        }
+    }
+}
+
+/// An error returned by [`ClientOptions::connect`].
+#[derive(Debug)]
+pub enum ConnectError {
+    /// Failed to connect to the electrum server through the SOCKS5 proxy.
+    Proxy {
+        /// The address of the proxy.
+        proxy: String,
+        /// The underlying error.
+        error: Error,
+    },
+    /// Failed to connect to the electrum server.
+    Electrum(Error),
+}
+
+impl core::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConnectError::Proxy { proxy, error } => write!(
+                f,
+                "failed to connect through socks5 proxy {} (is the proxy running?): {}",
+                proxy, error
+            ),
+            ConnectError::Electrum(error) => {
+                write!(f, "failed to connect to electrum server: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The address of a local port which nothing listens on.
+    fn closed_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn options(proxy: Option<String>) -> ClientOptions {
+        ClientOptions {
+            proxy,
+            ..ClientOptions::new(Network::Regtest)
+        }
+    }
+
+    #[test]
+    fn config_rounds_timeout_up_to_whole_seconds() {
+        let timeout = |timeout: Duration| {
+            ClientOptions {
+                timeout: Some(timeout),
+                ..options(None)
+            }
+            .config()
+            .timeout()
+        };
+        assert_eq!(options(None).config().timeout(), None);
+        assert_eq!(
+            timeout(Duration::from_secs(30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            timeout(Duration::from_millis(1_500)),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            timeout(Duration::from_millis(1)),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            timeout(Duration::from_secs(1_000)),
+            Some(Duration::from_secs(255))
+        );
+    }
+
+    #[test]
+    fn connect_maps_unreachable_proxy_to_proxy_error() {
+        let proxy = closed_addr();
+        match options(Some(proxy.clone())).connect("tcp://electrum.example:50001") {
+            Err(ConnectError::Proxy {
+                proxy: error_proxy,
+                error: Error::IOError(_),
+            }) => assert_eq!(error_proxy, proxy),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn connect_maps_unreachable_server_to_electrum_error() {
+        let url = format!("tcp://{}", closed_addr());
+        assert!(matches!(
+            options(None).connect(&url),
+            Err(ConnectError::Electrum(Error::IOError(_)))
+        ));
+    }
+
+    #[test]
+    fn connect_error_only_maps_io_errors_to_proxy_error() {
+        let options = options(Some("127.0.0.1:9050".to_string()));
+        assert!(matches!(
+            options.connect_error(Error::IOError(std::io::ErrorKind::ConnectionRefused.into())),
+            ConnectError::Proxy { .. }
+        ));
+        assert!(matches!(
+            options.connect_error(Error::MissingDomain),
+            ConnectError::Electrum(Error::MissingDomain)
+        ));
+        assert!(matches!(
+            options.connect_error(Error::InvalidDNSNameError("onion".to_string())),
+            ConnectError::Electrum(Error::InvalidDNSNameError(_))
+        ));
+    }
+}
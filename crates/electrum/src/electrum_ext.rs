@@ -1,15 +1,23 @@
 use bdk_chain::{
-    bitcoin::{OutPoint, ScriptBuf, Transaction, Txid},
+    bitcoin::{
+        block::Header, blockdata::constants::genesis_block, hashes::Hash, Block, BlockHash,
+        FeeRate, Network, OutPoint, ScriptBuf, Transaction, Txid,
+    },
     keychain::WalletUpdate,
     local_chain::{self, CheckPoint},
+    serde::{Deserialize, Serialize},
     tx_graph::{self, TxGraph},
-    Anchor, BlockId, ConfirmationHeightAnchor, ConfirmationTimeAnchor,
+    Anchor, BlockId, ConfirmationHashAnchor, ConfirmationHeightAnchor, ConfirmationTimeAnchor,
 };
-use electrum_client::{Client, ElectrumApi, Error, HeaderNotification};
+use electrum_client::{Client, ElectrumApi, Error, HeaderNotification, Param, ScriptStatus};
 use std::{
+    cell::Cell,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Debug,
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
 };
 
 /// We assume that a block of this depth and deeper cannot be reorged.
@@ -50,33 +58,121 @@ impl<K, A: Anchor> ElectrumUpdate<K, A> {
             .collect()
     }
 
+    /// Determine the prevouts of the update's transactions whose txouts are missing from `graph`.
+    ///
+    /// These are the txouts needed to calculate the fees of the update's transactions (refer to
+    /// [`TxGraph::calculate_fee`]). Unlike fetching the parent transactions (as full transactions
+    /// with [`missing_full_txs`]), only the txouts can be stored with [`fetch_missing_txouts`].
+    ///
+    /// The inputs of a transaction are only known once its full transaction is in `graph`, so this
+    /// should be called with a `graph` which the finalized update has been applied to.
+    /// Transactions of the update which are not in `graph` are skipped. Outpoints are returned in
+    /// order and without duplicates.
+    ///
+    /// [`missing_full_txs`]: Self::missing_full_txs
+    /// [`fetch_missing_txouts`]: Self::fetch_missing_txouts
+    pub fn missing_txouts<A2>(&self, graph: &TxGraph<A2>) -> Vec<OutPoint> {
+        self.graph_update
+            .keys()
+            .filter_map(|&txid| graph.get_tx(txid))
+            .filter(|tx| !tx.is_coin_base())
+            .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+            .filter(|&outpoint| graph.get_txout(outpoint).is_none())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Fetches the [`missing_txouts`] of `graph` from `client` and returns a [`TxGraph`] update
+    /// which only contains these txouts.
+    ///
+    /// The electrum protocol can only serve whole transactions, so each parent transaction is
+    /// still downloaded (once, even if multiple of its outputs are missing). However, only the
+    /// missing txouts are kept, so the parent transactions are not stored.
+    ///
+    /// [`missing_txouts`]: Self::missing_txouts
+    pub fn fetch_missing_txouts<A2>(
+        &self,
+        client: &Client,
+        graph: &TxGraph<A2>,
+    ) -> Result<TxGraph<A>, Error> {
+        let missing = self.missing_txouts(graph);
+        let parent_txids = missing
+            .iter()
+            .map(|outpoint| outpoint.txid)
+            .collect::<BTreeSet<_>>();
+        let parents = client
+            .batch_transaction_get(&parent_txids)?
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect::<HashMap<_, _>>();
+
+        let mut txout_update = TxGraph::<A>::default();
+        for outpoint in missing {
+            let txout = parents
+                .get(&outpoint.txid)
+                .and_then(|tx| tx.output.get(outpoint.vout as usize));
+            if let Some(txout) = txout {
+                let _ = txout_update.insert_txout(outpoint, txout.clone());
+            }
+        }
+        Ok(txout_update)
+    }
+
     /// Finalizes update with `missing` txids to fetch from `client`.
     ///
     /// Refer to [`ElectrumUpdate`].
     pub fn finalize(
         self,
-        client: &Client,
+        client: &impl ElectrumApi,
         seen_at: Option<u64>,
         missing: Vec<Txid>,
     ) -> Result<WalletUpdate<K, A>, Error> {
+        let graph = self.finalize_graph(client, seen_at, missing)?;
+        Ok(WalletUpdate {
+            chain: self.finalize_chain_only(),
+            last_active_indices: self.keychain_update,
+            graph,
+        })
+    }
+
+    /// Returns the [`local_chain::Update`] of this update.
+    ///
+    /// This allows the chain update to be applied before the graph update (obtained with
+    /// [`finalize_graph`]). Applying the chain update and then the graph update results in the same
+    /// state as applying the [`WalletUpdate`] returned by [`finalize`].
+    ///
+    /// [`finalize`]: Self::finalize
+    /// [`finalize_graph`]: Self::finalize_graph
+    pub fn finalize_chain_only(&self) -> local_chain::Update {
+        local_chain::Update {
+            tip: self.new_tip.clone(),
+            introduce_older_blocks: true,
+        }
+    }
+
+    /// Fetches the `missing` full transactions from `client` and returns the [`TxGraph`] update.
+    ///
+    /// Refer to [`finalize_chain_only`] for applying the chain and graph updates separately.
+    ///
+    /// [`finalize_chain_only`]: Self::finalize_chain_only
+    pub fn finalize_graph(
+        &self,
+        client: &impl ElectrumApi,
+        seen_at: Option<u64>,
+        missing: Vec<Txid>,
+    ) -> Result<TxGraph<A>, Error> {
         let new_txs = client.batch_transaction_get(&missing)?;
         let mut graph_update = TxGraph::<A>::new(new_txs);
-        for (txid, anchors) in self.graph_update {
+        for (txid, anchors) in &self.graph_update {
             if let Some(seen_at) = seen_at {
-                let _ = graph_update.insert_seen_at(txid, seen_at);
+                let _ = graph_update.insert_seen_at(*txid, seen_at);
             }
             for anchor in anchors {
-                let _ = graph_update.insert_anchor(txid, anchor);
+                let _ = graph_update.insert_anchor(*txid, anchor.clone());
             }
         }
-        Ok(WalletUpdate {
-            last_active_indices: self.keychain_update,
-            graph: graph_update,
-            chain: local_chain::Update {
-                tip: self.new_tip,
-                introduce_older_blocks: true,
-            },
-        })
+        Ok(graph_update)
     }
 }
 
@@ -93,6 +189,44 @@ impl<K> ElectrumUpdate<K, ConfirmationHeightAnchor> {
         seen_at: Option<u64>,
         missing: Vec<Txid>,
     ) -> Result<WalletUpdate<K, ConfirmationTimeAnchor>, Error> {
+        self.finalize_with_headers(client, seen_at, missing, |height_anchor, header| {
+            ConfirmationTimeAnchor {
+                anchor_block: height_anchor.anchor_block,
+                confirmation_height: height_anchor.confirmation_height,
+                confirmation_time: header.time as u64,
+            }
+        })
+    }
+
+    /// Finalizes the [`ElectrumUpdate`] with `new_txs` and anchors of type
+    /// [`ConfirmationHashAnchor`].
+    ///
+    /// The confirmation block hash is obtained from the header at the confirmation height. This
+    /// allows anchors to be invalidated if the confirmation block gets reorged out of the chain.
+    pub fn finalize_as_confirmation_hash(
+        self,
+        client: &Client,
+        seen_at: Option<u64>,
+        missing: Vec<Txid>,
+    ) -> Result<WalletUpdate<K, ConfirmationHashAnchor>, Error> {
+        self.finalize_with_headers(client, seen_at, missing, |height_anchor, header| {
+            ConfirmationHashAnchor {
+                anchor_block: height_anchor.anchor_block,
+                confirmation_height: height_anchor.confirmation_height,
+                confirmation_hash: header.block_hash(),
+            }
+        })
+    }
+
+    /// Finalizes the [`ElectrumUpdate`] and converts anchors with the header of the confirmation
+    /// block.
+    fn finalize_with_headers<A2: Anchor>(
+        self,
+        client: &Client,
+        seen_at: Option<u64>,
+        missing: Vec<Txid>,
+        to_anchor: impl Fn(ConfirmationHeightAnchor, &Header) -> A2,
+    ) -> Result<WalletUpdate<K, A2>, Error> {
         let update = self.finalize(client, seen_at, missing)?;
 
         let relevant_heights = {
@@ -106,16 +240,11 @@ impl<K> ElectrumUpdate<K, ConfirmationHeightAnchor> {
                 .collect::<Vec<_>>()
         };
 
-        let height_to_time = relevant_heights
+        let height_to_header = relevant_heights
             .clone()
             .into_iter()
-            .zip(
-                client
-                    .batch_block_header(relevant_heights)?
-                    .into_iter()
-                    .map(|bh| bh.time as u64),
-            )
-            .collect::<HashMap<u32, u64>>();
+            .zip(client.batch_block_header(relevant_heights)?)
+            .collect::<HashMap<u32, Header>>();
 
         let graph_changeset = {
             let old_changeset = TxGraph::default().apply_update(update.graph.clone());
@@ -127,14 +256,8 @@ impl<K> ElectrumUpdate<K, ConfirmationHeightAnchor> {
                     .anchors
                     .into_iter()
                     .map(|(height_anchor, txid)| {
-                        let confirmation_height = height_anchor.confirmation_height;
-                        let confirmation_time = height_to_time[&confirmation_height];
-                        let time_anchor = ConfirmationTimeAnchor {
-                            anchor_block: height_anchor.anchor_block,
-                            confirmation_height,
-                            confirmation_time,
-                        };
-                        (time_anchor, txid)
+                        let header = &height_to_header[&height_anchor.confirmation_height];
+                        (to_anchor(height_anchor, header), txid)
                     })
                     .collect(),
             }
@@ -152,6 +275,292 @@ impl<K> ElectrumUpdate<K, ConfirmationHeightAnchor> {
     }
 }
 
+/// Progress of a keychain scan, reported by [`ElectrumExt::scan_with_progress`] (or to
+/// [`ScanOptions::progress`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanProgress<K> {
+    /// The keychain currently being scanned.
+    pub keychain: K,
+    /// The last derivation index of the keychain that was processed.
+    pub index: u32,
+    /// The total number of script pubkeys processed so far during the scan.
+    pub spks_processed: usize,
+}
+
+/// The stop gap of each keychain, used by [`ElectrumExt::scan`].
+///
+/// Keychains that are not in `keychains` use the `default` stop gap. A `usize` converts into a
+/// [`StopGap`] that uses the same stop gap for all keychains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopGap<K> {
+    /// The stop gap of keychains that are not in `keychains`.
+    pub default: usize,
+    /// The stop gap of specific keychains.
+    pub keychains: BTreeMap<K, usize>,
+}
+
+impl<K: Ord> StopGap<K> {
+    /// Get the stop gap of `keychain`.
+    pub fn of_keychain(&self, keychain: &K) -> usize {
+        self.keychains
+            .get(keychain)
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// The largest stop gap of all keychains.
+    fn max(&self) -> usize {
+        self.keychains
+            .values()
+            .copied()
+            .fold(self.default, usize::max)
+    }
+}
+
+impl<K> From<usize> for StopGap<K> {
+    fn from(stop_gap: usize) -> Self {
+        Self {
+            default: stop_gap,
+            keychains: BTreeMap::new(),
+        }
+    }
+}
+
+/// Policy for retrying electrum requests that fail with a transient error.
+///
+/// Each request made during a scan (such as a single batch of script pubkey histories) is retried
+/// up to `max_retries` times if it fails with an I/O error. The delay before the `n`th retry is
+/// `base_delay * 2^(n - 1)`. Requests that were completed are never redone.
+///
+/// The [`Default`] policy does not retry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of times a failed request is retried.
+    pub max_retries: usize,
+    /// The delay before the first retry, which is doubled for each subsequent retry.
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Call `request`, retrying on transient errors as specified by the policy.
+    ///
+    /// Once retries are exhausted, the error of the last attempt is returned.
+    fn call<T>(&self, mut request: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut retries = 0_usize;
+        loop {
+            match request() {
+                Err(Error::IOError(_) | Error::SharedIOError(_) | Error::AllAttemptsErrored(_))
+                    if retries < self.max_retries =>
+                {
+                    let factor = 2_u32.saturating_pow(retries.min(u32::MAX as usize) as u32);
+                    thread::sleep(self.base_delay.saturating_mul(factor));
+                    retries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Options of [`ElectrumExt::scan_with_options`].
+///
+/// The [`Default`] options do not retry failed requests, do not report progress and cannot be
+/// cancelled.
+pub struct ScanOptions<'a, K> {
+    /// Policy for retrying requests that fail with a transient error.
+    pub retry: RetryPolicy,
+    /// Called with a [`ScanProgress`] after each batch of script pubkeys is processed.
+    ///
+    /// It is never called after the scan returns.
+    pub progress: Option<&'a mut dyn FnMut(ScanProgress<K>)>,
+    /// The scan stops early once this is set.
+    ///
+    /// This is checked before each request, so a scan that is cancelled (e.g. from another thread)
+    /// returns once the in-flight request completes. The update of a cancelled scan only contains
+    /// what was gathered up to that point, but it is consistent with its chain tip, so it can be
+    /// applied as usual. If a reorg is detected after cancelling, the scan is not redone and the
+    /// returned update only contains the latest chain tip (the transactions and keychain indices
+    /// gathered are discarded).
+    pub should_stop: Option<&'a AtomicBool>,
+}
+
+impl<'a, K> Default for ScanOptions<'a, K> {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::default(),
+            progress: None,
+            should_stop: None,
+        }
+    }
+}
+
+impl<'a, K> Debug for ScanOptions<'a, K> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ScanOptions")
+            .field("retry", &self.retry)
+            .field("progress", &self.progress.is_some())
+            .field("should_stop", &self.should_stop)
+            .finish()
+    }
+}
+
+/// Tracks whether a scan is cancelled, as requested by [`ScanOptions::should_stop`].
+struct Cancellation<'a> {
+    should_stop: Option<&'a AtomicBool>,
+    // set once `should_stop` is observed, so that the remaining requests are skipped
+    cancelled: Cell<bool>,
+}
+
+impl<'a> Cancellation<'a> {
+    fn new(should_stop: Option<&'a AtomicBool>) -> Self {
+        Self {
+            should_stop,
+            cancelled: Cell::new(false),
+        }
+    }
+
+    /// Whether the scan should continue, which is `false` once `should_stop` is observed.
+    fn keep_going(&self) -> bool {
+        if !self.cancelled.get()
+            && self
+                .should_stop
+                .map_or(false, |should_stop| should_stop.load(Ordering::Relaxed))
+        {
+            self.cancelled.set(true);
+        }
+        !self.cancelled.get()
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+}
+
+/// Records the progress of [`ElectrumExt::scan_without_keychain_resumable`] so that an interrupted
+/// scan can continue where it left off.
+///
+/// The cursor is only valid for the chain tip it was created with. If the tip of the electrum
+/// server has changed when the scan is resumed, the cursor is discarded and the scan restarts from
+/// scratch. The cursor is cleared (reset to [`ScanCursor::default`]) once a scan completes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "bdk_chain::serde")]
+pub struct ScanCursor {
+    tip: Option<BlockId>,
+    spks: usize,
+    txids: usize,
+    outpoints: usize,
+    graph_update: HashMap<Txid, BTreeSet<ConfirmationHeightAnchor>>,
+}
+
+impl ScanCursor {
+    /// Whether the cursor has no recorded progress (the next scan starts from scratch).
+    pub fn is_empty(&self) -> bool {
+        self.spks == 0 && self.txids == 0 && self.outpoints == 0 && self.graph_update.is_empty()
+    }
+
+    fn reset(&mut self, tip: BlockId) {
+        *self = Self {
+            tip: Some(tip),
+            ..Default::default()
+        };
+    }
+
+    fn record(&mut self, batch_update: ElectrumUpdate<(), ConfirmationHeightAnchor>) {
+        for (txid, anchors) in batch_update.graph_update {
+            self.graph_update.entry(txid).or_default().extend(anchors);
+        }
+    }
+}
+
+/// Script pubkeys subscribed to with [`ElectrumExt::subscribe_spks`], alongside their last known
+/// status.
+///
+/// A status is the hash of the script pubkey's history (as defined by the electrum protocol), which
+/// is `None` if the script pubkey has no history. Pass the subscription to
+/// [`ElectrumExt::poll_notifications`] to find out which script pubkeys have new activity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subscription {
+    statuses: BTreeMap<ScriptBuf, Option<ScriptStatus>>,
+}
+
+impl Subscription {
+    /// Iterate over the subscribed script pubkeys.
+    pub fn spks(&self) -> impl Iterator<Item = &ScriptBuf> {
+        self.statuses.keys()
+    }
+
+    /// Get the last known status of `spk`, or `None` if `spk` is not subscribed to.
+    pub fn status(&self, spk: &ScriptBuf) -> Option<Option<ScriptStatus>> {
+        self.statuses.get(spk).copied()
+    }
+
+    /// Record `status` as the latest status of `spk`, returning whether it has changed.
+    fn update(&mut self, spk: &ScriptBuf, status: Option<ScriptStatus>) -> bool {
+        match self.statuses.get_mut(spk) {
+            Some(old_status) if *old_status != status => {
+                *old_status = status;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Features of an electrum server, returned by [`ElectrumExt::server_capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    /// The software version of the server.
+    pub server_version: String,
+    /// The minimum protocol version supported by the server.
+    pub protocol_min: String,
+    /// The maximum protocol version supported by the server.
+    pub protocol_max: String,
+    /// The hash of the genesis block of the server's chain.
+    pub genesis_hash: BlockHash,
+    /// The height below which the server has pruned blocks (if the server is pruned).
+    pub pruning: Option<i64>,
+}
+
+impl ServerCapabilities {
+    /// Check that the server's chain is the chain of `network`, by comparing genesis hashes.
+    pub fn check_network(&self, network: Network) -> Result<(), NetworkMismatchError> {
+        let expected_genesis_hash = genesis_block(network).block_hash();
+        if self.genesis_hash == expected_genesis_hash {
+            Ok(())
+        } else {
+            Err(NetworkMismatchError {
+                network,
+                expected_genesis_hash,
+                server_genesis_hash: self.genesis_hash,
+            })
+        }
+    }
+}
+
+/// An error returned by [`ServerCapabilities::check_network`] when the electrum server is on a
+/// different network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkMismatchError {
+    /// The expected network.
+    pub network: Network,
+    /// The genesis hash of `network`.
+    pub expected_genesis_hash: BlockHash,
+    /// The genesis hash of the server's chain.
+    pub server_genesis_hash: BlockHash,
+}
+
+impl core::fmt::Display for NetworkMismatchError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "electrum server is not on {}: expected genesis hash {}, server has genesis hash {}",
+            self.network, self.expected_genesis_hash, self.server_genesis_hash
+        )
+    }
+}
+
+impl std::error::Error for NetworkMismatchError {}
+
 /// Trait to extend [`Client`] functionality.
 pub trait ElectrumExt<A> {
     /// Scan the blockchain (via electrum) for the data specified and returns a [`ElectrumUpdate`].
@@ -163,17 +572,89 @@ pub trait ElectrumExt<A> {
     ///     want to included in the update
     ///
     /// The scan for each keychain stops after a gap of `stop_gap` script pubkeys with no associated
-    /// transactions. `batch_size` specifies the max number of script pubkeys to request for in a
-    /// single batch request.
+    /// transactions. A single `usize` can be provided to use the same stop gap for all keychains,
+    /// refer to [`StopGap`] for specifying the stop gap per keychain. `batch_size` specifies the
+    /// max number of script pubkeys to request for in a single batch request.
+    ///
+    /// Use [`scan_with_options`] to retry failed requests, report progress or cancel the scan.
+    ///
+    /// [`scan_with_options`]: ElectrumExt::scan_with_options
     fn scan<K: Ord + Clone>(
         &self,
         prev_tip: Option<CheckPoint>,
         keychain_spks: BTreeMap<K, impl IntoIterator<Item = (u32, ScriptBuf)>>,
         txids: impl IntoIterator<Item = Txid>,
         outpoints: impl IntoIterator<Item = OutPoint>,
-        stop_gap: usize,
+        stop_gap: impl Into<StopGap<K>>,
+        batch_size: usize,
+    ) -> Result<ElectrumUpdate<K, A>, Error> {
+        self.scan_with_options(
+            prev_tip,
+            keychain_spks,
+            txids,
+            outpoints,
+            stop_gap,
+            batch_size,
+            ScanOptions::default(),
+        )
+        .map(|(update, _)| update)
+    }
+
+    /// Same as [`scan`], but reports the progress of scanning `keychain_spks` to `progress`.
+    ///
+    /// `progress` is called with a [`ScanProgress`] after each batch of script pubkeys is
+    /// processed. It is never called after this method returns. This is a shorthand for
+    /// [`scan_with_options`] with only [`ScanOptions::progress`] set.
+    ///
+    /// [`scan`]: ElectrumExt::scan
+    /// [`scan_with_options`]: ElectrumExt::scan_with_options
+    #[allow(clippy::too_many_arguments)]
+    fn scan_with_progress<K: Ord + Clone>(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        keychain_spks: BTreeMap<K, impl IntoIterator<Item = (u32, ScriptBuf)>>,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        stop_gap: impl Into<StopGap<K>>,
+        batch_size: usize,
+        progress: &mut dyn FnMut(ScanProgress<K>),
+    ) -> Result<ElectrumUpdate<K, A>, Error> {
+        self.scan_with_options(
+            prev_tip,
+            keychain_spks,
+            txids,
+            outpoints,
+            stop_gap,
+            batch_size,
+            ScanOptions {
+                progress: Some(progress),
+                ..Default::default()
+            },
+        )
+        .map(|(update, _)| update)
+    }
+
+    /// Same as [`scan`], but configured with [`ScanOptions`].
+    ///
+    /// The options specify how requests that fail with a transient error are retried, where the
+    /// progress of scanning `keychain_spks` is reported to, and whether the scan is cancelled. The
+    /// returned `bool` is `true` if the scan was cancelled (refer to [`ScanOptions::should_stop`]).
+    /// With [`ScanOptions::default`], this behaves exactly like [`scan`].
+    ///
+    /// Retries are done per request, so work that was already completed is not redone.
+    ///
+    /// [`scan`]: ElectrumExt::scan
+    #[allow(clippy::too_many_arguments)]
+    fn scan_with_options<K: Ord + Clone>(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        keychain_spks: BTreeMap<K, impl IntoIterator<Item = (u32, ScriptBuf)>>,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        stop_gap: impl Into<StopGap<K>>,
         batch_size: usize,
-    ) -> Result<ElectrumUpdate<K, A>, Error>;
+        options: ScanOptions<'_, K>,
+    ) -> Result<(ElectrumUpdate<K, A>, bool), Error>;
 
     /// Convenience method to call [`scan`] without requiring a keychain.
     ///
@@ -186,114 +667,758 @@ pub trait ElectrumExt<A> {
         outpoints: impl IntoIterator<Item = OutPoint>,
         batch_size: usize,
     ) -> Result<ElectrumUpdate<(), A>, Error> {
+        self.scan_without_keychain_with_options(
+            prev_tip,
+            misc_spks,
+            txids,
+            outpoints,
+            batch_size,
+            ScanOptions::default(),
+        )
+        .map(|(update, _)| update)
+    }
+
+    /// Convenience method to call [`scan_with_options`] without requiring a keychain.
+    ///
+    /// [`scan_with_options`]: ElectrumExt::scan_with_options
+    fn scan_without_keychain_with_options(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        misc_spks: impl IntoIterator<Item = ScriptBuf>,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        batch_size: usize,
+        options: ScanOptions<'_, ()>,
+    ) -> Result<(ElectrumUpdate<(), A>, bool), Error> {
         let spk_iter = misc_spks
             .into_iter()
             .enumerate()
             .map(|(i, spk)| (i as u32, spk));
 
-        self.scan(
+        self.scan_with_options(
             prev_tip,
             [((), spk_iter)].into(),
             txids,
             outpoints,
             usize::MAX,
             batch_size,
+            options,
         )
     }
-}
 
-impl ElectrumExt<ConfirmationHeightAnchor> for Client {
-    fn scan<K: Ord + Clone>(
+    /// Same as [`scan_without_keychain`], but expands the scan to neighbouring script pubkeys of
+    /// used script pubkeys.
+    ///
+    /// `misc_spks` are provided alongside their derivation index. Whenever a script pubkey at index
+    /// `i` is found to be used, the script pubkeys at indices `i + 1` to `i + expand_by` (which are
+    /// not already scanned) are derived with `derive_spk` and scanned as well. This repeats for
+    /// the expanded script pubkeys, so a run of used script pubkeys is discovered in full.
+    /// `derive_spk` may return `None` for indices which cannot be derived.
+    ///
+    /// The `keychain_update` of the returned update contains the highest used index (if any).
+    /// Requests that fail with a transient error are retried according to `retry`.
+    ///
+    /// [`scan_without_keychain`]: ElectrumExt::scan_without_keychain
+    #[allow(clippy::too_many_arguments)]
+    fn scan_without_keychain_with_expansion(
         &self,
         prev_tip: Option<CheckPoint>,
-        keychain_spks: BTreeMap<K, impl IntoIterator<Item = (u32, ScriptBuf)>>,
+        misc_spks: impl IntoIterator<Item = (u32, ScriptBuf)>,
+        derive_spk: impl Fn(u32) -> Option<ScriptBuf>,
+        expand_by: u32,
         txids: impl IntoIterator<Item = Txid>,
         outpoints: impl IntoIterator<Item = OutPoint>,
-        stop_gap: usize,
         batch_size: usize,
-    ) -> Result<ElectrumUpdate<K, ConfirmationHeightAnchor>, Error> {
-        let mut request_spks = keychain_spks
-            .into_iter()
-            .map(|(k, s)| (k, s.into_iter()))
-            .collect::<BTreeMap<K, _>>();
-        let mut scanned_spks = BTreeMap::<(K, u32), (ScriptBuf, bool)>::new();
+        retry: RetryPolicy,
+    ) -> Result<ElectrumUpdate<(), A>, Error>;
 
-        let txids = txids.into_iter().collect::<Vec<_>>();
-        let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+    /// Same as [`scan_without_keychain`], but records progress in `cursor` so that an interrupted
+    /// scan can be resumed.
+    ///
+    /// Script pubkeys, txids and outpoints that were already queried (as recorded in `cursor`) are
+    /// skipped, so the same `misc_spks`, `txids` and `outpoints` must be provided (in the same
+    /// order) when resuming. If the scan is interrupted by an error, `cursor` contains the progress
+    /// made so far. On completion, the returned update contains the results of all runs and
+    /// `cursor` is cleared. Requests that fail with a transient error are retried according to
+    /// `retry` before the scan is interrupted.
+    ///
+    /// Refer to [`ScanCursor`] for more.
+    ///
+    /// [`scan_without_keychain`]: ElectrumExt::scan_without_keychain
+    #[allow(clippy::too_many_arguments)]
+    fn scan_without_keychain_resumable(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        misc_spks: impl IntoIterator<Item = ScriptBuf>,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        batch_size: usize,
+        retry: RetryPolicy,
+        cursor: &mut ScanCursor,
+    ) -> Result<ElectrumUpdate<(), A>, Error>;
 
-        let update = loop {
-            let (tip, _) = construct_update_tip(self, prev_tip.clone())?;
-            let mut update = ElectrumUpdate::<K, ConfirmationHeightAnchor>::new(tip.clone());
-            let cps = update
-                .new_tip
-                .iter()
-                .take(10)
-                .map(|cp| (cp.height(), cp))
-                .collect::<BTreeMap<u32, CheckPoint>>();
+    /// Broadcast multiple transactions via electrum.
+    ///
+    /// Transactions are broadcast one-by-one in the order given, and the returned [`Vec`] contains
+    /// a result for each transaction of `txs` (in the same order). A transaction rejected by the
+    /// server does not stop the remaining transactions from being broadcast; its result is the
+    /// [`Error::Protocol`] returned by the server, which contains the rejection reason.
+    ///
+    /// The order of `txs` is not rearranged to respect dependencies. The caller is responsible for
+    /// sorting parents before children.
+    ///
+    /// # Errors
+    ///
+    /// Errors which are not a rejection from the server (i.e. I/O errors) abort the broadcast and
+    /// are returned directly.
+    fn broadcast_batch(&self, txs: &[Transaction]) -> Result<Vec<Result<Txid, Error>>, Error>;
 
-            if !request_spks.is_empty() {
-                if !scanned_spks.is_empty() {
-                    scanned_spks.append(&mut populate_with_spks(
-                        self,
-                        &cps,
-                        &mut update,
-                        &mut scanned_spks
-                            .iter()
-                            .map(|(i, (spk, _))| (i.clone(), spk.clone())),
-                        stop_gap,
-                        batch_size,
-                    )?);
-                }
-                for (keychain, keychain_spks) in &mut request_spks {
-                    scanned_spks.extend(
-                        populate_with_spks(
-                            self,
-                            &cps,
-                            &mut update,
-                            keychain_spks,
-                            stop_gap,
-                            batch_size,
-                        )?
-                        .into_iter()
-                        .map(|(spk_i, spk)| ((keychain.clone(), spk_i), spk)),
-                    );
-                }
-            }
+    /// Get the [`ServerCapabilities`] of the electrum server.
+    ///
+    /// This can be used to check that the server is compatible before scanning. Use
+    /// [`ServerCapabilities::check_network`] to check that the server is on the expected network.
+    fn server_capabilities(&self) -> Result<ServerCapabilities, Error>;
 
-            populate_with_txids(self, &cps, &mut update, &mut txids.iter().cloned())?;
+    /// Fetch the block [`Header`] at `height` from the electrum server.
+    ///
+    /// This can be used to backfill data that is missing from existing anchors (such as the
+    /// confirmation time of [`ConfirmationHeightAnchor`]s) without performing a full scan.
+    ///
+    /// Each call is a separate request to the server, so callers should dedupe heights (e.g. by
+    /// collecting them into a [`BTreeSet`]) to avoid redundant requests.
+    fn fetch_header(&self, height: u32) -> Result<Header, Error>;
 
-            let _txs =
-                populate_with_outpoints(self, &cps, &mut update, &mut outpoints.iter().cloned())?;
+    /// Fetch the full [`Block`] at `height` from the electrum server.
+    ///
+    /// This is useful when a block has matched a compact block filter (BIP158) and every
+    /// transaction of the block needs to be indexed (and anchored to the block).
+    ///
+    /// The electrum protocol has no method to retrieve a whole block. Instead, the block is
+    /// assembled from its header, the txids at each position of the block (fetched one-by-one via
+    /// `blockchain.transaction.id_from_pos`) and a batch request for the transactions themselves.
+    /// This costs one round trip per transaction of the block, and more bandwidth than the
+    /// serialized block. Prefer fetching blocks from a full node where possible.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Message`] is returned if the server does not support
+    /// `blockchain.transaction.id_from_pos` (this requires protocol version 1.4), or if the
+    /// assembled block does not match the merkle root of its header.
+    fn fetch_block(&self, height: u32) -> Result<Block, Error>;
 
-            // check for reorgs during scan process
-            let server_blockhash = self.block_header(tip.height() as usize)?.block_hash();
-            if tip.hash() != server_blockhash {
-                continue; // reorg
-            }
+    /// Get the fee histogram of the electrum server's mempool.
+    ///
+    /// This wraps `mempool.get_fee_histogram`. Each bucket is a `(fee_rate, vsize)` pair, where
+    /// `fee_rate` is in sat/vB and `vsize` is the total virtual size (in vbytes) of the mempool
+    /// transactions paying between `fee_rate` and the `fee_rate` of the previous bucket. Buckets
+    /// are ordered by descending `fee_rate`.
+    fn fee_histogram(&self) -> Result<Vec<(f64, u64)>, Error>;
 
-            update.keychain_update = request_spks
-                .into_keys()
-                .filter_map(|k| {
-                    scanned_spks
-                        .range((k.clone(), u32::MIN)..=(k.clone(), u32::MAX))
-                        .rev()
-                        .find(|(_, (_, active))| *active)
-                        .map(|((_, i), _)| (k, *i))
-                })
-                .collect::<BTreeMap<_, _>>();
-            break update;
-        };
+    /// Estimate the [`FeeRate`] for a transaction to be confirmed within `blocks` blocks.
+    ///
+    /// This wraps `blockchain.estimatefee` and converts the result (which is in BTC/kvB) to a
+    /// [`FeeRate`]. Unlike [`ElectrumApi::estimate_fee`], [`Error::Message`] is returned if the
+    /// server does not have enough data to provide an estimate (it returns `-1`).
+    fn estimate_fee_rate(&self, blocks: usize) -> Result<FeeRate, Error>;
 
-        Ok(update)
-    }
+    /// Subscribe to status changes of `spks` (via `blockchain.scripthash.subscribe`).
+    ///
+    /// The returned [`Subscription`] contains the current status of each script pubkey. Use
+    /// [`poll_notifications`] to find out which script pubkeys have changed since, instead of
+    /// repeatedly scanning. Script pubkeys which are already subscribed to by the client are
+    /// resubscribed so that their current status is known.
+    ///
+    /// [`poll_notifications`]: ElectrumExt::poll_notifications
+    fn subscribe_spks(
+        &self,
+        spks: impl IntoIterator<Item = ScriptBuf>,
+    ) -> Result<Subscription, Error>;
+
+    /// Drain status changes of the script pubkeys of `subscription`.
+    ///
+    /// Returns the script pubkeys whose status has changed since the last poll (or since
+    /// subscribing), alongside their new status. `subscription` is updated with the new statuses.
+    /// A changed script pubkey can then be scanned with [`scan_without_keychain`] to fetch its
+    /// new transactions.
+    ///
+    /// If the client has reconnected to the server (which drops all subscriptions), script
+    /// pubkeys are resubscribed to and their status is compared with the last known status, so
+    /// changes which happened while disconnected are still reported.
+    ///
+    /// [`scan_without_keychain`]: ElectrumExt::scan_without_keychain
+    fn poll_notifications(
+        &self,
+        subscription: &mut Subscription,
+    ) -> Result<Vec<(ScriptBuf, Option<ScriptStatus>)>, Error>;
+}
+
+impl ElectrumExt<ConfirmationHeightAnchor> for Client {
+    fn scan_with_options<K: Ord + Clone>(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        keychain_spks: BTreeMap<K, impl IntoIterator<Item = (u32, ScriptBuf)>>,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        stop_gap: impl Into<StopGap<K>>,
+        batch_size: usize,
+        options: ScanOptions<'_, K>,
+    ) -> Result<(ElectrumUpdate<K, ConfirmationHeightAnchor>, bool), Error> {
+        scan_with_options(
+            self,
+            prev_tip,
+            keychain_spks,
+            txids,
+            outpoints,
+            stop_gap.into(),
+            batch_size,
+            options,
+        )
+    }
+
+    fn scan_without_keychain_resumable(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        misc_spks: impl IntoIterator<Item = ScriptBuf>,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        batch_size: usize,
+        retry: RetryPolicy,
+        cursor: &mut ScanCursor,
+    ) -> Result<ElectrumUpdate<(), ConfirmationHeightAnchor>, Error> {
+        scan_without_keychain_resumable(
+            self, prev_tip, misc_spks, txids, outpoints, batch_size, retry, cursor,
+        )
+    }
+
+    fn scan_without_keychain_with_expansion(
+        &self,
+        prev_tip: Option<CheckPoint>,
+        misc_spks: impl IntoIterator<Item = (u32, ScriptBuf)>,
+        derive_spk: impl Fn(u32) -> Option<ScriptBuf>,
+        expand_by: u32,
+        txids: impl IntoIterator<Item = Txid>,
+        outpoints: impl IntoIterator<Item = OutPoint>,
+        batch_size: usize,
+        retry: RetryPolicy,
+    ) -> Result<ElectrumUpdate<(), ConfirmationHeightAnchor>, Error> {
+        scan_without_keychain_with_expansion(
+            self, prev_tip, misc_spks, derive_spk, expand_by, txids, outpoints, batch_size, retry,
+        )
+    }
+
+    fn broadcast_batch(&self, txs: &[Transaction]) -> Result<Vec<Result<Txid, Error>>, Error> {
+        broadcast_batch(self, txs)
+    }
+
+    fn server_capabilities(&self) -> Result<ServerCapabilities, Error> {
+        server_capabilities(self)
+    }
+
+    fn fetch_header(&self, height: u32) -> Result<Header, Error> {
+        fetch_header(self, height)
+    }
+
+    fn fetch_block(&self, height: u32) -> Result<Block, Error> {
+        fetch_block(self, height)
+    }
+
+    fn fee_histogram(&self) -> Result<Vec<(f64, u64)>, Error> {
+        fee_histogram(self)
+    }
+
+    fn subscribe_spks(
+        &self,
+        spks: impl IntoIterator<Item = ScriptBuf>,
+    ) -> Result<Subscription, Error> {
+        subscribe_spks(self, spks)
+    }
+
+    fn poll_notifications(
+        &self,
+        subscription: &mut Subscription,
+    ) -> Result<Vec<(ScriptBuf, Option<ScriptStatus>)>, Error> {
+        poll_notifications(self, subscription)
+    }
+
+    fn estimate_fee_rate(&self, blocks: usize) -> Result<FeeRate, Error> {
+        estimate_fee_rate(self, blocks)
+    }
+}
+
+/// Per-keychain state of [`scan_parallel`]: the keychain's connection, the remaining script pubkeys
+/// to scan and the script pubkeys that are already scanned (alongside whether they are used).
+#[cfg(feature = "parallel")]
+type KeychainScan<C, S> = (C, S, BTreeMap<u32, (ScriptBuf, bool)>);
+
+/// Same as [`ElectrumExt::scan_with_options`] (with a `retry` policy), but scans each keychain of
+/// `keychain_spks` concurrently.
+///
+/// As requests of a single [`Client`] are not processed concurrently, `new_client` is used to
+/// create a separate connection for each keychain (and one more for the chain tip, `txids` and
+/// `outpoints`). Each keychain is scanned on its own thread. Any [`ElectrumApi`] implementation can
+/// be used as the client.
+///
+/// The returned update is the same as the one returned by [`ElectrumExt::scan_with_options`]. If
+/// the chain tip changes during the scan, all keychains are rescanned against the new tip.
+///
+/// This requires the `parallel` feature.
+#[cfg(feature = "parallel")]
+#[allow(clippy::too_many_arguments)]
+pub fn scan_parallel<C, K, I>(
+    new_client: impl Fn() -> Result<C, Error>,
+    prev_tip: Option<CheckPoint>,
+    keychain_spks: BTreeMap<K, I>,
+    txids: impl IntoIterator<Item = Txid>,
+    outpoints: impl IntoIterator<Item = OutPoint>,
+    stop_gap: impl Into<StopGap<K>>,
+    batch_size: usize,
+    retry: RetryPolicy,
+) -> Result<ElectrumUpdate<K, ConfirmationHeightAnchor>, Error>
+where
+    C: ElectrumApi + Send + 'static,
+    K: Ord + Clone + Send + 'static,
+    I: IntoIterator<Item = (u32, ScriptBuf)>,
+    I::IntoIter: Send + 'static,
+{
+    let client = new_client()?;
+    let mut keychain_scans = keychain_spks
+        .into_iter()
+        .map(|(keychain, spks)| Ok((keychain, (new_client()?, spks.into_iter(), BTreeMap::new()))))
+        .collect::<Result<BTreeMap<K, KeychainScan<C, I::IntoIter>>, Error>>()?;
+
+    let stop_gap = stop_gap.into();
+
+    let txids = txids.into_iter().collect::<Vec<_>>();
+    let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+
+    let (_, scanned) = scan_until_consistent(
+        &client,
+        &retry,
+        prev_tip,
+        &Cancellation::new(None),
+        |tip, cps| {
+            let mut update = ElectrumUpdate::<K, ConfirmationHeightAnchor>::new(tip.clone());
+
+            let handles = core::mem::take(&mut keychain_scans)
+                .into_iter()
+                .map(
+                    |(keychain, (keychain_client, mut spks, mut scanned_spks))| {
+                        let (tip, cps) = (tip.clone(), cps.clone());
+                        let (rescan_stop_gap, keychain_stop_gap) =
+                            (stop_gap.max(), stop_gap.of_keychain(&keychain));
+                        let handle = thread::spawn(move || {
+                            let mut update =
+                                ElectrumUpdate::<(), ConfirmationHeightAnchor>::new(tip);
+                            if !scanned_spks.is_empty() {
+                                scanned_spks.append(&mut populate_with_spks(
+                                    &keychain_client,
+                                    &retry,
+                                    &cps,
+                                    &mut update,
+                                    &mut scanned_spks.iter().map(|(&i, (spk, _))| (i, spk.clone())),
+                                    rescan_stop_gap,
+                                    batch_size,
+                                    &mut |_, _| {},
+                                )?);
+                            }
+                            scanned_spks.extend(populate_with_spks(
+                                &keychain_client,
+                                &retry,
+                                &cps,
+                                &mut update,
+                                &mut spks,
+                                keychain_stop_gap,
+                                batch_size,
+                                &mut |_, _| {},
+                            )?);
+                            Ok::<_, Error>((
+                                (keychain_client, spks, scanned_spks),
+                                update.graph_update,
+                            ))
+                        });
+                        (keychain, handle)
+                    },
+                )
+                .collect::<Vec<_>>();
+
+            // always join all threads before returning an error
+            let populate_result = populate_with_txids(
+                &client,
+                &retry,
+                cps,
+                &mut update,
+                &mut txids.iter().cloned(),
+            )
+            .and_then(|_| {
+                populate_with_outpoints(
+                    &client,
+                    &retry,
+                    cps,
+                    &mut update,
+                    &mut outpoints.iter().cloned(),
+                )
+            });
+            let keychain_results = handles
+                .into_iter()
+                .map(|(keychain, handle)| {
+                    let result = handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                    (keychain, result)
+                })
+                .collect::<Vec<_>>();
+            let _txs = populate_result?;
+            for (keychain, result) in keychain_results {
+                let (keychain_scan, graph_update) = result?;
+                for (txid, anchors) in graph_update {
+                    update.graph_update.entry(txid).or_default().extend(anchors);
+                }
+                keychain_scans.insert(keychain, keychain_scan);
+            }
+            Ok(update)
+        },
+    )?;
+    let mut update = scanned.expect("scan is never cancelled");
+
+    update.keychain_update = keychain_scans
+        .iter()
+        .filter_map(|(keychain, (_, _, scanned_spks))| {
+            scanned_spks
+                .iter()
+                .rev()
+                .find(|(_, (_, active))| *active)
+                .map(|(&i, _)| (keychain.clone(), i))
+        })
+        .collect::<BTreeMap<_, _>>();
+    Ok(update)
+}
+
+/// Implementation of [`ElectrumExt::scan_without_keychain_resumable`].
+#[allow(clippy::too_many_arguments)]
+fn scan_without_keychain_resumable<C: ElectrumApi>(
+    client: &C,
+    prev_tip: Option<CheckPoint>,
+    misc_spks: impl IntoIterator<Item = ScriptBuf>,
+    txids: impl IntoIterator<Item = Txid>,
+    outpoints: impl IntoIterator<Item = OutPoint>,
+    batch_size: usize,
+    retry: RetryPolicy,
+    cursor: &mut ScanCursor,
+) -> Result<ElectrumUpdate<(), ConfirmationHeightAnchor>, Error> {
+    let spks = misc_spks.into_iter().collect::<Vec<_>>();
+    let txids = txids.into_iter().collect::<Vec<_>>();
+    let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+    let batch_size = batch_size.max(1);
+
+    let (tip, scanned) = scan_until_consistent(
+        client,
+        &retry,
+        prev_tip,
+        &Cancellation::new(None),
+        |tip, cps| {
+            if cursor.tip != Some(tip.block_id()) {
+                cursor.reset(tip.block_id());
+            }
+
+            for batch in spks[cursor.spks.min(spks.len())..].chunks(batch_size) {
+                let mut batch_update = ElectrumUpdate::new(tip.clone());
+                populate_with_spks(
+                    client,
+                    &retry,
+                    cps,
+                    &mut batch_update,
+                    &mut batch.iter().cloned().enumerate(),
+                    usize::MAX,
+                    batch_size,
+                    &mut |_, _| {},
+                )?;
+                cursor.record(batch_update);
+                cursor.spks += batch.len();
+            }
+
+            for &txid in &txids[cursor.txids.min(txids.len())..] {
+                let mut batch_update = ElectrumUpdate::new(tip.clone());
+                populate_with_txids(
+                    client,
+                    &retry,
+                    cps,
+                    &mut batch_update,
+                    &mut core::iter::once(txid),
+                )?;
+                cursor.record(batch_update);
+                cursor.txids += 1;
+            }
+
+            for &outpoint in &outpoints[cursor.outpoints.min(outpoints.len())..] {
+                let mut batch_update = ElectrumUpdate::new(tip.clone());
+                let _txs = populate_with_outpoints(
+                    client,
+                    &retry,
+                    cps,
+                    &mut batch_update,
+                    &mut core::iter::once(outpoint),
+                )?;
+                cursor.record(batch_update);
+                cursor.outpoints += 1;
+            }
+            Ok(())
+        },
+    )?;
+    scanned.expect("scan is never cancelled");
+
+    let mut update = ElectrumUpdate::new(tip);
+    update.graph_update = core::mem::take(cursor).graph_update;
+    Ok(update)
+}
+
+/// Implementation of [`ElectrumExt::scan_without_keychain_with_expansion`].
+#[allow(clippy::too_many_arguments)]
+fn scan_without_keychain_with_expansion<C: ElectrumApi>(
+    client: &C,
+    prev_tip: Option<CheckPoint>,
+    misc_spks: impl IntoIterator<Item = (u32, ScriptBuf)>,
+    derive_spk: impl Fn(u32) -> Option<ScriptBuf>,
+    expand_by: u32,
+    txids: impl IntoIterator<Item = Txid>,
+    outpoints: impl IntoIterator<Item = OutPoint>,
+    batch_size: usize,
+    retry: RetryPolicy,
+) -> Result<ElectrumUpdate<(), ConfirmationHeightAnchor>, Error> {
+    let misc_spks = misc_spks.into_iter().collect::<BTreeMap<u32, ScriptBuf>>();
+    let txids = txids.into_iter().collect::<Vec<_>>();
+    let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+
+    let (_, scanned) = scan_until_consistent(
+        client,
+        &retry,
+        prev_tip,
+        &Cancellation::new(None),
+        |tip, cps| {
+            let mut update = ElectrumUpdate::<(), ConfirmationHeightAnchor>::new(tip.clone());
+
+            let mut scanned_spks = BTreeMap::<u32, (ScriptBuf, bool)>::new();
+            let mut to_scan = misc_spks.clone();
+            while !to_scan.is_empty() {
+                let newly_scanned = populate_with_spks(
+                    client,
+                    &retry,
+                    cps,
+                    &mut update,
+                    &mut core::mem::take(&mut to_scan).into_iter(),
+                    usize::MAX,
+                    batch_size,
+                    &mut |_, _| {},
+                )?;
+                for (&index, _) in newly_scanned.iter().filter(|(_, (_, used))| *used) {
+                    for neighbour in index.saturating_add(1)..=index.saturating_add(expand_by) {
+                        if scanned_spks.contains_key(&neighbour)
+                            || newly_scanned.contains_key(&neighbour)
+                        {
+                            continue;
+                        }
+                        if let Some(spk) = derive_spk(neighbour) {
+                            to_scan.insert(neighbour, spk);
+                        }
+                    }
+                }
+                scanned_spks.extend(newly_scanned);
+            }
+
+            populate_with_txids(client, &retry, cps, &mut update, &mut txids.iter().cloned())?;
+
+            let _txs = populate_with_outpoints(
+                client,
+                &retry,
+                cps,
+                &mut update,
+                &mut outpoints.iter().cloned(),
+            )?;
+            Ok((update, scanned_spks))
+        },
+    )?;
+    let (mut update, scanned_spks) = scanned.expect("scan is never cancelled");
+
+    update.keychain_update = scanned_spks
+        .iter()
+        .rev()
+        .find(|(_, (_, used))| *used)
+        .map(|(&index, _)| ((), index))
+        .into_iter()
+        .collect();
+    Ok(update)
+}
+
+/// Implementation of [`ElectrumExt::scan_with_options`].
+///
+/// Returns the update alongside whether the scan was cancelled.
+#[allow(clippy::too_many_arguments)]
+fn scan_with_options<C: ElectrumApi, K: Ord + Clone>(
+    client: &C,
+    prev_tip: Option<CheckPoint>,
+    keychain_spks: BTreeMap<K, impl IntoIterator<Item = (u32, ScriptBuf)>>,
+    txids: impl IntoIterator<Item = Txid>,
+    outpoints: impl IntoIterator<Item = OutPoint>,
+    stop_gap: StopGap<K>,
+    batch_size: usize,
+    options: ScanOptions<'_, K>,
+) -> Result<(ElectrumUpdate<K, ConfirmationHeightAnchor>, bool), Error> {
+    let ScanOptions {
+        retry,
+        mut progress,
+        should_stop,
+    } = options;
+    let mut report_progress = |keychain: &K, index: u32, spks_processed: usize| {
+        if let Some(progress) = progress.as_mut() {
+            progress(ScanProgress {
+                keychain: keychain.clone(),
+                index,
+                spks_processed,
+            });
+        }
+    };
+    let cancellation = Cancellation::new(should_stop);
+
+    let mut request_spks = keychain_spks
+        .into_iter()
+        .map(|(k, s)| (k, s.into_iter()))
+        .collect::<BTreeMap<K, _>>();
+    let mut scanned_spks = BTreeMap::<(K, u32), (ScriptBuf, bool)>::new();
+
+    let txids = txids.into_iter().collect::<Vec<_>>();
+    let outpoints = outpoints.into_iter().collect::<Vec<_>>();
+
+    let (tip, scanned) =
+        scan_until_consistent(client, &retry, prev_tip, &cancellation, |tip, cps| {
+            let mut update = ElectrumUpdate::<K, ConfirmationHeightAnchor>::new(tip.clone());
+
+            if !request_spks.is_empty() {
+                let mut spks_processed = 0_usize;
+                if !scanned_spks.is_empty() {
+                    scanned_spks.append(&mut populate_with_spks(
+                        client,
+                        &retry,
+                        cps,
+                        &mut update,
+                        &mut scanned_spks
+                            .iter()
+                            .map(|(i, (spk, _))| (i.clone(), spk.clone()))
+                            .take_while(|_| cancellation.keep_going()),
+                        stop_gap.max(),
+                        batch_size,
+                        &mut |(keychain, index), count| {
+                            spks_processed += count;
+                            report_progress(keychain, *index, spks_processed);
+                        },
+                    )?);
+                }
+                for (keychain, keychain_spks) in &mut request_spks {
+                    scanned_spks.extend(
+                        populate_with_spks(
+                            client,
+                            &retry,
+                            cps,
+                            &mut update,
+                            &mut keychain_spks.take_while(|_| cancellation.keep_going()),
+                            stop_gap.of_keychain(keychain),
+                            batch_size,
+                            &mut |&index, count| {
+                                spks_processed += count;
+                                report_progress(keychain, index, spks_processed);
+                            },
+                        )?
+                        .into_iter()
+                        .map(|(spk_i, spk)| ((keychain.clone(), spk_i), spk)),
+                    );
+                }
+            }
+
+            populate_with_txids(
+                client,
+                &retry,
+                cps,
+                &mut update,
+                &mut txids
+                    .iter()
+                    .cloned()
+                    .take_while(|_| cancellation.keep_going()),
+            )?;
+
+            let _txs = populate_with_outpoints(
+                client,
+                &retry,
+                cps,
+                &mut update,
+                &mut outpoints
+                    .iter()
+                    .cloned()
+                    .take_while(|_| cancellation.keep_going()),
+            )?;
+
+            Ok(update)
+        })?;
+
+    let mut update = match scanned {
+        Some(update) => update,
+        None => return Ok((ElectrumUpdate::new(tip), true)),
+    };
+    update.keychain_update = request_spks
+        .into_keys()
+        .filter_map(|k| {
+            scanned_spks
+                .range((k.clone(), u32::MIN)..=(k.clone(), u32::MAX))
+                .rev()
+                .find(|(_, (_, active))| *active)
+                .map(|((_, i), _)| (k, *i))
+        })
+        .collect::<BTreeMap<_, _>>();
+    Ok((update, cancellation.is_cancelled()))
+}
+
+/// Repeatedly calls `populate` with the latest tip (that connects with `prev_tip`) and the
+/// checkpoints used to anchor transactions, until no reorg happened during `populate`.
+///
+/// Returns the tip alongside the output of `populate`. If a reorg is detected after the scan is
+/// cancelled, `populate` is not called again, as the data gathered may be from either side of the
+/// reorg. Instead, the tip is fetched again (as the previous one is known to be stale) and returned
+/// alongside `None`.
+fn scan_until_consistent<C: ElectrumApi, T>(
+    client: &C,
+    retry: &RetryPolicy,
+    prev_tip: Option<CheckPoint>,
+    cancellation: &Cancellation<'_>,
+    mut populate: impl FnMut(&CheckPoint, &BTreeMap<u32, CheckPoint>) -> Result<T, Error>,
+) -> Result<(CheckPoint, Option<T>), Error> {
+    loop {
+        let (tip, _) = construct_update_tip(client, retry, prev_tip.clone())?;
+        let cps = tip
+            .iter()
+            .take(10)
+            .map(|cp| (cp.height(), cp))
+            .collect::<BTreeMap<u32, CheckPoint>>();
+
+        let output = populate(&tip, &cps)?;
+
+        // check for reorgs during scan process
+        let server_blockhash = retry
+            .call(|| client.block_header(tip.height() as usize))?
+            .block_hash();
+        if tip.hash() == server_blockhash {
+            return Ok((tip, Some(output)));
+        }
+        if cancellation.is_cancelled() {
+            let (tip, _) = construct_update_tip(client, retry, prev_tip)?;
+            return Ok((tip, None));
+        }
+    }
 }
 
 /// Return a [`CheckPoint`] of the latest tip, that connects with `prev_tip`.
-fn construct_update_tip(
-    client: &Client,
+fn construct_update_tip<C: ElectrumApi>(
+    client: &C,
+    retry: &RetryPolicy,
     prev_tip: Option<CheckPoint>,
 ) -> Result<(CheckPoint, Option<u32>), Error> {
-    let HeaderNotification { height, .. } = client.block_headers_subscribe()?;
+    let HeaderNotification { height, .. } = retry.call(|| client.block_headers_subscribe())?;
     let new_tip_height = height as u32;
 
     // If electrum returns a tip height that is lower than our previous tip, then checkpoints do
@@ -308,8 +1433,8 @@ fn construct_update_tip(
     // to construct our checkpoint update.
     let mut new_blocks = {
         let start_height = new_tip_height.saturating_sub(ASSUME_FINAL_DEPTH);
-        let hashes = client
-            .block_headers(start_height as _, ASSUME_FINAL_DEPTH as _)?
+        let hashes = retry
+            .call(|| client.block_headers(start_height as _, ASSUME_FINAL_DEPTH as _))?
             .headers
             .into_iter()
             .map(|h| h.block_hash());
@@ -328,7 +1453,9 @@ fn construct_update_tip(
                         new_tip_height >= cp_block.height,
                         "already checked that electrum's tip cannot be smaller"
                     );
-                    let hash = client.block_header(cp_block.height as _)?.block_hash();
+                    let hash = retry
+                        .call(|| client.block_header(cp_block.height as _))?
+                        .block_hash();
                     new_blocks.insert(cp_block.height, hash);
                     hash
                 }
@@ -405,8 +1532,9 @@ fn determine_tx_anchor(
     }
 }
 
-fn populate_with_outpoints<K>(
-    client: &Client,
+fn populate_with_outpoints<C: ElectrumApi, K>(
+    client: &C,
+    retry: &RetryPolicy,
     cps: &BTreeMap<u32, CheckPoint>,
     update: &mut ElectrumUpdate<K, ConfirmationHeightAnchor>,
     outpoints: &mut impl Iterator<Item = OutPoint>,
@@ -414,7 +1542,7 @@ fn populate_with_outpoints<K>(
     let mut full_txs = HashMap::new();
     for outpoint in outpoints {
         let txid = outpoint.txid;
-        let tx = client.transaction_get(&txid)?;
+        let tx = retry.call(|| client.transaction_get(&txid))?;
         debug_assert_eq!(tx.txid(), txid);
         let txout = match tx.output.get(outpoint.vout as usize) {
             Some(txout) => txout,
@@ -424,7 +1552,7 @@ fn populate_with_outpoints<K>(
         // add to our sparsechain `update`:
         let mut has_residing = false; // tx in which the outpoint resides
         let mut has_spending = false; // tx that spends the outpoint
-        for res in client.script_get_history(&txout.script_pubkey)? {
+        for res in retry.call(|| client.script_get_history(&txout.script_pubkey))? {
             if has_residing && has_spending {
                 break;
             }
@@ -442,7 +1570,7 @@ fn populate_with_outpoints<K>(
                 let res_tx = match full_txs.get(&res.tx_hash) {
                     Some(tx) => tx,
                     None => {
-                        let res_tx = client.transaction_get(&res.tx_hash)?;
+                        let res_tx = retry.call(|| client.transaction_get(&res.tx_hash))?;
                         full_txs.insert(res.tx_hash, res_tx);
                         full_txs.get(&res.tx_hash).expect("just inserted")
                     }
@@ -467,14 +1595,15 @@ fn populate_with_outpoints<K>(
     Ok(full_txs)
 }
 
-fn populate_with_txids<K>(
-    client: &Client,
+fn populate_with_txids<C: ElectrumApi, K>(
+    client: &C,
+    retry: &RetryPolicy,
     cps: &BTreeMap<u32, CheckPoint>,
     update: &mut ElectrumUpdate<K, ConfirmationHeightAnchor>,
     txids: &mut impl Iterator<Item = Txid>,
 ) -> Result<(), Error> {
     for txid in txids {
-        let tx = match client.transaction_get(&txid) {
+        let tx = match retry.call(|| client.transaction_get(&txid)) {
             Ok(tx) => tx,
             Err(electrum_client::Error::Protocol(_)) => continue,
             Err(other_err) => return Err(other_err),
@@ -486,8 +1615,8 @@ fn populate_with_txids<K>(
             .map(|txo| &txo.script_pubkey)
             .expect("tx must have an output");
 
-        let anchor = match client
-            .script_get_history(spk)?
+        let anchor = match retry
+            .call(|| client.script_get_history(spk))?
             .into_iter()
             .find(|r| r.tx_hash == txid)
         {
@@ -503,13 +1632,16 @@ fn populate_with_txids<K>(
     Ok(())
 }
 
-fn populate_with_spks<K, I: Ord + Clone>(
-    client: &Client,
+#[allow(clippy::too_many_arguments)]
+fn populate_with_spks<C: ElectrumApi, K, I: Ord + Clone>(
+    client: &C,
+    retry: &RetryPolicy,
     cps: &BTreeMap<u32, CheckPoint>,
     update: &mut ElectrumUpdate<K, ConfirmationHeightAnchor>,
     spks: &mut impl Iterator<Item = (I, ScriptBuf)>,
     stop_gap: usize,
     batch_size: usize,
+    on_batch: &mut impl FnMut(&I, usize),
 ) -> Result<BTreeMap<I, (ScriptBuf, bool)>, Error> {
     let mut unused_spk_count = 0_usize;
     let mut scanned_spks = BTreeMap::new();
@@ -522,15 +1654,23 @@ fn populate_with_spks<K, I: Ord + Clone>(
             return Ok(scanned_spks);
         }
 
-        let spk_histories =
-            client.batch_script_get_history(spks.iter().map(|(_, s)| s.as_script()))?;
+        let spk_histories = retry
+            .call(|| client.batch_script_get_history(spks.iter().map(|(_, s)| s.as_script())))?;
+
+        let mut last_processed = None;
+        let mut processed_count = 0_usize;
+        let mut reached_stop_gap = false;
 
         for ((spk_index, spk), spk_history) in spks.into_iter().zip(spk_histories) {
+            last_processed = Some(spk_index.clone());
+            processed_count += 1;
+
             if spk_history.is_empty() {
                 scanned_spks.insert(spk_index, (spk, false));
                 unused_spk_count += 1;
                 if unused_spk_count > stop_gap {
-                    return Ok(scanned_spks);
+                    reached_stop_gap = true;
+                    break;
                 }
                 continue;
             } else {
@@ -545,5 +1685,1245 @@ fn populate_with_spks<K, I: Ord + Clone>(
                 }
             }
         }
+
+        if let Some(last_processed) = &last_processed {
+            on_batch(last_processed, processed_count);
+        }
+        if reached_stop_gap {
+            return Ok(scanned_spks);
+        }
+    }
+}
+
+fn broadcast_batch<C: ElectrumApi>(
+    client: &C,
+    txs: &[Transaction],
+) -> Result<Vec<Result<Txid, Error>>, Error> {
+    let mut results = Vec::with_capacity(txs.len());
+    for tx in txs {
+        match client.transaction_broadcast(tx) {
+            Ok(txid) => results.push(Ok(txid)),
+            Err(err @ Error::Protocol(_)) => results.push(Err(err)),
+            Err(other_err) => return Err(other_err),
+        }
+    }
+    Ok(results)
+}
+
+fn server_capabilities<C: ElectrumApi>(client: &C) -> Result<ServerCapabilities, Error> {
+    let features = client.server_features()?;
+    // electrum returns the genesis hash in display (reversed) byte order
+    let mut genesis_hash = features.genesis_hash;
+    genesis_hash.reverse();
+    Ok(ServerCapabilities {
+        server_version: features.server_version,
+        protocol_min: features.protocol_min,
+        protocol_max: features.protocol_max,
+        genesis_hash: BlockHash::from_byte_array(genesis_hash),
+        pruning: features.pruning,
+    })
+}
+
+fn fetch_header<C: ElectrumApi>(client: &C, height: u32) -> Result<Header, Error> {
+    client.block_header(height as usize)
+}
+
+fn fetch_block<C: ElectrumApi>(client: &C, height: u32) -> Result<Block, Error> {
+    let header = fetch_header(client, height)?;
+
+    let mut txids = Vec::<Txid>::new();
+    loop {
+        let response = client.raw_call(
+            "blockchain.transaction.id_from_pos",
+            [Param::U32(height), Param::Usize(txids.len())],
+        );
+        match response {
+            Ok(value) => {
+                let txid = value
+                    .as_str()
+                    .and_then(|txid| Txid::from_str(txid).ok())
+                    .ok_or(Error::InvalidResponse(value))?;
+                txids.push(txid);
+            }
+            // every block has a coinbase transaction, so the server does not support the method
+            Err(Error::Protocol(_)) if txids.is_empty() => {
+                return Err(Error::Message(format!(
+                    "electrum server does not support fetching the block at height {}",
+                    height
+                )))
+            }
+            // the position is past the last transaction of the block
+            Err(Error::Protocol(_)) => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    let block = Block {
+        header,
+        txdata: client.batch_transaction_get(&txids)?,
+    };
+    // the transactions may be incomplete if the block was reorged out while fetching
+    if !block.check_merkle_root() {
+        return Err(Error::Message(format!(
+            "transactions fetched for the block at height {} do not match its merkle root",
+            height
+        )));
+    }
+    Ok(block)
+}
+
+fn fee_histogram<C: ElectrumApi>(client: &C) -> Result<Vec<(f64, u64)>, Error> {
+    let response = client.raw_call("mempool.get_fee_histogram", [])?;
+    response
+        .as_array()
+        .and_then(|buckets| {
+            buckets
+                .iter()
+                .map(|bucket| match bucket.as_array()?.as_slice() {
+                    [fee_rate, vsize] => Some((fee_rate.as_f64()?, vsize.as_u64()?)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or(Error::InvalidResponse(response))
+}
+
+fn estimate_fee_rate<C: ElectrumApi>(client: &C, blocks: usize) -> Result<FeeRate, Error> {
+    let btc_per_kvb = client.estimate_fee(blocks)?;
+    if btc_per_kvb < 0.0 {
+        return Err(Error::Message(format!(
+            "electrum server has insufficient data to estimate the fee rate for a target of {} blocks",
+            blocks
+        )));
+    }
+    // 1 BTC/kvB is 10^8 sat per 4000 wu
+    Ok(FeeRate::from_sat_per_kwu(
+        (btc_per_kvb * 100_000_000.0 / 4.0).round() as u64,
+    ))
+}
+
+fn subscribe_spks<C: ElectrumApi>(
+    client: &C,
+    spks: impl IntoIterator<Item = ScriptBuf>,
+) -> Result<Subscription, Error> {
+    let mut subscription = Subscription::default();
+    for spk in spks {
+        let status = match client.script_subscribe(&spk) {
+            Err(Error::AlreadySubscribed(_)) => {
+                client.script_unsubscribe(&spk)?;
+                client.script_subscribe(&spk)?
+            }
+            res => res?,
+        };
+        subscription.statuses.insert(spk, status);
+    }
+    Ok(subscription)
+}
+
+fn poll_notifications<C: ElectrumApi>(
+    client: &C,
+    subscription: &mut Subscription,
+) -> Result<Vec<(ScriptBuf, Option<ScriptStatus>)>, Error> {
+    // notifications are only read from the connection when a request is made
+    client.ping()?;
+
+    let spks = subscription.statuses.keys().cloned().collect::<Vec<_>>();
+    let mut changed = Vec::new();
+    for spk in spks {
+        let mut latest_status = None;
+        loop {
+            match client.script_pop(&spk) {
+                Ok(Some(status)) => latest_status = Some(Some(status)),
+                Ok(None) => break,
+                // the client has reconnected and lost its subscriptions
+                Err(Error::NotSubscribed(_)) => {
+                    latest_status = Some(client.script_subscribe(&spk)?);
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if let Some(status) = latest_status {
+            if subscription.update(&spk, status) {
+                changed.push((spk, status));
+            }
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk_chain::bitcoin::{
+        block, consensus,
+        hash_types::TxMerkleNode,
+        hashes::{sha256d, HashEngine},
+        CompactTarget, Script,
+    };
+    use electrum_client::{
+        Batch, GetBalanceRes, GetHeadersRes, GetHistoryRes, GetMerkleRes, ListUnspentRes,
+        RawHeaderNotification, ServerFeaturesRes, ToElectrumScriptHash,
+    };
+    use std::{
+        borrow::Borrow,
+        sync::{Arc, Mutex},
+    };
+
+    /// The chain and script pubkey histories served by a [`MockClient`].
+    #[derive(Default)]
+    struct MockServer {
+        headers: Vec<Header>,
+        histories: HashMap<ScriptBuf, Vec<(Txid, i32)>>,
+        /// Full transactions which can be fetched.
+        txs: HashMap<Txid, Transaction>,
+        /// The number of script pubkeys whose history was requested.
+        spks_requested: usize,
+        /// The number of history requests which fail with an I/O error before succeeding.
+        failures: usize,
+        /// Headers which replace `headers` (a reorg) on the next history request, alongside a
+        /// flag which is set at the same time.
+        reorg_on_history: Option<(Vec<Header>, Arc<AtomicBool>)>,
+        /// The hash of the genesis block of the server's chain (all zeros if `None`).
+        genesis_hash: Option<BlockHash>,
+        /// Transactions which are rejected when broadcast.
+        rejected_txs: HashSet<Txid>,
+        /// The txids of blocks by height, served by `blockchain.transaction.id_from_pos`. The method
+        /// is not supported if there are none.
+        block_txids: HashMap<u32, Vec<Txid>>,
+        /// The response to `mempool.get_fee_histogram`.
+        fee_histogram: serde_json::Value,
+        /// Fee estimates (in BTC/kvB) by confirmation target. Other targets have no estimate.
+        fee_estimates: HashMap<usize, f64>,
+        /// The current status of script pubkeys. Script pubkeys without a status have no history.
+        statuses: HashMap<ScriptBuf, ScriptStatus>,
+        /// The status notifications of subscribed script pubkeys which are not popped yet.
+        subscriptions: HashMap<ScriptBuf, Vec<ScriptStatus>>,
+    }
+
+    /// An [`ElectrumApi`] implementation which serves a [`MockServer`].
+    #[derive(Clone, Default)]
+    struct MockClient(Arc<Mutex<MockServer>>);
+
+    impl MockClient {
+        fn new(headers: Vec<Header>, histories: HashMap<ScriptBuf, Vec<(Txid, i32)>>) -> Self {
+            Self(Arc::new(Mutex::new(MockServer {
+                headers,
+                histories,
+                ..Default::default()
+            })))
+        }
+
+        fn server(&self) -> std::sync::MutexGuard<'_, MockServer> {
+            self.0.lock().unwrap()
+        }
+
+        /// Change the status of `spk`, notifying the client if it is subscribed.
+        fn set_status(&self, spk: &ScriptBuf, status: ScriptStatus) {
+            let mut server = self.server();
+            server.statuses.insert(spk.clone(), status);
+            if let Some(notifications) = server.subscriptions.get_mut(spk) {
+                notifications.push(status);
+            }
+        }
+
+        /// Reconnect to the server, which drops all subscriptions.
+        fn reconnect(&self) {
+            self.server().subscriptions.clear();
+        }
+    }
+
+    /// An error returned by the server in response to a request, like an electrum server does.
+    fn protocol_error(message: impl Into<String>) -> Error {
+        Error::Protocol(serde_json::json!({ "code": 1, "message": message.into() }))
+    }
+
+    /// A chain of `len` headers, where `salt` distinguishes headers of different chains.
+    fn headers(len: u32, salt: u32) -> Vec<Header> {
+        extend_headers(Vec::new(), len, salt)
+    }
+
+    /// Extend `headers` to a chain of `len` headers, where `salt` distinguishes headers of
+    /// different chains.
+    fn extend_headers(mut headers: Vec<Header>, len: u32, salt: u32) -> Vec<Header> {
+        for height in headers.len() as u32..len {
+            headers.push(Header {
+                version: block::Version::ONE,
+                prev_blockhash: headers
+                    .last()
+                    .map_or(BlockHash::all_zeros(), Header::block_hash),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: height,
+                bits: CompactTarget::from_consensus(0),
+                nonce: salt,
+            });
+        }
+        headers
+    }
+
+    fn spk(i: u32) -> ScriptBuf {
+        ScriptBuf::from(i.to_be_bytes().to_vec())
+    }
+
+    fn txid(i: u32) -> Txid {
+        Txid::hash(&i.to_be_bytes())
+    }
+
+    /// A transaction which is distinguished by `i`.
+    fn new_tx(i: u32) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: bdk_chain::bitcoin::absolute::LockTime::from_consensus(i),
+            input: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    fn keychain_spks(count: u32) -> BTreeMap<u8, Vec<(u32, ScriptBuf)>> {
+        [(0, (0..count).map(|i| (i, spk(i))).collect())].into()
+    }
+
+    type UpdateParts = (
+        BlockId,
+        HashMap<Txid, BTreeSet<ConfirmationHeightAnchor>>,
+        BTreeMap<u8, u32>,
+    );
+
+    fn parts(update: ElectrumUpdate<u8, ConfirmationHeightAnchor>) -> UpdateParts {
+        (
+            update.new_tip.block_id(),
+            update.graph_update,
+            update.keychain_update,
+        )
+    }
+
+    impl ElectrumApi for MockClient {
+        fn raw_call(
+            &self,
+            method_name: &str,
+            params: impl IntoIterator<Item = Param>,
+        ) -> Result<serde_json::Value, Error> {
+            let server = self.server();
+            match method_name {
+                "blockchain.transaction.id_from_pos" if !server.block_txids.is_empty() => {
+                    let params = params.into_iter().collect::<Vec<_>>();
+                    let txid = match params.as_slice() {
+                        [Param::U32(height), Param::Usize(pos)] => server
+                            .block_txids
+                            .get(height)
+                            .and_then(|txids| txids.get(*pos)),
+                        _ => None,
+                    };
+                    match txid {
+                        Some(txid) => Ok(serde_json::json!(txid.to_string())),
+                        None => Err(protocol_error("no tx at the position of the block")),
+                    }
+                }
+                "mempool.get_fee_histogram" => Ok(server.fee_histogram.clone()),
+                _ => Err(protocol_error(format!("unknown method {}", method_name))),
+            }
+        }
+
+        fn batch_call(&self, batch: &Batch) -> Result<Vec<serde_json::Value>, Error> {
+            batch
+                .iter()
+                .map(|(method_name, params)| self.raw_call(method_name, params.clone()))
+                .collect()
+        }
+
+        fn block_headers_subscribe_raw(&self) -> Result<RawHeaderNotification, Error> {
+            let server = self.server();
+            let tip = server.headers.last().expect("must have tip");
+            Ok(RawHeaderNotification {
+                height: server.headers.len() - 1,
+                header: consensus::serialize(tip),
+            })
+        }
+
+        fn block_headers_pop_raw(&self) -> Result<Option<RawHeaderNotification>, Error> {
+            // the mock does not notify of new headers
+            Ok(None)
+        }
+
+        fn block_header_raw(&self, height: usize) -> Result<Vec<u8>, Error> {
+            match self.server().headers.get(height) {
+                Some(header) => Ok(consensus::serialize(header)),
+                None => Err(protocol_error(format!("height {} out of range", height))),
+            }
+        }
+
+        fn block_headers(&self, start_height: usize, count: usize) -> Result<GetHeadersRes, Error> {
+            let headers = self
+                .server()
+                .headers
+                .iter()
+                .skip(start_height)
+                .take(count)
+                .cloned()
+                .collect::<Vec<_>>();
+            Ok(GetHeadersRes {
+                max: count,
+                count: headers.len(),
+                raw_headers: Vec::new(),
+                headers,
+            })
+        }
+
+        fn estimate_fee(&self, number: usize) -> Result<f64, Error> {
+            // electrum servers return -1 if they cannot provide an estimate
+            Ok(self
+                .server()
+                .fee_estimates
+                .get(&number)
+                .copied()
+                .unwrap_or(-1.0))
+        }
+
+        fn relay_fee(&self) -> Result<f64, Error> {
+            // the default minimum relay fee of bitcoin core (in BTC/kvB)
+            Ok(0.00001)
+        }
+
+        fn script_subscribe(&self, script: &Script) -> Result<Option<ScriptStatus>, Error> {
+            let mut server = self.server();
+            if server.subscriptions.contains_key(script) {
+                return Err(Error::AlreadySubscribed(script.to_electrum_scripthash()));
+            }
+            server.subscriptions.insert(script.into(), Vec::new());
+            Ok(server.statuses.get(script).copied())
+        }
+
+        fn batch_script_subscribe<'s, I>(
+            &self,
+            scripts: I,
+        ) -> Result<Vec<Option<ScriptStatus>>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<&'s Script>,
+        {
+            scripts
+                .into_iter()
+                .map(|script| self.script_subscribe(script.borrow()))
+                .collect()
+        }
+
+        fn script_unsubscribe(&self, script: &Script) -> Result<bool, Error> {
+            match self.server().subscriptions.remove(script) {
+                Some(_) => Ok(true),
+                None => Err(Error::NotSubscribed(script.to_electrum_scripthash())),
+            }
+        }
+
+        fn script_pop(&self, script: &Script) -> Result<Option<ScriptStatus>, Error> {
+            match self.server().subscriptions.get_mut(script) {
+                Some(notifications) if notifications.is_empty() => Ok(None),
+                Some(notifications) => Ok(Some(notifications.remove(0))),
+                None => Err(Error::NotSubscribed(script.to_electrum_scripthash())),
+            }
+        }
+
+        fn script_get_balance(&self, script: &Script) -> Result<GetBalanceRes, Error> {
+            let mut balance = GetBalanceRes {
+                confirmed: 0,
+                unconfirmed: 0,
+            };
+            for unspent in self.script_list_unspent(script)? {
+                if unspent.height > 0 {
+                    balance.confirmed += unspent.value;
+                } else {
+                    balance.unconfirmed += unspent.value as i64;
+                }
+            }
+            Ok(balance)
+        }
+
+        fn batch_script_get_balance<'s, I>(&self, scripts: I) -> Result<Vec<GetBalanceRes>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<&'s Script>,
+        {
+            scripts
+                .into_iter()
+                .map(|script| self.script_get_balance(script.borrow()))
+                .collect()
+        }
+
+        fn script_get_history(&self, script: &Script) -> Result<Vec<GetHistoryRes>, Error> {
+            self.batch_script_get_history([script])
+                .map(|mut histories| histories.remove(0))
+        }
+
+        fn batch_script_get_history<'s, I>(
+            &self,
+            scripts: I,
+        ) -> Result<Vec<Vec<GetHistoryRes>>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<&'s Script>,
+        {
+            let mut server = self.server();
+            if server.failures > 0 {
+                server.failures -= 1;
+                return Err(Error::IOError(std::io::ErrorKind::ConnectionReset.into()));
+            }
+            if let Some((headers, flag)) = server.reorg_on_history.take() {
+                server.headers = headers;
+                flag.store(true, Ordering::Relaxed);
+            }
+            let histories = scripts
+                .into_iter()
+                .map(|script| {
+                    let script = ScriptBuf::from(*script.borrow());
+                    server
+                        .histories
+                        .get(&script)
+                        .into_iter()
+                        .flatten()
+                        .map(|&(tx_hash, height)| GetHistoryRes {
+                            height,
+                            tx_hash,
+                            fee: None,
+                        })
+                        .collect()
+                })
+                .collect::<Vec<_>>();
+            server.spks_requested += histories.len();
+            Ok(histories)
+        }
+
+        fn script_list_unspent(&self, script: &Script) -> Result<Vec<ListUnspentRes>, Error> {
+            let server = self.server();
+            let spent = server
+                .txs
+                .values()
+                .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+                .collect::<HashSet<_>>();
+            let spent = &spent;
+            Ok(server
+                .histories
+                .get(script)
+                .into_iter()
+                .flatten()
+                .filter_map(|&(tx_hash, height)| Some((server.txs.get(&tx_hash)?, height)))
+                .flat_map(|(tx, height)| {
+                    let tx_hash = tx.txid();
+                    tx.output
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, txout)| txout.script_pubkey.as_script() == script)
+                        .filter(move |(vout, _)| {
+                            !spent.contains(&OutPoint::new(tx_hash, *vout as u32))
+                        })
+                        .map(move |(tx_pos, txout)| ListUnspentRes {
+                            height: height.max(0) as usize,
+                            tx_hash,
+                            tx_pos,
+                            value: txout.value,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect())
+        }
+
+        fn batch_script_list_unspent<'s, I>(
+            &self,
+            scripts: I,
+        ) -> Result<Vec<Vec<ListUnspentRes>>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<&'s Script>,
+        {
+            scripts
+                .into_iter()
+                .map(|script| self.script_list_unspent(script.borrow()))
+                .collect()
+        }
+
+        fn transaction_get_raw(&self, txid: &Txid) -> Result<Vec<u8>, Error> {
+            match self.server().txs.get(txid) {
+                Some(tx) => Ok(consensus::serialize(tx)),
+                None => Err(protocol_error(format!("unknown transaction {}", txid))),
+            }
+        }
+
+        fn batch_transaction_get_raw<'t, I>(&self, txids: I) -> Result<Vec<Vec<u8>>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<&'t Txid>,
+        {
+            txids
+                .into_iter()
+                .map(|txid| self.transaction_get_raw(txid.borrow()))
+                .collect()
+        }
+
+        fn batch_block_header_raw<I>(&self, heights: I) -> Result<Vec<Vec<u8>>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<u32>,
+        {
+            heights
+                .into_iter()
+                .map(|height| self.block_header_raw(*height.borrow() as usize))
+                .collect()
+        }
+
+        fn batch_estimate_fee<I>(&self, numbers: I) -> Result<Vec<f64>, Error>
+        where
+            I: IntoIterator + Clone,
+            I::Item: Borrow<usize>,
+        {
+            numbers
+                .into_iter()
+                .map(|number| self.estimate_fee(*number.borrow()))
+                .collect()
+        }
+
+        fn transaction_broadcast_raw(&self, raw_tx: &[u8]) -> Result<Txid, Error> {
+            let tx: Transaction = consensus::deserialize(raw_tx)?;
+            let mut server = self.server();
+            if server.rejected_txs.contains(&tx.txid()) {
+                return Err(protocol_error(
+                    "the transaction was rejected by network rules",
+                ));
+            }
+            server.txs.insert(tx.txid(), tx.clone());
+            Ok(tx.txid())
+        }
+
+        fn transaction_get_merkle(
+            &self,
+            txid: &Txid,
+            height: usize,
+        ) -> Result<GetMerkleRes, Error> {
+            let server = self.server();
+            let txids = server.block_txids.get(&(height as u32));
+            let pos = match txids.and_then(|txids| txids.iter().position(|t| t == txid)) {
+                Some(pos) => pos,
+                None => return Err(protocol_error(format!("{} is not in the block", txid))),
+            };
+            let mut level = txids
+                .into_iter()
+                .flatten()
+                .map(|txid| txid.to_raw_hash())
+                .collect::<Vec<_>>();
+            let (mut level_pos, mut merkle) = (pos, Vec::new());
+            while level.len() > 1 {
+                if level.len() % 2 == 1 {
+                    level.push(*level.last().expect("level is not empty"));
+                }
+                // electrum returns the hashes of the merkle path in display (reversed) byte order
+                let mut sibling = level[level_pos ^ 1].to_byte_array();
+                sibling.reverse();
+                merkle.push(sibling);
+                level = level
+                    .chunks(2)
+                    .map(|pair| {
+                        let mut engine = sha256d::Hash::engine();
+                        engine.input(pair[0].as_byte_array());
+                        engine.input(pair[1].as_byte_array());
+                        sha256d::Hash::from_engine(engine)
+                    })
+                    .collect();
+                level_pos /= 2;
+            }
+            Ok(GetMerkleRes {
+                block_height: height,
+                pos,
+                merkle,
+            })
+        }
+
+        fn server_features(&self) -> Result<ServerFeaturesRes, Error> {
+            // electrum returns the genesis hash in display (reversed) byte order
+            let mut genesis_hash = self
+                .server()
+                .genesis_hash
+                .unwrap_or_else(BlockHash::all_zeros)
+                .to_byte_array();
+            genesis_hash.reverse();
+            Ok(ServerFeaturesRes {
+                server_version: "mock 1.0".to_string(),
+                genesis_hash,
+                protocol_min: "1.4".to_string(),
+                protocol_max: "1.4.2".to_string(),
+                hash_function: Some("sha256".to_string()),
+                pruning: None,
+            })
+        }
+
+        fn ping(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn scan(
+        client: &MockClient,
+        spk_count: u32,
+        stop_gap: impl Into<StopGap<u8>>,
+        batch_size: usize,
+        options: ScanOptions<'_, u8>,
+    ) -> Result<(ElectrumUpdate<u8, ConfirmationHeightAnchor>, bool), Error> {
+        scan_with_options(
+            client,
+            None,
+            keychain_spks(spk_count),
+            core::iter::empty(),
+            core::iter::empty(),
+            stop_gap.into(),
+            batch_size,
+            options,
+        )
+    }
+
+    #[test]
+    fn finalize_chain_then_graph_is_same_as_finalize() {
+        let tx = Transaction {
+            version: 1,
+            lock_time: bdk_chain::bitcoin::absolute::LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![bdk_chain::bitcoin::TxOut {
+                value: 10_000,
+                script_pubkey: spk(0),
+            }],
+        };
+        let client = MockClient::new(headers(10, 0), [(spk(0), vec![(tx.txid(), 5)])].into());
+        client.server().txs.insert(tx.txid(), tx.clone());
+
+        let (update, _) = scan(&client, 1, 5, 5, Default::default()).unwrap();
+        let missing = update.missing_full_txs(&TxGraph::<()>::default());
+        assert_eq!(missing, vec![tx.txid()]);
+
+        let combined = update
+            .clone()
+            .finalize(&client, Some(100), missing.clone())
+            .unwrap();
+        let mut combined_chain = local_chain::LocalChain::default();
+        let mut combined_graph = TxGraph::<ConfirmationHeightAnchor>::default();
+        let _ = combined_chain.apply_update(combined.chain).unwrap();
+        let _ = combined_graph.apply_update(combined.graph);
+
+        let mut chain = local_chain::LocalChain::default();
+        let mut graph = TxGraph::<ConfirmationHeightAnchor>::default();
+        let _ = chain.apply_update(update.finalize_chain_only()).unwrap();
+        let _ = graph.apply_update(update.finalize_graph(&client, Some(100), missing).unwrap());
+
+        assert_eq!(chain.blocks(), combined_chain.blocks());
+        assert_eq!(
+            graph.initial_changeset(),
+            combined_graph.initial_changeset()
+        );
+        assert!(graph.get_tx(tx.txid()).is_some());
+    }
+
+    #[test]
+    fn scan_with_options_retries_failed_requests() {
+        let histories: HashMap<_, _> = [(spk(1), vec![(txid(1), 15)])].into();
+        let expected = scan(
+            &MockClient::new(headers(20, 0), histories.clone()),
+            4,
+            10,
+            2,
+            ScanOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(expected.0.graph_update.len(), 1);
+
+        // without retries, the failed request aborts the scan
+        let client = MockClient::new(headers(20, 0), histories.clone());
+        client.server().failures = 2;
+        assert!(matches!(
+            scan(&client, 4, 10, 2, ScanOptions::default()),
+            Err(Error::IOError(_))
+        ));
+
+        // with retries, the failed request is redone and the result is the same
+        let client = MockClient::new(headers(20, 0), histories);
+        client.server().failures = 2;
+        let retry = RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::ZERO,
+        };
+        let (update, cancelled) = scan(
+            &client,
+            4,
+            10,
+            2,
+            ScanOptions {
+                retry,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!cancelled);
+        assert_eq!(parts(update), parts(expected.0));
+        // completed batches are not requested again
+        assert_eq!(client.server().spks_requested, 4);
+    }
+
+    #[test]
+    fn scan_with_options_reports_progress() {
+        let client = MockClient::new(headers(20, 0), [(spk(1), vec![(txid(1), 15)])].into());
+        let mut reported = Vec::new();
+        let (update, _) = scan(
+            &client,
+            5,
+            10,
+            2,
+            ScanOptions {
+                progress: Some(&mut |progress| reported.push(progress)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(update.keychain_update, [(0, 1)].into());
+        assert_eq!(
+            reported,
+            [(1, 2), (3, 4), (4, 5)]
+                .iter()
+                .map(|&(index, spks_processed)| ScanProgress {
+                    keychain: 0,
+                    index,
+                    spks_processed,
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn scan_stops_each_keychain_at_its_own_stop_gap() {
+        let keychain_spks: BTreeMap<u8, Vec<(u32, ScriptBuf)>> = [
+            (0, (0..30).map(|i| (i, spk(i))).collect()),
+            (1, (0..30).map(|i| (i, spk(100 + i))).collect()),
+        ]
+        .into();
+        let histories = [
+            (spk(0), vec![(txid(0), 15)]),
+            (spk(100), vec![(txid(1), 15)]),
+        ]
+        .into();
+        let client = MockClient::new(headers(20, 0), histories);
+        let stop_gap = StopGap {
+            default: 20,
+            keychains: [(0, 1)].into(),
+        };
+
+        let mut last_indices = BTreeMap::<u8, u32>::new();
+        let (update, _) = scan_with_options(
+            &client,
+            None,
+            keychain_spks,
+            core::iter::empty(),
+            core::iter::empty(),
+            stop_gap,
+            1,
+            ScanOptions {
+                progress: Some(&mut |progress| {
+                    last_indices.insert(progress.keychain, progress.index);
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(update.keychain_update, [(0, 0), (1, 0)].into());
+        // each keychain stops once its gap of unused script pubkeys exceeds its stop gap, so
+        // keychain 0 (stop gap 1) makes far fewer requests than keychain 1 (default stop gap 20)
+        assert_eq!(last_indices, [(0, 2), (1, 21)].into());
+        assert_eq!(client.server().spks_requested, 3 + 22);
+    }
+
+    #[test]
+    fn scan_cancelled_during_reorg_returns_the_latest_tip() {
+        let histories = [(spk(0), vec![(txid(0), 18)])].into();
+        let client = MockClient::new(headers(20, 0), histories);
+        let reorged_headers = extend_headers(headers(15, 0), 21, 1);
+        let should_stop = Arc::new(AtomicBool::new(false));
+        client.server().reorg_on_history =
+            Some((reorged_headers.clone(), Arc::clone(&should_stop)));
+
+        let (update, cancelled) = scan(
+            &client,
+            4,
+            10,
+            2,
+            ScanOptions {
+                should_stop: Some(&should_stop),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(cancelled);
+        // the update is built on a tip after the reorg, and the stale scan results are dropped
+        let tip = update.new_tip.block_id();
+        assert!(tip.height >= 15);
+        assert_eq!(tip.hash, reorged_headers[tip.height as usize].block_hash());
+        assert!(update.graph_update.is_empty());
+        assert!(update.keychain_update.is_empty());
+    }
+
+    #[test]
+    fn scan_without_keychain_resumable_retries_failed_requests() {
+        let histories: HashMap<_, _> =
+            [(spk(1), vec![(txid(1), 15)]), (spk(4), vec![(txid(2), 16)])].into();
+        let resumable = |client: &MockClient, retry: RetryPolicy, cursor: &mut ScanCursor| {
+            scan_without_keychain_resumable(
+                client,
+                None,
+                (0..6).map(spk),
+                core::iter::empty(),
+                core::iter::empty(),
+                2,
+                retry,
+                cursor,
+            )
+        };
+        let expected = resumable(
+            &MockClient::new(headers(20, 0), histories.clone()),
+            RetryPolicy::default(),
+            &mut ScanCursor::default(),
+        )
+        .unwrap();
+        assert_eq!(expected.graph_update.len(), 2);
+
+        // the failed request is retried
+        let client = MockClient::new(headers(20, 0), histories.clone());
+        client.server().failures = 1;
+        let mut cursor = ScanCursor::default();
+        let retry = RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::ZERO,
+        };
+        let update = resumable(&client, retry, &mut cursor).unwrap();
+        assert_eq!(update.graph_update, expected.graph_update);
+        assert!(cursor.is_empty());
+
+        // without retries, the failed request interrupts the scan, which can then be resumed
+        let client = MockClient::new(headers(20, 0), histories);
+        client.server().failures = 1;
+        let mut cursor = ScanCursor::default();
+        assert!(matches!(
+            resumable(&client, RetryPolicy::default(), &mut cursor),
+            Err(Error::IOError(_))
+        ));
+        let update = resumable(&client, RetryPolicy::default(), &mut cursor).unwrap();
+        assert_eq!(update.graph_update, expected.graph_update);
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    fn scan_without_keychain_with_expansion_retries_failed_requests() {
+        let histories: HashMap<_, _> = [
+            (spk(1), vec![(txid(1), 15)]),
+            (spk(4), vec![(txid(2), 16)]),
+            (spk(9), vec![(txid(3), 17)]),
+        ]
+        .into();
+        let expand = |client: &MockClient, retry: RetryPolicy| {
+            scan_without_keychain_with_expansion(
+                client,
+                None,
+                (0..2).map(|i| (i, spk(i))),
+                |i| Some(spk(i)),
+                3,
+                core::iter::empty(),
+                core::iter::empty(),
+                2,
+                retry,
+            )
+        };
+
+        let client = MockClient::new(headers(20, 0), histories);
+        client.server().failures = 1;
+        assert!(matches!(
+            expand(&client, RetryPolicy::default()),
+            Err(Error::IOError(_))
+        ));
+
+        client.server().failures = 1;
+        let retry = RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::ZERO,
+        };
+        let update = expand(&client, retry).unwrap();
+        // `spk(4)` is found by expanding from `spk(1)`, but `spk(9)` is out of reach
+        assert_eq!(
+            update.graph_update.keys().collect::<HashSet<_>>(),
+            [txid(1), txid(2)].iter().collect::<HashSet<_>>()
+        );
+        assert_eq!(update.keychain_update, [((), 4)].into());
+    }
+
+    #[test]
+    fn estimate_fee_rate_converts_to_sat_per_kwu() {
+        let client = MockClient::default();
+        client.server().fee_estimates = [(1, 0.0002)].into();
+        // 0.0002 BTC/kvB is 20 sat/vB, which is 5000 sat/kwu
+        assert_eq!(
+            estimate_fee_rate(&client, 1).unwrap(),
+            FeeRate::from_sat_per_kwu(5000)
+        );
+    }
+
+    #[test]
+    fn estimate_fee_rate_errors_without_estimate() {
+        let client = MockClient::default();
+        // the server returns -1 for targets it has no estimate for
+        assert_eq!(client.estimate_fee(6).unwrap(), -1.0);
+        assert!(matches!(
+            estimate_fee_rate(&client, 6),
+            Err(Error::Message(_))
+        ));
+    }
+
+    #[test]
+    fn fee_histogram_is_parsed() {
+        let client = MockClient::default();
+        client.server().fee_histogram = serde_json::json!([[53.01, 102030], [38.56, 110990]]);
+        assert_eq!(
+            fee_histogram(&client).unwrap(),
+            vec![(53.01, 102030), (38.56, 110990)]
+        );
+
+        // a malformed bucket is an invalid response
+        client.server().fee_histogram = serde_json::json!([[53.01]]);
+        assert!(matches!(
+            fee_histogram(&client),
+            Err(Error::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn poll_notifications_reports_changed_statuses() {
+        let client = MockClient::default();
+        let status = |i: u8| ScriptStatus::from([i; 32]);
+        client.set_status(&spk(0), status(0));
+
+        let mut subscription = subscribe_spks(&client, [spk(0), spk(1)]).unwrap();
+        assert_eq!(subscription.status(&spk(0)), Some(Some(status(0))));
+        assert_eq!(subscription.status(&spk(1)), Some(None));
+        assert!(poll_notifications(&client, &mut subscription)
+            .unwrap()
+            .is_empty());
+
+        // only the latest status of a script pubkey is reported
+        client.set_status(&spk(1), status(1));
+        client.set_status(&spk(1), status(2));
+        assert_eq!(
+            poll_notifications(&client, &mut subscription).unwrap(),
+            vec![(spk(1), Some(status(2)))]
+        );
+        assert_eq!(subscription.status(&spk(1)), Some(Some(status(2))));
+        assert!(poll_notifications(&client, &mut subscription)
+            .unwrap()
+            .is_empty());
+
+        // script pubkeys which are already subscribed to are resubscribed
+        assert_eq!(
+            subscribe_spks(&client, [spk(1)]).unwrap().status(&spk(1)),
+            Some(Some(status(2)))
+        );
+    }
+
+    #[test]
+    fn poll_notifications_resubscribes_after_reconnect() {
+        let client = MockClient::default();
+        let status = |i: u8| ScriptStatus::from([i; 32]);
+        client.set_status(&spk(0), status(0));
+        let mut subscription = subscribe_spks(&client, [spk(0), spk(1)]).unwrap();
+
+        // the change happens while the subscriptions are dropped, so there is no notification
+        client.reconnect();
+        client.set_status(&spk(0), status(1));
+        assert!(client.server().subscriptions.is_empty());
+
+        assert_eq!(
+            poll_notifications(&client, &mut subscription).unwrap(),
+            vec![(spk(0), Some(status(1)))]
+        );
+        assert_eq!(
+            client.server().subscriptions.keys().collect::<HashSet<_>>(),
+            [spk(0), spk(1)].iter().collect::<HashSet<_>>()
+        );
+
+        // notifications are received again after resubscribing
+        client.set_status(&spk(1), status(2));
+        assert_eq!(
+            poll_notifications(&client, &mut subscription).unwrap(),
+            vec![(spk(1), Some(status(2)))]
+        );
+    }
+
+    /// A [`MockClient`] serving a block of `tx_count` transactions at height 1.
+    fn client_with_block(tx_count: u32) -> (MockClient, Block) {
+        let txdata = (0..tx_count).map(new_tx).collect::<Vec<_>>();
+        let mut block = Block {
+            header: headers(2, 0)[1],
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().expect("must have txs");
+
+        let client = MockClient::new(vec![headers(1, 0)[0], block.header], HashMap::new());
+        let mut server = client.server();
+        server
+            .block_txids
+            .insert(1, block.txdata.iter().map(Transaction::txid).collect());
+        server.txs = block
+            .txdata
+            .iter()
+            .map(|tx| (tx.txid(), tx.clone()))
+            .collect();
+        drop(server);
+        (client, block)
+    }
+
+    #[test]
+    fn fetch_block_fetches_every_tx_of_the_block() {
+        let (client, block) = client_with_block(3);
+        // txids are fetched until the position is past the last transaction
+        assert_eq!(fetch_block(&client, 1).unwrap(), block);
+    }
+
+    #[test]
+    fn fetch_block_errors_if_server_is_unsupported() {
+        let (client, _) = client_with_block(3);
+        client.server().block_txids.clear();
+        let err = fetch_block(&client, 1).unwrap_err();
+        assert!(
+            matches!(&err, Error::Message(msg) if msg.contains("does not support")),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn fetch_block_errors_on_merkle_root_mismatch() {
+        let (client, _) = client_with_block(3);
+        // the server is missing the last transaction of the block (e.g. due to a reorg)
+        client
+            .server()
+            .block_txids
+            .get_mut(&1)
+            .expect("must have block")
+            .pop();
+        let err = fetch_block(&client, 1).unwrap_err();
+        assert!(
+            matches!(&err, Error::Message(msg) if msg.contains("merkle root")),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn broadcast_batch_continues_after_rejected_tx() {
+        let client = MockClient::default();
+        let txs = (0..3).map(new_tx).collect::<Vec<_>>();
+        client.server().rejected_txs.insert(txs[1].txid());
+
+        let results = broadcast_batch(&client, &txs).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &txs[0].txid());
+        assert!(matches!(results[1], Err(Error::Protocol(_))));
+        // the rejected transaction does not stop the remaining transactions from being broadcast
+        assert_eq!(results[2].as_ref().unwrap(), &txs[2].txid());
+        assert_eq!(
+            client.server().txs.keys().collect::<HashSet<_>>(),
+            [txs[0].txid(), txs[2].txid()]
+                .iter()
+                .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn fetch_header_fetches_header_at_height() {
+        let headers = headers(10, 0);
+        let client = MockClient::new(headers.clone(), HashMap::new());
+        assert_eq!(fetch_header(&client, 5).unwrap(), headers[5]);
+        // the server has no header above its tip
+        assert!(matches!(fetch_header(&client, 10), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn server_capabilities_checks_network() {
+        let client = MockClient::default();
+        let testnet_genesis_hash = genesis_block(Network::Testnet).block_hash();
+        client.server().genesis_hash = Some(testnet_genesis_hash);
+
+        let capabilities = server_capabilities(&client).unwrap();
+        // the genesis hash is converted from display byte order
+        assert_eq!(capabilities.genesis_hash, testnet_genesis_hash);
+        assert_eq!(capabilities.check_network(Network::Testnet), Ok(()));
+        assert_eq!(
+            capabilities.check_network(Network::Bitcoin),
+            Err(NetworkMismatchError {
+                network: Network::Bitcoin,
+                expected_genesis_hash: genesis_block(Network::Bitcoin).block_hash(),
+                server_genesis_hash: testnet_genesis_hash,
+            })
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn scan_parallel_is_same_as_sequential_scan() {
+        let keychain_spks = || -> BTreeMap<u8, Vec<(u32, ScriptBuf)>> {
+            [
+                (0, (0..30).map(|i| (i, spk(i))).collect()),
+                (1, (100..130).map(|i| (i - 100, spk(i))).collect()),
+            ]
+            .into()
+        };
+        let histories: HashMap<_, _> = [
+            (spk(0), vec![(txid(0), 16)]),
+            (spk(3), vec![(txid(1), 17), (txid(2), 0)]),
+            (spk(101), vec![(txid(3), 15)]),
+            (spk(107), vec![(txid(4), 18)]),
+        ]
+        .into();
+        let stop_gap = StopGap {
+            default: 3,
+            keychains: [(1, 5)].into(),
+        };
+
+        for reorg in [false, true] {
+            let new_client = || {
+                let client = MockClient::new(headers(20, 0), histories.clone());
+                if reorg {
+                    // the tip changes during the first scan, so keychains are rescanned
+                    client.server().reorg_on_history = Some((
+                        extend_headers(headers(16, 0), 21, 1),
+                        Arc::new(AtomicBool::new(false)),
+                    ));
+                }
+                client
+            };
+
+            let (sequential, _) = scan_with_options(
+                &new_client(),
+                None,
+                keychain_spks(),
+                core::iter::empty(),
+                core::iter::empty(),
+                stop_gap.clone(),
+                2,
+                ScanOptions::default(),
+            )
+            .unwrap();
+
+            let server = new_client();
+            let parallel = scan_parallel(
+                || Ok(server.clone()),
+                None,
+                keychain_spks(),
+                core::iter::empty(),
+                core::iter::empty(),
+                stop_gap.clone(),
+                2,
+                RetryPolicy::default(),
+            )
+            .unwrap();
+
+            assert_eq!(parallel.keychain_update, [(0, 3), (1, 7)].into());
+            assert_eq!(parts(parallel), parts(sequential));
+        }
     }
 }
@@ -22,7 +22,67 @@
 
 #![warn(missing_docs)]
 
+mod client_options;
 mod electrum_ext;
 pub use bdk_chain;
+pub use client_options::*;
 pub use electrum_client;
 pub use electrum_ext::*;
+
+use bdk_chain::bitcoin::Network;
+
+/// Returns the URL of a default public electrum server for `network`.
+///
+/// `None` is returned if there is no default server for `network`. Use with
+/// [`should_validate_domain`] to configure the electrum client.
+pub fn default_electrum_url(network: Network) -> Option<&'static str> {
+    match network {
+        Network::Bitcoin => Some("ssl://electrum.blockstream.info:50002"),
+        Network::Testnet => Some("ssl://electrum.blockstream.info:60002"),
+        Network::Regtest => Some("tcp://localhost:60401"),
+        Network::Signet => Some("tcp://signet-electrumx.wakiyamap.dev:50001"),
+        _ => None,
+    }
+}
+
+/// Returns whether the domain of the electrum server's certificate should be validated for
+/// `network`.
+///
+/// Only the default server of [`Network::Bitcoin`] (refer to [`default_electrum_url`]) is expected
+/// to have a certificate matching its domain.
+pub fn should_validate_domain(network: Network) -> bool {
+    matches!(network, Network::Bitcoin)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_electrum_url_matches_network() {
+        assert_eq!(
+            default_electrum_url(Network::Bitcoin),
+            Some("ssl://electrum.blockstream.info:50002")
+        );
+        assert_eq!(
+            default_electrum_url(Network::Testnet),
+            Some("ssl://electrum.blockstream.info:60002")
+        );
+        assert_eq!(
+            default_electrum_url(Network::Regtest),
+            Some("tcp://localhost:60401")
+        );
+        assert_eq!(
+            default_electrum_url(Network::Signet),
+            Some("tcp://signet-electrumx.wakiyamap.dev:50001")
+        );
+    }
+
+    #[test]
+    fn should_validate_domain_only_on_bitcoin() {
+        assert!(should_validate_domain(Network::Bitcoin));
+        assert!(!should_validate_domain(Network::Testnet));
+        assert!(!should_validate_domain(Network::Regtest));
+        assert!(!should_validate_domain(Network::Signet));
+    }
+}
@@ -8,7 +8,7 @@ use std::{cmp::Reverse, collections::HashMap, path::PathBuf, sync::Mutex, time::
 use bdk_chain::{
     bitcoin::{
         absolute, address, psbt::Prevouts, secp256k1::Secp256k1, sighash::SighashCache, Address,
-        Network, Sequence, Transaction, TxIn, TxOut,
+        FeeRate, Network, Sequence, Transaction, TxIn, TxOut, Weight,
     },
     indexed_tx_graph::{self, IndexedTxGraph},
     keychain::{self, KeychainTxOutIndex},
@@ -45,6 +45,10 @@ pub struct Args<S: clap::Subcommand> {
     #[clap(env = "BDK_CP_LIMIT", long, default_value = "20")]
     pub cp_limit: usize,
 
+    /// SOCKS5 proxy to connect to the chain source through (e.g. `127.0.0.1:9050` for Tor).
+    #[clap(env = "BDK_PROXY", long)]
+    pub proxy: Option<String>,
+
     #[clap(subcommand)]
     pub command: Commands<S>,
 }
@@ -73,6 +77,9 @@ pub enum Commands<S: clap::Subcommand> {
         address: Address<address::NetworkUnchecked>,
         #[clap(short, default_value = "bnb")]
         coin_select: CoinSelectionAlgo,
+        /// Target fee rate in sat/vB.
+        #[clap(long, default_value = "2")]
+        fee_rate: u64,
     },
 }
 
@@ -162,22 +169,7 @@ pub enum TxOutCmd {
     },
 }
 
-#[derive(
-    Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, serde::Deserialize, serde::Serialize,
-)]
-pub enum Keychain {
-    External,
-    Internal,
-}
-
-impl core::fmt::Display for Keychain {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Keychain::External => write!(f, "external"),
-            Keychain::Internal => write!(f, "internal"),
-        }
-    }
-}
+pub use bdk_chain::keychain::KeychainKind as Keychain;
 
 pub fn run_address_cmd<A, C>(
     graph: &mut KeychainTxGraph<A>,
@@ -336,6 +328,7 @@ pub fn run_send_cmd<A: Anchor, O: ChainOracle, C>(
     cs_algorithm: CoinSelectionAlgo,
     address: Address,
     value: u64,
+    fee_rate: FeeRate,
     broadcast: impl FnOnce(&Transaction) -> anyhow::Result<()>,
 ) -> anyhow::Result<()>
 where
@@ -345,7 +338,28 @@ where
     let (transaction, change_index) = {
         let graph = &mut *graph.lock().unwrap();
         // take mutable ref to construct tx -- it is only open for a short time while building it.
-        let (tx, change_info) = create_tx(graph, chain, keymap, cs_algorithm, address, value)?;
+        let (tx, weight, change_info) =
+            create_tx(graph, chain, keymap, cs_algorithm, address, value, fee_rate)?;
+
+        let input_value = tx
+            .input
+            .iter()
+            .map(|txin| {
+                graph
+                    .graph()
+                    .get_txout(txin.previous_output)
+                    .map(|txout| txout.value)
+                    .context("prevout must be in the graph")
+            })
+            .sum::<anyhow::Result<u64>>()?;
+        let output_value = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        println!(
+            "Fee: {} sats (estimated {} sats for {} vbytes at {} sat/vB)",
+            input_value - output_value,
+            (weight * fee_rate).to_sat(),
+            weight.to_vbytes_ceil(),
+            fee_rate.to_sat_per_vb_ceil(),
+        );
 
         if let Some((index_changeset, (change_keychain, index))) = change_info {
             // We must first persist to disk the fact that we've got a new address from the
@@ -397,8 +411,10 @@ pub fn create_tx<A: Anchor, O: ChainOracle>(
     cs_algorithm: CoinSelectionAlgo,
     address: Address,
     value: u64,
+    fee_rate: FeeRate,
 ) -> anyhow::Result<(
     Transaction,
+    Weight,
     Option<(keychain::ChangeSet<Keychain>, (Keychain, u32))>,
 )>
 where
@@ -477,7 +493,8 @@ where
     };
 
     let cs_opts = CoinSelectorOpt {
-        target_feerate: 0.5,
+        // the coin selector's fee rate is in sat/wu
+        target_feerate: fee_rate.to_sat_per_kwu() as f32 / 1000.0,
         min_drain_value: graph
             .index
             .keychains()
@@ -547,6 +564,8 @@ where
         }
     }
 
+    let weight = estimate_signed_weight(graph, &transaction, &prevouts)?;
+
     // create a short lived transaction
     let _sighash_tx = transaction.clone();
     let mut sighash_cache = SighashCache::new(&_sighash_tx);
@@ -599,7 +618,41 @@ where
         None
     };
 
-    Ok((transaction, change_info))
+    Ok((transaction, weight, change_info))
+}
+
+/// Estimate the weight of the unsigned `tx` once all of its inputs are signed.
+///
+/// `prevouts` are the txouts spent by `tx` (in input order), which must pay to script pubkeys of
+/// `graph`'s index. The weight to satisfy each input is estimated with miniscript's
+/// `max_weight_to_satisfy` for the descriptor that derives the spent script pubkey, so this is an
+/// upper bound (i.e. it assumes signatures of the maximum size).
+pub fn estimate_signed_weight<A>(
+    graph: &KeychainTxGraph<A>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+) -> anyhow::Result<Weight> {
+    let mut weight = tx.weight().to_wu();
+    let mut has_witness = false;
+    for prevout in prevouts {
+        let &(keychain, index) = graph
+            .index
+            .index_of_spk(&prevout.script_pubkey)
+            .context("input must spend a script pubkey of the wallet")?;
+        let descriptor = graph
+            .index
+            .keychains()
+            .get(&keychain)
+            .expect("keychain must exist")
+            .at_derivation_index(index)?;
+        weight += descriptor.max_weight_to_satisfy()? as u64;
+        has_witness |= descriptor.desc_type().segwit_version().is_some();
+    }
+    if has_witness {
+        // the segwit marker and flag
+        weight += 2;
+    }
+    Ok(Weight::from_wu(weight))
 }
 
 #[allow(clippy::type_complexity)]
@@ -667,9 +720,11 @@ where
             value,
             address,
             coin_select,
+            fee_rate,
         } => {
             let chain = &*chain.lock().unwrap();
             let address = address.require_network(network)?;
+            let fee_rate = FeeRate::from_sat_per_vb(fee_rate).context("fee rate is too high")?;
             run_send_cmd(
                 graph,
                 db,
@@ -678,6 +733,7 @@ where
                 coin_select,
                 address,
                 value,
+                fee_rate,
                 broadcast,
             )
         }
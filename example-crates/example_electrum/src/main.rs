@@ -1,27 +1,24 @@
-use std::{
-    collections::BTreeMap,
-    io::{self, Write},
-    sync::Mutex,
-};
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
 
 use bdk_chain::{
-    bitcoin::{Address, Network, OutPoint, ScriptBuf, Txid},
+    bitcoin::{Address, OutPoint, ScriptBuf, Txid},
     indexed_tx_graph::{self, IndexedTxGraph},
-    keychain::WalletChangeSet,
+    keychain::{Balance, WalletChangeSet},
     local_chain::LocalChain,
-    Append, ConfirmationHeightAnchor,
+    Append, ConfirmationTimeAnchor,
 };
 use bdk_electrum::{
-    electrum_client::{self, ElectrumApi},
-    ElectrumExt, ElectrumUpdate,
+    electrum_client::ElectrumApi, ClientOptions, ElectrumExt, ElectrumUpdate, RetryPolicy,
+    ScanCursor,
 };
 use example_cli::{
     anyhow::{self, Context},
     clap::{self, Parser, Subcommand},
-    Keychain,
+    Keychain, KeychainTxGraph,
 };
 
-const DB_MAGIC: &[u8] = b"bdk_example_electrum";
+// bumped as the persisted anchor type changed, so databases of older versions are not misread
+const DB_MAGIC: &[u8] = b"bdk_example_electrum_v2";
 const DB_PATH: &str = ".bdk_example_electrum.db";
 
 #[derive(Subcommand, Debug, Clone)]
@@ -58,9 +55,36 @@ pub struct ScanOptions {
     /// Set batch size for each script_history call to electrum client.
     #[clap(long, default_value = "25")]
     pub batch_size: usize,
+    /// Timeout (in seconds) for each request to the electrum server. A request (such as a batch of
+    /// script_history calls) that exceeds the timeout errors. If not set, the client's default
+    /// (no timeout) is used.
+    #[clap(long, value_parser = parse_secs)]
+    pub timeout: Option<Duration>,
+}
+
+fn parse_secs(secs: &str) -> Result<Duration, std::num::ParseIntError> {
+    secs.parse().map(Duration::from_secs)
+}
+
+impl ElectrumCommands {
+    fn scan_options(&self) -> &ScanOptions {
+        match self {
+            ElectrumCommands::Scan { scan_options, .. } => scan_options,
+            ElectrumCommands::Sync { scan_options, .. } => scan_options,
+        }
+    }
 }
 
-type ChangeSet = WalletChangeSet<Keychain, ConfirmationHeightAnchor>;
+type ChangeSet = WalletChangeSet<Keychain, ConfirmationTimeAnchor>;
+
+fn balance(graph: &KeychainTxGraph<ConfirmationTimeAnchor>, chain: &LocalChain) -> Balance {
+    graph.graph().balance(
+        chain,
+        chain.tip().map(|cp| cp.block_id()).unwrap_or_default(),
+        graph.index.outpoints().iter().cloned(),
+        |(k, _), _| k == &Keychain::Internal,
+    )
+}
 
 fn main() -> anyhow::Result<()> {
     let (args, keymap, index, db, init_changeset) =
@@ -74,18 +98,22 @@ fn main() -> anyhow::Result<()> {
 
     let chain = Mutex::new(LocalChain::from_changeset(init_changeset.chain));
 
-    let electrum_url = match args.network {
-        Network::Bitcoin => "ssl://electrum.blockstream.info:50002",
-        Network::Testnet => "ssl://electrum.blockstream.info:60002",
-        Network::Regtest => "tcp://localhost:60401",
-        Network::Signet => "tcp://signet-electrumx.wakiyamap.dev:50001",
-        _ => panic!("Unknown network"),
+    let electrum_url = bdk_electrum::default_electrum_url(args.network)
+        .with_context(|| format!("no default electrum server for {}", args.network))?;
+    let timeout = match &args.command {
+        example_cli::Commands::ChainSpecific(electrum_cmd) => electrum_cmd.scan_options().timeout,
+        _ => None,
     };
-    let config = electrum_client::Config::builder()
-        .validate_domain(matches!(args.network, Network::Bitcoin))
-        .build();
-
-    let client = electrum_client::Client::from_config(electrum_url, config)?;
+    let client = ClientOptions {
+        proxy: args.proxy.clone(),
+        timeout,
+        ..ClientOptions::new(args.network)
+    }
+    .connect(electrum_url)?;
+    client
+        .server_capabilities()?
+        .check_network(args.network)
+        .context("checking the electrum server")?;
 
     let electrum_cmd = match &args.command {
         example_cli::Commands::ChainSpecific(electrum_cmd) => electrum_cmd,
@@ -110,6 +138,9 @@ fn main() -> anyhow::Result<()> {
         }
     };
 
+    // The balance before syncing, used to report how much the balance changed.
+    let mut balance_before = Option::<Balance>::None;
+
     let response = match electrum_cmd.clone() {
         ElectrumCommands::Scan {
             stop_gap,
@@ -119,39 +150,32 @@ fn main() -> anyhow::Result<()> {
                 let graph = &*graph.lock().unwrap();
                 let chain = &*chain.lock().unwrap();
 
-                let keychain_spks = graph
-                    .index
-                    .spks_of_all_keychains()
-                    .into_iter()
-                    .map(|(keychain, iter)| {
-                        let mut first = true;
-                        let spk_iter = iter.inspect(move |(i, _)| {
-                            if first {
-                                eprint!("\nscanning {}: ", keychain);
-                                first = false;
-                            }
-
-                            eprint!("{} ", i);
-                            let _ = io::stdout().flush();
-                        });
-                        (keychain, spk_iter)
-                    })
-                    .collect::<BTreeMap<_, _>>();
+                let keychain_spks = graph.index.spks_of_all_keychains();
 
                 let tip = chain.tip();
                 (keychain_spks, tip)
             };
 
-            client
-                .scan(
+            let (update, _) = client
+                .scan_with_options(
                     tip,
                     keychain_spks,
                     core::iter::empty(),
                     core::iter::empty(),
                     stop_gap,
                     scan_options.batch_size,
+                    bdk_electrum::ScanOptions {
+                        progress: Some(&mut |progress| {
+                            eprintln!(
+                                "scanning {}: up to index {} ({} spks scanned)",
+                                progress.keychain, progress.index, progress.spks_processed
+                            );
+                        }),
+                        ..Default::default()
+                    },
                 )
-                .context("scanning the blockchain")?
+                .context("scanning the blockchain")?;
+            update
         }
         ElectrumCommands::Sync {
             mut unused_spks,
@@ -164,6 +188,7 @@ fn main() -> anyhow::Result<()> {
             let graph = graph.lock().unwrap();
             let chain = chain.lock().unwrap();
             let chain_tip = chain.tip().map(|cp| cp.block_id()).unwrap_or_default();
+            balance_before = Some(balance(&graph, &chain));
 
             if !(all_spks || unused_spks || utxos || unconfirmed) {
                 unused_spks = true;
@@ -231,12 +256,7 @@ fn main() -> anyhow::Result<()> {
             let mut txids: Box<dyn Iterator<Item = Txid>> = Box::new(core::iter::empty());
 
             if unconfirmed {
-                let unconfirmed_txids = graph
-                    .graph()
-                    .list_chain_txs(&*chain, chain_tip)
-                    .filter(|canonical_tx| !canonical_tx.chain_position.is_confirmed())
-                    .map(|canonical_tx| canonical_tx.tx_node.txid)
-                    .collect::<Vec<Txid>>();
+                let unconfirmed_txids = graph.unconfirmed_txids(&*chain, chain_tip);
 
                 txids = Box::new(unconfirmed_txids.into_iter().inspect(|txid| {
                     eprintln!("Checking if {} is confirmed yet", txid);
@@ -248,9 +268,36 @@ fn main() -> anyhow::Result<()> {
             // drop lock on graph and chain
             drop((graph, chain));
 
-            let update = client
-                .scan_without_keychain(tip, spks, txids, outpoints, scan_options.batch_size)
-                .context("scanning the blockchain")?;
+            // the cursor allows an interrupted sync to continue where it left off
+            let cursor_path = args.db_path.with_extension("sync_cursor");
+            let mut cursor = match std::fs::read(&cursor_path) {
+                Ok(bytes) => serde_json::from_slice::<ScanCursor>(&bytes)
+                    .context("reading the sync cursor")?,
+                Err(_) => ScanCursor::default(),
+            };
+
+            let update = match client.scan_without_keychain_resumable(
+                tip,
+                spks,
+                txids,
+                outpoints,
+                scan_options.batch_size,
+                RetryPolicy::default(),
+                &mut cursor,
+            ) {
+                Ok(update) => {
+                    if cursor_path.exists() {
+                        std::fs::remove_file(&cursor_path)?;
+                    }
+                    update
+                }
+                Err(err) => {
+                    if !cursor.is_empty() {
+                        std::fs::write(&cursor_path, serde_json::to_vec(&cursor)?)?;
+                    }
+                    return Err(anyhow::Error::from(err).context("scanning the blockchain"));
+                }
+            };
             ElectrumUpdate {
                 graph_update: update.graph_update,
                 new_tip: update.new_tip,
@@ -269,7 +316,7 @@ fn main() -> anyhow::Result<()> {
         .expect("must get time")
         .as_secs();
 
-    let final_update = response.finalize(&client, Some(now), missing_txids)?;
+    let final_update = response.finalize_as_confirmation_time(&client, Some(now), missing_txids)?;
 
     let db_changeset = {
         let mut chain = chain.lock().unwrap();
@@ -278,8 +325,7 @@ fn main() -> anyhow::Result<()> {
         let chain = chain.apply_update(final_update.chain)?;
 
         let indexed_tx_graph = {
-            let mut changeset =
-                indexed_tx_graph::ChangeSet::<ConfirmationHeightAnchor, _>::default();
+            let mut changeset = indexed_tx_graph::ChangeSet::<ConfirmationTimeAnchor, _>::default();
             let (_, indexer) = graph
                 .index
                 .reveal_to_target_multi(&final_update.last_active_indices);
@@ -298,7 +344,13 @@ fn main() -> anyhow::Result<()> {
     };
 
     let mut db = db.lock().unwrap();
-    db.stage(db_changeset);
-    db.commit()?;
+    if !db.stage_and_commit(db_changeset)? {
+        println!("no changes to persist");
+    }
+
+    if let Some(balance_before) = balance_before {
+        let balance_after = balance(&graph.lock().unwrap(), &chain.lock().unwrap());
+        println!("balance change: {}", balance_after - balance_before);
+    }
     Ok(())
 }
@@ -20,7 +20,7 @@ use example_cli::{
     Keychain,
 };
 
-const DB_MAGIC: &[u8] = b"bdk_example_esplora";
+const DB_MAGIC: &[u8] = b"bdk_example_esplora_v2";
 const DB_PATH: &str = ".bdk_esplora_example.db";
 
 #[derive(Subcommand, Debug, Clone)]
@@ -87,7 +87,12 @@ fn main() -> anyhow::Result<()> {
         _ => panic!("unsupported network"),
     };
 
-    let client = esplora_client::Builder::new(esplora_url).build_blocking()?;
+    let mut client_builder = esplora_client::Builder::new(esplora_url);
+    if let Some(proxy) = &args.proxy {
+        // without a scheme, the proxy would be used as an HTTP proxy
+        client_builder = client_builder.proxy(&format!("socks5://{}", proxy));
+    }
+    let client = client_builder.build_blocking()?;
 
     let esplora_cmd = match &args.command {
         // These are commands that are handled by this example (sync, scan).
@@ -262,12 +267,7 @@ fn main() -> anyhow::Result<()> {
                     // We want to search for whether the unconfirmed transaction is now confirmed.
                     // We provide the unconfirmed txids to
                     // `EsploraExt::update_tx_graph_without_keychain`.
-                    let unconfirmed_txids = graph
-                        .graph()
-                        .list_chain_txs(&*chain, chain_tip)
-                        .filter(|canonical_tx| !canonical_tx.chain_position.is_confirmed())
-                        .map(|canonical_tx| canonical_tx.tx_node.txid)
-                        .collect::<Vec<Txid>>();
+                    let unconfirmed_txids = graph.unconfirmed_txids(&*chain, chain_tip);
                     txids = Box::new(unconfirmed_txids.into_iter().inspect(|txid| {
                         eprintln!("Checking if {} is confirmed yet", txid);
                         // Flush early to ensure we print at every iteration.
@@ -1,4 +1,4 @@
-const DB_MAGIC: &str = "bdk_wallet_electrum_example";
+const DB_MAGIC: &str = "bdk_wallet_electrum_example_v2";
 const SEND_AMOUNT: u64 = 5000;
 const STOP_GAP: usize = 50;
 const BATCH_SIZE: usize = 5;
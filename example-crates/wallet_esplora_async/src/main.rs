@@ -9,7 +9,7 @@ use bdk::{
 use bdk_esplora::{esplora_client, EsploraAsyncExt};
 use bdk_file_store::Store;
 
-const DB_MAGIC: &str = "bdk_wallet_esplora_async_example";
+const DB_MAGIC: &str = "bdk_wallet_esplora_async_example_v2";
 const SEND_AMOUNT: u64 = 5000;
 const STOP_GAP: usize = 50;
 const PARALLEL_REQUESTS: usize = 5;
@@ -1,4 +1,4 @@
-const DB_MAGIC: &str = "bdk_wallet_esplora_example";
+const DB_MAGIC: &str = "bdk_wallet_esplora_example_v2";
 const SEND_AMOUNT: u64 = 1000;
 const STOP_GAP: usize = 5;
 const PARALLEL_REQUESTS: usize = 1;